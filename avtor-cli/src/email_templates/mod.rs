@@ -0,0 +1,51 @@
+use serde_json::json;
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use avtor_core::db::with_transaction;
+use avtor_core::email::{EmailError, EmailKind, EmailTemplate};
+use avtor_core::models::email_templates::{self, EmailTemplateError};
+use avtor_core::models::users::AccountId;
+use avtor_core::repo::{PgAccountRepo, PgEmailTemplateRepo};
+
+use crate::output::Report;
+
+/// Stores the template file at `path` as the account's `kind` template.
+pub async fn set_email_template(
+    client: &mut Client,
+    account_id: Uuid,
+    kind: EmailKind,
+    path: &str,
+) -> Result<Report, anyhow::Error> {
+    let template = EmailTemplate::parse(&std::fs::read_to_string(path)?).map_err(|e| match e {
+        EmailError::InvalidTemplate(message) => EmailTemplateError::Invalid(message),
+        e => EmailTemplateError::Invalid(e.to_string()),
+    })?;
+    let subject = template.subject.clone();
+    with_transaction(client, move |trans| {
+        Box::pin(async move {
+            email_templates::set_email_template(
+                &PgEmailTemplateRepo::new(trans),
+                &PgAccountRepo::new(trans),
+                AccountId::from(account_id),
+                kind,
+                &template,
+            )
+            .await
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| EmailTemplateError::RepoError(e.to_string())))?;
+    Ok(Report::line(
+        format!(
+            "set the {} template of account {}",
+            kind.as_str(),
+            account_id
+        ),
+        json!({
+            "account_id": account_id,
+            "kind": kind.as_str(),
+            "subject": subject,
+        }),
+    ))
+}
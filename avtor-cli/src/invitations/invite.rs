@@ -0,0 +1,106 @@
+use chrono::{Duration, Utc};
+use serde_json::json;
+use tokio_postgres::Client;
+
+use avtor_core::db::with_transaction;
+use avtor_core::email::{EmailKind, EmailSender, EmailTemplate};
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::models::email_templates::email_template_for;
+use avtor_core::models::invitations::{
+    invite_user, send_invitation, InvitationDto, InvitationError,
+};
+use avtor_core::models::users::AccountId;
+use avtor_core::repo::{PgAccountRepo, PgEmailTemplateRepo, PgInvitationRepo};
+use avtor_core::secret::Secret;
+
+use super::EXPIRES_FORMAT;
+use crate::output::{Report, EMAIL_FAILED};
+
+/// How the invitation reaches the invitee besides being printed.
+pub struct Delivery {
+    pub sender: Option<Box<dyn EmailSender>>,
+    /// Used when the account has no invitation template of its own.
+    pub fallback: Option<EmailTemplate>,
+}
+
+/// Stores an invitation, emails it when a sender is configured and reports
+/// the token to hand to the invitee, as a full url when `base_url` is given.
+/// A failed email leaves the invitation in place, so its url can still be
+/// passed on by hand.
+pub async fn invite(
+    client: &mut Client,
+    key: Secret<String>,
+    ttl: Duration,
+    leeway: Duration,
+    base_url: Option<String>,
+    delivery: Delivery,
+    dto: InvitationDto,
+) -> Result<Report, anyhow::Error> {
+    let fallback = delivery.fallback;
+    let (issued, template) = with_transaction(client, move |trans| {
+        Box::pin(async move {
+            let issued = invite_user(
+                &PgInvitationRepo::new(trans),
+                &PgAccountRepo::new(trans),
+                &StandardNormalizer::default(),
+                &key,
+                ttl,
+                Utc::now(),
+                leeway,
+                &dto,
+            )
+            .await?;
+            let template = email_template_for(
+                &PgEmailTemplateRepo::new(trans),
+                AccountId::from(dto.account_id),
+                EmailKind::Invitation,
+                fallback,
+            )
+            .await?;
+            Ok::<_, InvitationError>((issued, template))
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| InvitationError::RepoError(e.to_string())))?;
+    let invitation = &issued.invitation;
+    let token = issued.token.expose_secret();
+    let url = match &base_url {
+        Some(base_url) => format!("{}{}", base_url.trim_end_matches('/'), token),
+        None => token.clone(),
+    };
+    let emailed = match &delivery.sender {
+        Some(sender) => Some(
+            send_invitation(sender.as_ref(), &template, &issued, &url)
+                .await
+                .map_err(|e| e.to_string()),
+        ),
+        None => None,
+    };
+    let data = json!({
+        "invitation_id": invitation.id().uuid(),
+        "email": invitation.email,
+        "account_id": invitation.account_id,
+        "roles": invitation.roles,
+        "expires_at": invitation.expires_at.format(EXPIRES_FORMAT).to_string(),
+        "token": token,
+        "url": base_url.map(|_| &url),
+        "emailed": matches!(emailed, Some(Ok(()))),
+    });
+    let report = Report::new(
+        vec![
+            format!(
+                "invited {} until {} (invitation {})",
+                invitation.email,
+                invitation.expires_at.format(EXPIRES_FORMAT),
+                invitation.id().uuid()
+            ),
+            url.clone(),
+        ],
+        data,
+    )?;
+    Ok(match emailed {
+        Some(Ok(())) => report.with_note(format!("emailed {}", invitation.email)),
+        Some(Err(e)) => report.with_failure(EMAIL_FAILED, e),
+        None => report,
+    })
+}
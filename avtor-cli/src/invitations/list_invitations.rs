@@ -0,0 +1,80 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use avtor_core::db::with_transaction;
+use avtor_core::models::invitations::{
+    find_invitations, Invitation, InvitationError, InvitationStatus,
+};
+use avtor_core::models::users::AccountId;
+use avtor_core::repo::PgInvitationRepo;
+
+use super::EXPIRES_FORMAT;
+use crate::output::{self, Report};
+
+/// What `list-invitations` shows of an invitation; the token can't be shown
+/// again, only its hash is stored.
+#[derive(Serialize)]
+struct InvitationRow {
+    id: Uuid,
+    email: String,
+    roles: String,
+    status: &'static str,
+    expires_at: String,
+}
+
+impl InvitationRow {
+    fn new(i: &Invitation, now: DateTime<Utc>, leeway: Duration) -> Self {
+        InvitationRow {
+            id: i.id().uuid(),
+            email: i.email.clone(),
+            roles: i.roles.clone(),
+            status: i.status_at(now, leeway).as_str(),
+            expires_at: i.expires_at.format(EXPIRES_FORMAT).to_string(),
+        }
+    }
+}
+
+/// The account's invitations, oldest expiry first.
+pub async fn list_invitations(
+    client: &mut Client,
+    account_id: Uuid,
+    status: Option<InvitationStatus>,
+    leeway: Duration,
+) -> Result<Report, anyhow::Error> {
+    let now = Utc::now();
+    let mut invitations = with_transaction(client, move |trans| {
+        Box::pin(async move {
+            find_invitations(
+                &PgInvitationRepo::new(trans),
+                AccountId::from(account_id),
+                status,
+                now,
+                leeway,
+            )
+            .await
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| InvitationError::RepoError(e.to_string())))?;
+    invitations.sort_by_key(|i| i.expires_at);
+    let rows: Vec<InvitationRow> = invitations
+        .iter()
+        .map(|i| InvitationRow::new(i, now, leeway))
+        .collect();
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|r| {
+            vec![
+                r.id.to_string(),
+                r.email.clone(),
+                r.roles.clone(),
+                r.status.to_string(),
+                r.expires_at.clone(),
+            ]
+        })
+        .collect();
+    let lines = output::table(&["id", "email", "roles", "status", "expires_at"], &cells);
+    Ok(Report::new(lines, rows)?)
+}
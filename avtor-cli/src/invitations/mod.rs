@@ -0,0 +1,5 @@
+pub mod invite;
+pub mod list_invitations;
+
+/// Expiry times are stored in UTC.
+const EXPIRES_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
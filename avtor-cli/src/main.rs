@@ -1,122 +1,573 @@
-use std::{io::Error, str::FromStr};
+use std::{process::ExitCode, str::FromStr};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use tokio_postgres::{tls::NoTlsStream, Client, Connection, NoTls, Socket};
 
-use avtor_core::models::users::{
-    create_super_user, find_account_by_id, find_super_user, insert_account, insert_user,
-    AccountDto, CreateSuperUserError, UserDto,
+use avtor_core::db::with_transaction;
+use avtor_core::email::EmailKind;
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::migrations::Runner;
+use avtor_core::models::{
+    invitations::{InvitationDto, InvitationStatus},
+    migrations::ensure_schema_compatible,
+    system_info::{
+        current_version_info, find_latest_by_event, insert_system_info, AVTOR_VERSION,
+        EVENT_BOOTSTRAP, EVENT_MIGRATION,
+    },
+    users::{
+        create_super_user_with_repos, AccountDto, CreateSuperUserError, UserDto, UserFilter,
+        SUPER_USER_ROLE,
+    },
 };
+use avtor_core::password::breach::BreachChecker;
+use avtor_core::repo::{PgAccountRepo, PgUserRepo};
+use avtor_core::secret::Secret;
 
+pub mod config;
+pub mod email_templates;
+pub mod invitations;
 pub mod migrations;
-use migrations::migration_01::run_migration_up;
+pub mod output;
+pub mod prompt;
+pub mod seed;
+pub mod users;
+
+use config::EffectiveConfig;
+use output::{OutputMode, Report};
+use prompt::Prompter;
+use users::list_users::OutputFormat;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(long)]
-    op: String,
+    /// TOML or YAML file with database, super user and token settings;
+    /// environment variables override its values.
+    #[clap(long, global = true)]
+    config: Option<String>,
+
+    /// Fail instead of prompting for missing usernames or passwords.
+    #[clap(long, global = true)]
+    non_interactive: bool,
 
-    #[clap(long)]
-    other: Option<String>,
+    /// `json` prints `{"ok": true, "result": ...}` on success and
+    /// `{"ok": false, "error": {"code": ...}}` on failure, both to stdout.
+    #[clap(long, global = true, arg_enum, default_value = "text")]
+    output: OutputMode,
 
-    path: Option<String>,
+    #[clap(subcommand)]
+    command: Command,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Connect to the database and print hello.
+    Hello,
+
+    /// Print the avtor-cli and avtor-core versions.
+    Version {
+        /// Also print the versions recorded in the database.
+        #[clap(long)]
+        remote: bool,
+    },
+
+    /// Apply pending migrations; `migrate down` rolls back and `migrate
+    /// baseline` adopts an existing schema.
+    Migrate {
+        #[clap(subcommand)]
+        direction: Option<MigrateDirection>,
+
+        /// Apply pending migrations even if applied ones were edited since
+        /// they ran, warning about them instead of failing.
+        #[clap(long, global = true)]
+        allow_drift: bool,
+    },
+
+    /// Create the super user and its account.
+    CreateSuperUser {
+        /// YAML file with `username` and `password`.
+        path: Option<String>,
+    },
+
+    /// Create a regular user in an existing account.
+    CreateUser {
+        /// Prompted for when missing.
+        #[clap(long)]
+        username: Option<String>,
+
+        /// Visible in the process list; prefer --password-stdin or the
+        /// prompt shown when neither is given.
+        #[clap(long)]
+        password: Option<String>,
+
+        /// Read the password from the first line of stdin.
+        #[clap(long, conflicts_with = "password")]
+        password_stdin: bool,
+
+        /// Comma separated, e.g. `user,billing`.
+        #[clap(long)]
+        roles: String,
+
+        #[clap(long)]
+        account_id: uuid::Uuid,
+
+        /// Unique within the account.
+        #[clap(long)]
+        email: Option<String>,
+    },
+
+    /// List users one page at a time, ordered by username.
+    ListUsers {
+        #[clap(long)]
+        account_id: Option<uuid::Uuid>,
+
+        #[clap(long)]
+        role: Option<String>,
+
+        /// Ignored with --output json.
+        #[clap(long, arg_enum, default_value = "table")]
+        format: OutputFormat,
+
+        /// Page size.
+        #[clap(long, default_value = "100")]
+        limit: i64,
+
+        /// Cursor printed with the previous page.
+        #[clap(long)]
+        after: Option<String>,
+    },
+
+    /// Invite someone into an existing account and print the token to send
+    /// them; needs `tokens.secret`. Also emails it when `email.sender` is
+    /// set.
+    Invite {
+        #[clap(long)]
+        email: String,
+
+        /// Id of the account.
+        #[clap(long)]
+        account: uuid::Uuid,
+
+        /// Comma separated, e.g. `user,billing`.
+        #[clap(long)]
+        roles: String,
+
+        #[clap(long, default_value = "72")]
+        expires_in_hours: i64,
+
+        /// Printed before the token to make a link, e.g.
+        /// `https://auth.example.com`.
+        #[clap(long)]
+        base_url: Option<String>,
+    },
+
+    /// List the invitations of an account.
+    ListInvitations {
+        /// Id of the account.
+        #[clap(long)]
+        account: uuid::Uuid,
+
+        /// `pending`, `accepted`, `revoked` or `expired`.
+        #[clap(long)]
+        status: Option<InvitationStatus>,
+    },
+
+    /// Give an account its own email template, replacing the built in one
+    /// and `email.invitation_template`.
+    SetEmailTemplate {
+        /// Id of the account.
+        #[clap(long)]
+        account: uuid::Uuid,
+
+        /// `invitation` or `password_reset`.
+        #[clap(long)]
+        kind: EmailKind,
+
+        /// File with a `Subject:` line, a blank line and the body, using
+        /// `{{link}}`, `{{account_name}}` and the other variables of the kind.
+        path: String,
+    },
+
+    /// Create or update accounts and users from a YAML or JSON file, matched
+    /// by account slug and username.
+    Seed {
+        /// `{accounts: [{slug, name}], users: [{username, password, roles,
+        /// account}]}`
+        path: String,
+    },
+
+    /// Inspect the configuration.
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum MigrateDirection {
+    /// Apply pending migrations; the same as plain `migrate`.
+    Up {
+        /// YAML file listing databases to migrate instead of the configured
+        /// one: `targets: [{name: tenant-a, url: postgres://...}]`.
+        #[clap(long)]
+        targets: Option<String>,
+
+        /// How many targets to migrate at once.
+        #[clap(long, default_value = "4", requires = "targets")]
+        parallel: usize,
+
+        /// Keep starting targets after one fails instead of skipping the
+        /// rest.
+        #[clap(long, requires = "targets")]
+        continue_on_error: bool,
+    },
+
+    /// Run the stored down migrations, newest first.
+    Down {
+        /// Roll back every migration after this seq_order instead of only
+        /// the latest one.
+        #[clap(long)]
+        to: Option<i32>,
+
+        /// Don't ask for confirmation; required with --non-interactive.
+        #[clap(long)]
+        yes: bool,
+    },
+
+    /// Record migrations as applied without running them, for a database
+    /// whose schema already exists.
+    Baseline {
+        /// Last seq_order to record; every migration up to it is marked.
+        #[clap(long)]
+        to: i32,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective configuration with secrets redacted.
+    Show,
+
+    /// Print every supported setting with its environment variable.
+    Schema,
+}
+
+/// Credentials file for `create_super_user`; missing values fall back to
+/// `super_user_username` and `super_user_password`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct YamlSuperUser {
-    pub username: String,
-    pub password: String,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+}
+
+fn super_user_dtos(
+    env_config: EnvConfig,
+    yaml: YamlSuperUser,
+    prompter: &Prompter,
+) -> Result<(UserDto, AccountDto), anyhow::Error> {
+    let username = prompter.text_or_prompt(
+        yaml.username.or(env_config.super_user_username),
+        "super user username",
+    )?;
+    let password = prompter.password_or_prompt(
+        yaml.password.or(env_config.super_user_password),
+        "super user password",
+    )?;
+    let account_id = uuid::Uuid::from_str(env_config.main_account_id.as_str())?;
+    let user_dto = UserDto {
+        id: uuid::Uuid::new_v4(),
+        username,
+        password,
+        roles: SUPER_USER_ROLE.to_string(),
+        account_id,
+        email: None,
+    };
+    let account_dto = AccountDto {
+        id: account_id,
+        name: env_config.main_account_name,
+    };
+    Ok((user_dto, account_dto))
 }
 
 async fn _create_super_user(
     client: &mut Client,
+    breach_checker: Box<dyn BreachChecker>,
     user_dto: &UserDto,
     account_dto: &AccountDto,
 ) -> Result<(), CreateSuperUserError> {
-    // todo: map err
-    let trans = client.transaction().await.unwrap();
-    let this_insert_user = insert_user(&trans);
-    let this_find_super_user = find_super_user(&trans);
-    let insert_account_setup = insert_account(&trans);
-    let find_account_by_id_setup = find_account_by_id(&trans);
-    let r = create_super_user(
-        this_find_super_user,
-        this_insert_user,
-        insert_account_setup,
-        find_account_by_id_setup,
-        user_dto,
-        account_dto,
-    )
-    .await;
-    trans.commit().await;
-    r
+    let user_dto = user_dto.clone();
+    let account_dto = account_dto.clone();
+    with_transaction(client, move |trans| {
+        Box::pin(async move {
+            create_super_user_with_repos(
+                &PgUserRepo::new(trans),
+                &PgAccountRepo::new(trans),
+                &StandardNormalizer::default(),
+                breach_checker.as_ref(),
+                &user_dto,
+                &account_dto,
+            )
+            .await?;
+            insert_system_info(trans)(current_version_info(EVENT_BOOTSTRAP, "create_super_user"))
+                .await?;
+            Ok(())
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| CreateSuperUserError::RepoError(e.to_string())))
+}
+
+async fn version_report(client: &Client, remote: bool) -> Result<Report, anyhow::Error> {
+    let mut lines = vec![
+        format!("avtor-cli {}", env!("CARGO_PKG_VERSION")),
+        format!("avtor-core {}", AVTOR_VERSION),
+    ];
+    let mut data = json!({
+        "avtor_cli": env!("CARGO_PKG_VERSION"),
+        "avtor_core": AVTOR_VERSION,
+    });
+    if remote {
+        let mut database = serde_json::Map::new();
+        for event in [EVENT_MIGRATION, EVENT_BOOTSTRAP] {
+            let info = find_latest_by_event(client)(event.to_string()).await?;
+            match &info {
+                Some(info) => {
+                    lines.push(format!(
+                        "database {}: {} by avtor-core {} on {}",
+                        event, info.subject, info.avtor_version, info.recorded_on
+                    ));
+                    if info.avtor_version != AVTOR_VERSION {
+                        lines.push(format!(
+                            "warning: database {} was recorded by avtor-core {} but this binary is {}",
+                            event, info.avtor_version, AVTOR_VERSION
+                        ));
+                    }
+                }
+                None => lines.push(format!("database {}: none recorded", event)),
+            }
+            database.insert(
+                event.to_string(),
+                json!(info.map(|info| json!({
+                    "subject": info.subject,
+                    "avtor_core": info.avtor_version,
+                    "recorded_on": info.recorded_on.to_string(),
+                }))),
+            );
+        }
+        data["database"] = database.into();
+    }
+    Ok(Report::new(lines, data)?)
 }
 
 #[derive(Deserialize, Debug)]
 pub struct EnvConfig {
-    pub db_host: String,
-    pub db_port: String,
-    pub db_user: String,
-    pub db_pass: String,
-    pub db_name: Option<String>,
     pub main_account_id: String,
     pub main_account_name: String,
-    pub super_user_username: String,
-    pub super_user_password: String,
-}
-
-// todo: move into package
-pub fn conn_str_from_config(config: &EnvConfig) -> String {
-    format!(
-        "postgres://{user}:{password}@{host}:{port}/{db}",
-        user = config.db_user,
-        password = config.db_pass,
-        host = config.db_host,
-        port = config.db_port,
-        db = config.db_name.to_owned().unwrap_or("postgres".to_string()),
-    )
+    pub super_user_username: Option<String>,
+    pub super_user_password: Option<Secret<String>>,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn main() -> ExitCode {
     let args = Args::parse();
-    let env_config = envy::from_env::<EnvConfig>()?;
-    let conn_str = conn_str_from_config(&env_config);
+    let mode = args.output;
+    let result = match run(args).await {
+        Ok(report) => output::print_report(mode, report).map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => ExitCode::from(output::print_error(mode, &e)),
+    }
+}
+
+async fn run(args: Args) -> Result<Report, anyhow::Error> {
+    if let Command::Config(ConfigCommand::Schema) = args.command {
+        let mut lines = vec![
+            "# environment variables are read upper case first, e.g. DB_HOST before db_host"
+                .to_string(),
+        ];
+        lines.extend(config::schema_lines());
+        return Ok(Report::new(lines, config::FileConfig::keys())?);
+    }
+    let config = EffectiveConfig::from_env(args.config.as_deref())?;
+    if let Command::Config(ConfigCommand::Show) = args.command {
+        let data = json!({
+            "settings": config.redacted_vars(),
+            "database_url": config.redacted_database_url().ok(),
+        });
+        return Ok(Report::new(config.redacted(), data)?);
+    }
+    avtor_core::password::install_hashers(config.password_hashers()?);
+    if let Command::Migrate {
+        direction:
+            Some(MigrateDirection::Up {
+                targets: Some(path),
+                parallel,
+                continue_on_error,
+            }),
+        allow_drift,
+    } = args.command
+    {
+        let targets = migrations::targets::TargetsFile::load(&path)?.targets;
+        return migrations::targets::migrate_targets(
+            targets,
+            parallel,
+            !continue_on_error,
+            allow_drift,
+        )
+        .await;
+    }
+    let prompter = Prompter::new(args.non_interactive);
+    let conn_str = config.database_url()?;
     let (mut client, conn) = tokio_postgres::connect(&conn_str, NoTls).await?;
-    match args.op.as_str() {
-        "hello" => Ok(println!("hello")),
-        "run_migrations" => {
-            tokio::spawn(async move {
-                if let Err(e) = conn.await {
-                    eprintln!("conn error: {}", e);
-                }
-            });
-            migrations::run_migrations::run_migration_up(&mut client).await
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
         }
-        "create_super_user" => match args.path {
-            None => Ok(println!(
-                "Credentials file path required for {} operation",
-                args.op
-            )),
-            Some(path) => {
-                let file = std::fs::File::open(path)?;
-                let yamlSuperUser: YamlSuperUser = serde_yaml::from_reader(file)?;
-                let user_dto = UserDto {
-                    id: uuid::Uuid::new_v4(),
-                    username: env_config.super_user_username,
-                    password: env_config.super_user_password,
-                    roles: "super_user".to_string(),
-                    account_id: uuid::Uuid::from_str(env_config.main_account_id.as_str())?,
-                };
-                let account_dto = AccountDto {
-                    id: uuid::Uuid::from_str(env_config.main_account_id.as_str())?,
-                    name: env_config.main_account_name,
-                };
-                Ok(println!("put parsing logic here"))
+    });
+    // see Runner::rollback for why rolling back is let through
+    if !matches!(
+        args.command,
+        Command::Hello
+            | Command::Version { .. }
+            | Command::Migrate {
+                direction: Some(MigrateDirection::Down { .. }),
+                ..
             }
-        },
-        _ => Ok(println!("operation {} not recognized", args.op)),
+    ) {
+        ensure_schema_compatible(&client, Runner::builtin().expected_schema_version()).await?;
+    }
+    match args.command {
+        Command::Hello => Ok(Report::line("hello", json!({ "message": "hello" }))),
+        Command::Version { remote } => version_report(&client, remote).await,
+        Command::Migrate {
+            direction: None | Some(MigrateDirection::Up { .. }),
+            allow_drift,
+        } => {
+            migrations::run_migrations::run_migration_up(&mut client, allow_drift, args.output)
+                .await
+        }
+        Command::Migrate {
+            direction: Some(MigrateDirection::Down { to, yes }),
+            ..
+        } => {
+            let question = match to {
+                Some(to) => format!("roll back every migration after {}?", to),
+                None => "roll back the latest migration?".to_string(),
+            };
+            if !yes && !prompter.confirm(&question, "--yes")? {
+                anyhow::bail!("rollback cancelled");
+            }
+            migrations::run_migrations::run_migration_down(&mut client, to, args.output).await
+        }
+        Command::Migrate {
+            direction: Some(MigrateDirection::Baseline { to }),
+            ..
+        } => migrations::run_migrations::run_migration_baseline(&mut client, to, args.output).await,
+        Command::CreateSuperUser { path } => {
+            let env_config = envy::from_iter::<_, EnvConfig>(config.vars())?;
+            let yaml = match &path {
+                Some(path) => serde_yaml::from_reader(std::fs::File::open(path)?)?,
+                None => YamlSuperUser::default(),
+            };
+            let (user_dto, account_dto) = super_user_dtos(env_config, yaml, &prompter)?;
+            let breach_checker = config.breach_checker()?;
+            _create_super_user(&mut client, breach_checker, &user_dto, &account_dto).await?;
+            Ok(Report::line(
+                format!("created super user {}", user_dto.username),
+                json!({
+                    "user_id": user_dto.id,
+                    "username": user_dto.username,
+                    "account_id": account_dto.id,
+                }),
+            ))
+        }
+        Command::CreateUser {
+            username,
+            password,
+            password_stdin,
+            roles,
+            account_id,
+            email,
+        } => {
+            let username = prompter.text_or_prompt(username, "username")?;
+            let password = match (password, password_stdin) {
+                (Some(password), _) => Secret::from(password),
+                (None, true) => users::create_user::read_password_stdin()?,
+                (None, false) => prompter.new_password("password")?,
+            };
+            let user_dto = UserDto {
+                id: uuid::Uuid::new_v4(),
+                username,
+                password,
+                roles,
+                account_id,
+                email,
+            };
+            let data = json!({
+                "user_id": user_dto.id,
+                "username": user_dto.username,
+                "account_id": user_dto.account_id,
+                "email": user_dto.email,
+            });
+            let line = format!("created user {}", user_dto.username);
+            let breach_checker = config.breach_checker()?;
+            users::create_user::create_user(&mut client, breach_checker, user_dto).await?;
+            Ok(Report::line(line, data))
+        }
+        Command::ListUsers {
+            account_id,
+            role,
+            format,
+            limit,
+            after,
+        } => {
+            let filter = UserFilter { account_id, role };
+            users::list_users::list_users(&client, filter, after, limit, format).await
+        }
+        Command::Invite {
+            email,
+            account,
+            roles,
+            expires_in_hours,
+            base_url,
+        } => {
+            let dto = InvitationDto {
+                email,
+                account_id: account,
+                roles,
+            };
+            let delivery = invitations::invite::Delivery {
+                sender: config.email_sender()?,
+                fallback: config.invitation_template()?,
+            };
+            invitations::invite::invite(
+                &mut client,
+                config.token_secret()?,
+                chrono::Duration::hours(expires_in_hours),
+                config.token_leeway()?,
+                base_url,
+                delivery,
+                dto,
+            )
+            .await
+        }
+        Command::ListInvitations { account, status } => {
+            invitations::list_invitations::list_invitations(
+                &mut client,
+                account,
+                status,
+                config.token_leeway()?,
+            )
+            .await
+        }
+        Command::SetEmailTemplate {
+            account,
+            kind,
+            path,
+        } => email_templates::set_email_template(&mut client, account, kind, &path).await,
+        Command::Seed { path } => seed::run_seed(&mut client, &path).await,
+        Command::Config(_) => unreachable!("config commands return before connecting"),
     }
 }
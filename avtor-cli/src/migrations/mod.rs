@@ -1,2 +1,2 @@
-pub mod migration_01;
-pub mod run_migrations;
\ No newline at end of file
+pub mod run_migrations;
+pub mod targets;
@@ -1,7 +1,82 @@
+use avtor_core::migrations::{MigrationProgress, Runner};
+use serde_json::json;
 use tokio_postgres::Client;
 
-use super::migration_01;
+use crate::output::{OutputMode, Report};
 
-pub async fn run_migration_up(client: &mut Client) -> Result<(), anyhow::Error> {
-    migration_01::run_migration(client).await
+fn print_progress(progress: &MigrationProgress) {
+    match progress {
+        MigrationProgress::AlreadyApplied { name, .. } => println!("{} already applied", name),
+        MigrationProgress::Applying { name, .. } => println!("applying {}", name),
+        MigrationProgress::Applied { name, .. } => println!("{} ran without error", name),
+        MigrationProgress::RollingBack { name, .. } => println!("rolling back {}", name),
+        MigrationProgress::RolledBack { name, .. } => println!("{} rolled back", name),
+        MigrationProgress::Baselined { name, .. } => println!("{} marked as applied", name),
+        MigrationProgress::Drifted { name, .. } => {
+            eprintln!("warning: {} was edited after it was applied", name)
+        }
+    }
+}
+
+/// Progress is printed as it happens in text mode; json only gets the names
+/// of the migrations that were applied.
+pub async fn run_migration_up(
+    client: &mut Client,
+    allow_drift: bool,
+    mode: OutputMode,
+) -> Result<Report, anyhow::Error> {
+    let runner = match mode {
+        OutputMode::Text => Runner::builtin().on_progress(print_progress),
+        OutputMode::Json => Runner::builtin(),
+    }
+    .allow_drift(allow_drift);
+    let applied: Vec<String> = runner
+        .run(client)
+        .await?
+        .into_iter()
+        .map(|m| m.name)
+        .collect();
+    Ok(Report::new(vec![], json!({ "applied": applied }))?)
+}
+
+/// Rolls back everything after `to`, or the latest migration without it.
+pub async fn run_migration_down(
+    client: &mut Client,
+    to: Option<i32>,
+    mode: OutputMode,
+) -> Result<Report, anyhow::Error> {
+    let runner = match mode {
+        OutputMode::Text => Runner::builtin().on_progress(print_progress),
+        OutputMode::Json => Runner::builtin(),
+    };
+    let rolled_back: Vec<String> = runner
+        .rollback(client, to)
+        .await?
+        .into_iter()
+        .map(|m| m.name)
+        .collect();
+    let lines = match rolled_back.is_empty() {
+        true => vec!["nothing to roll back".to_string()],
+        false => vec![],
+    };
+    Ok(Report::new(lines, json!({ "rolled_back": rolled_back }))?)
+}
+
+/// Marks every migration up to `to` as applied without running it.
+pub async fn run_migration_baseline(
+    client: &mut Client,
+    to: i32,
+    mode: OutputMode,
+) -> Result<Report, anyhow::Error> {
+    let runner = match mode {
+        OutputMode::Text => Runner::builtin().on_progress(print_progress),
+        OutputMode::Json => Runner::builtin(),
+    };
+    let baselined: Vec<String> = runner
+        .baseline(client, to)
+        .await?
+        .into_iter()
+        .map(|m| m.name)
+        .collect();
+    Ok(Report::new(vec![], json!({ "baselined": baselined }))?)
 }
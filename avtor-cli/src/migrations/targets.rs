@@ -0,0 +1,149 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio_postgres::NoTls;
+
+use avtor_core::config::validate_database_url;
+use avtor_core::migrations::Runner;
+
+use crate::output::{Report, TARGETS_FAILED};
+
+/// Layout of the `--targets` file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetsFile {
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    /// Shown in the results, e.g. the tenant.
+    pub name: String,
+    pub url: String,
+}
+
+impl TargetsFile {
+    /// Reads a YAML targets file and checks every url before anything runs.
+    pub fn load(path: &str) -> Result<TargetsFile, anyhow::Error> {
+        let file: TargetsFile = serde_yaml::from_reader(std::fs::File::open(path)?)?;
+        for target in &file.targets {
+            validate_database_url(&target.url)
+                .map_err(|e| anyhow::anyhow!("target {}: {}", target.name, e))?;
+        }
+        Ok(file)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetStatus {
+    Ok,
+    Failed,
+    /// Not started because an earlier target failed in fail-fast mode.
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TargetResult {
+    pub name: String,
+    pub status: TargetStatus,
+    pub applied: Vec<String>,
+    pub error: Option<String>,
+}
+
+async fn migrate_target(target: &Target, allow_drift: bool) -> Result<Vec<String>, anyhow::Error> {
+    let (mut client, conn) = tokio_postgres::connect(&target.url, NoTls).await?;
+    let name = target.name.clone();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("{}: conn error: {}", name, e);
+        }
+    });
+    let applied = Runner::builtin()
+        .allow_drift(allow_drift)
+        .run(&mut client)
+        .await?;
+    Ok(applied.into_iter().map(|m| m.name).collect())
+}
+
+/// Migrates every target with at most `parallel` running at once. With
+/// `fail_fast` the targets not yet started when one fails are skipped; the
+/// ones already running are left to finish since each migration is its own
+/// transaction anyway.
+pub async fn migrate_targets(
+    targets: Vec<Target>,
+    parallel: usize,
+    fail_fast: bool,
+    allow_drift: bool,
+) -> Result<Report, anyhow::Error> {
+    let permits = Arc::new(Semaphore::new(parallel.max(1)));
+    let failed = Arc::new(AtomicBool::new(false));
+    let handles: Vec<_> = targets
+        .into_iter()
+        .map(|target| {
+            let permits = permits.clone();
+            let failed = failed.clone();
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                if fail_fast && failed.load(Ordering::SeqCst) {
+                    return TargetResult {
+                        name: target.name,
+                        status: TargetStatus::Skipped,
+                        applied: vec![],
+                        error: None,
+                    };
+                }
+                match migrate_target(&target, allow_drift).await {
+                    Ok(applied) => TargetResult {
+                        name: target.name,
+                        status: TargetStatus::Ok,
+                        applied,
+                        error: None,
+                    },
+                    Err(e) => {
+                        failed.store(true, Ordering::SeqCst);
+                        TargetResult {
+                            name: target.name,
+                            status: TargetStatus::Failed,
+                            applied: vec![],
+                            error: Some(format!("{:#}", e)),
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+    let mut results = vec![];
+    for handle in handles {
+        results.push(handle.await?);
+    }
+
+    let lines = results
+        .iter()
+        .map(|r| match (&r.status, &r.error) {
+            (TargetStatus::Failed, Some(e)) => format!("{}: failed: {}", r.name, e),
+            (TargetStatus::Skipped, _) => format!("{}: skipped", r.name),
+            _ if r.applied.is_empty() => format!("{}: up to date", r.name),
+            _ => format!("{}: applied {}", r.name, r.applied.join(", ")),
+        })
+        .collect();
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let (failed, skipped) = (count(TargetStatus::Failed), count(TargetStatus::Skipped));
+    let total = results.len();
+    let report = Report::new(lines, serde_json::json!({ "targets": results }))?;
+    Ok(match failed {
+        0 => report,
+        _ => report.with_failure(
+            TARGETS_FAILED,
+            format!(
+                "{} of {} targets failed, {} skipped",
+                failed, total, skipped
+            ),
+        ),
+    })
+}
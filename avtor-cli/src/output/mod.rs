@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use clap::ArgEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use avtor_core::migrations::MigrationError;
+use avtor_core::models::{
+    email_templates::EmailTemplateError,
+    invitations::InvitationError,
+    migrations::SchemaVersionError,
+    users::{CreateSuperUserError, CreateUserError},
+};
+use avtor_core::seed::SeedError;
+
+use crate::config::CliConfigError;
+use crate::prompt::PromptError;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    Text,
+    Json,
+}
+
+/// What a command reports when it succeeds: lines for people and a value for
+/// scripts.
+pub struct Report {
+    lines: Vec<String>,
+    /// Printed to stderr after `lines` in text mode, left out of json.
+    notes: Vec<String>,
+    data: Value,
+    /// Set when the command did its work but part of it failed, e.g. one of
+    /// several databases; `data` still describes everything that happened.
+    failure: Option<(ErrorKind, String)>,
+}
+
+impl Report {
+    pub fn new(lines: Vec<String>, data: impl Serialize) -> Result<Report, serde_json::Error> {
+        Ok(Report {
+            lines,
+            notes: vec![],
+            data: serde_json::to_value(data)?,
+            failure: None,
+        })
+    }
+
+    pub fn line(line: impl Into<String>, data: Value) -> Report {
+        Report {
+            lines: vec![line.into()],
+            notes: vec![],
+            data,
+            failure: None,
+        }
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Report {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_failure(mut self, kind: ErrorKind, message: impl Into<String>) -> Report {
+        self.failure = Some((kind, message.into()));
+        self
+    }
+}
+
+/// Left aligned columns separated by two spaces, with `header` as the first
+/// line.
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> Vec<String> {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| {
+            rows.iter()
+                .map(|r| r[i].chars().count())
+                .chain([header[i].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |values: Vec<&str>| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(v, w)| format!("{:w$}", v, w = w))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let mut lines = vec![line(header.to_vec())];
+    lines.extend(
+        rows.iter()
+            .map(|r| line(r.iter().map(|s| s.as_str()).collect())),
+    );
+    lines
+}
+
+/// Classification of an error returned by `run`, shared by the exit status
+/// and the `code` of the json error object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorKind {
+    pub exit_code: u8,
+    pub code: &'static str,
+}
+
+const fn kind(exit_code: u8, code: &'static str) -> ErrorKind {
+    ErrorKind { exit_code, code }
+}
+
+/// At least one of several databases failed, see `migrate up --targets`.
+pub const TARGETS_FAILED: ErrorKind = kind(9, "targets_failed");
+
+/// An invitation was stored but emailing it failed, see `invite`.
+pub const EMAIL_FAILED: ErrorKind = kind(11, "email_failed");
+
+/// Exit status and code for errors returned by `run`. clap exits with 2 on
+/// usage errors before any of this runs.
+///
+/// | exit | meaning                                    |
+/// |------|--------------------------------------------|
+/// | 1    | anything not listed below                  |
+/// | 3    | the supplied user, account or input is bad |
+/// | 4    | the super user, account name, username,    |
+/// |      | email or pending invitation exists         |
+/// | 5    | the configuration is missing or invalid    |
+/// | 6    | the database could not be reached          |
+/// | 7    | the database schema is newer than the cli  |
+/// | 8    | applied migrations were edited             |
+/// | 9    | some of several target databases failed    |
+/// | 10   | another migration holds the migration lock |
+/// | 11   | an invitation was stored but not emailed   |
+pub fn classify(e: &anyhow::Error) -> ErrorKind {
+    if let Some(e) = e.downcast_ref::<CreateSuperUserError>() {
+        return match e {
+            CreateSuperUserError::SuperUserExists => kind(4, "super_user_exists"),
+            CreateSuperUserError::UsernameTaken => kind(4, "username_taken"),
+            CreateSuperUserError::UserInvalid(_) => kind(3, "user_invalid"),
+            CreateSuperUserError::AccountInvalid(_) => kind(3, "account_invalid"),
+            CreateSuperUserError::AccountNameTaken => kind(4, "account_name_taken"),
+            CreateSuperUserError::PasswordBreached => kind(3, "password_breached"),
+            _ => kind(1, "error"),
+        };
+    }
+    if let Some(e) = e.downcast_ref::<CreateUserError>() {
+        return match e {
+            CreateUserError::UsernameTaken => kind(4, "username_taken"),
+            CreateUserError::UsernameConfusable(_) => kind(4, "username_confusable"),
+            CreateUserError::EmailTaken => kind(4, "email_taken"),
+            CreateUserError::UserInvalid(_) => kind(3, "user_invalid"),
+            CreateUserError::ReservedRole(_) => kind(3, "reserved_role"),
+            CreateUserError::AccountNotFound => kind(3, "account_not_found"),
+            CreateUserError::PasswordBreached => kind(3, "password_breached"),
+            _ => kind(1, "error"),
+        };
+    }
+    if let Some(e) = e.downcast_ref::<InvitationError>() {
+        return match e {
+            InvitationError::Invalid(_) => kind(3, "invitation_invalid"),
+            InvitationError::ReservedRole(_) => kind(3, "reserved_role"),
+            InvitationError::AccountNotFound => kind(3, "account_not_found"),
+            InvitationError::AlreadyInvited(_) => kind(4, "already_invited"),
+            _ => kind(1, "error"),
+        };
+    }
+    if let Some(e) = e.downcast_ref::<EmailTemplateError>() {
+        return match e {
+            EmailTemplateError::Invalid(_) => kind(3, "template_invalid"),
+            EmailTemplateError::AccountNotFound => kind(3, "account_not_found"),
+            EmailTemplateError::RepoError(_) => kind(1, "error"),
+        };
+    }
+    if let Some(e) = e.downcast_ref::<PromptError>() {
+        return match e {
+            PromptError::NonInteractive(_) => kind(3, "input_required"),
+            PromptError::Mismatch => kind(3, "password_mismatch"),
+            PromptError::Io(_) => kind(1, "error"),
+        };
+    }
+    if let Some(e) = e.downcast_ref::<SeedError>() {
+        return match e {
+            SeedError::RepoError(_) => kind(1, "error"),
+            _ => kind(3, "seed_invalid"),
+        };
+    }
+    if let Some(MigrationError::UnknownSeqOrder(_)) = e.downcast_ref() {
+        return kind(3, "unknown_seq_order");
+    }
+    if let Some(MigrationError::Irreversible(_)) = e.downcast_ref() {
+        return kind(3, "migration_irreversible");
+    }
+    if e.is::<CliConfigError>() || e.is::<envy::Error>() {
+        return kind(5, "config_invalid");
+    }
+    if e.is::<tokio_postgres::Error>() {
+        return kind(6, "database_unavailable");
+    }
+    if let Some(SchemaVersionError::DatabaseNewer { .. }) = e.downcast_ref() {
+        return kind(7, "schema_newer");
+    }
+    if let Some(MigrationError::SchemaVersion(SchemaVersionError::DatabaseNewer { .. })) =
+        e.downcast_ref()
+    {
+        return kind(7, "schema_newer");
+    }
+    if let Some(MigrationError::Drift(_)) = e.downcast_ref() {
+        return kind(8, "migration_drift");
+    }
+    if let Some(MigrationError::InProgress) = e.downcast_ref() {
+        return kind(10, "migration_in_progress");
+    }
+    kind(1, "error")
+}
+
+/// Per field messages of a validation failure, if `e` is one.
+fn invalid_fields(e: &anyhow::Error) -> Option<&HashMap<String, String>> {
+    match (
+        e.downcast_ref::<CreateSuperUserError>(),
+        e.downcast_ref::<CreateUserError>(),
+        e.downcast_ref::<InvitationError>(),
+    ) {
+        (
+            Some(
+                CreateSuperUserError::UserInvalid(fields)
+                | CreateSuperUserError::AccountInvalid(fields),
+            ),
+            _,
+            _,
+        )
+        | (_, Some(CreateUserError::UserInvalid(fields)), _)
+        | (_, _, Some(InvitationError::Invalid(fields))) => Some(fields),
+        _ => None,
+    }
+}
+
+/// Prints `report` and returns the exit status, which is only non-zero for a
+/// report with a failure.
+pub fn print_report(mode: OutputMode, report: Report) -> Result<u8, serde_json::Error> {
+    match mode {
+        OutputMode::Text => {
+            for line in report.lines {
+                println!("{}", line);
+            }
+            for note in report.notes {
+                eprintln!("{}", note);
+            }
+            if let Some((_, message)) = &report.failure {
+                eprintln!("error: {}", message);
+            }
+        }
+        OutputMode::Json => {
+            let output = match &report.failure {
+                None => json!({ "ok": true, "result": report.data }),
+                Some((kind, message)) => json!({
+                    "ok": false,
+                    "result": report.data,
+                    "error": {
+                        "code": kind.code,
+                        "exit_code": kind.exit_code,
+                        "message": message,
+                    }
+                }),
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?)
+        }
+    }
+    Ok(report.failure.map_or(0, |(kind, _)| kind.exit_code))
+}
+
+/// Prints `e` to stderr as text, or to stdout as a json error object so
+/// scripts only have to parse one stream, and returns the exit status.
+pub fn print_error(mode: OutputMode, e: &anyhow::Error) -> u8 {
+    let kind = classify(e);
+    let fields = invalid_fields(e);
+    match mode {
+        OutputMode::Text => match fields {
+            Some(fields) => eprintln!("error: {}: {:?}", e, fields),
+            None => eprintln!("error: {:#}", e),
+        },
+        OutputMode::Json => {
+            let error = json!({
+                "ok": false,
+                "error": {
+                    "code": kind.code,
+                    "exit_code": kind.exit_code,
+                    "message": format!("{:#}", e),
+                    "fields": fields,
+                }
+            });
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&error).unwrap_or_else(|_| error.to_string())
+            );
+        }
+    }
+    kind.exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use avtor_core::models::invitations::InvitationError;
+    use avtor_core::models::users::{CreateSuperUserError, CreateUserError};
+
+    use super::{classify, kind};
+    use crate::prompt::PromptError;
+
+    #[test]
+    pub fn test_domain_errors_get_distinct_codes() {
+        let cases = [
+            (
+                anyhow::Error::from(CreateSuperUserError::SuperUserExists),
+                kind(4, "super_user_exists"),
+            ),
+            (
+                CreateUserError::UsernameTaken.into(),
+                kind(4, "username_taken"),
+            ),
+            (
+                CreateUserError::UserInvalid(HashMap::new()).into(),
+                kind(3, "user_invalid"),
+            ),
+            (
+                CreateUserError::AccountNotFound.into(),
+                kind(3, "account_not_found"),
+            ),
+            (
+                PromptError::NonInteractive("password").into(),
+                kind(3, "input_required"),
+            ),
+            (
+                InvitationError::AlreadyInvited("a@example.com".to_string()).into(),
+                kind(4, "already_invited"),
+            ),
+            (anyhow::anyhow!("something else"), kind(1, "error")),
+        ];
+        for (e, expected) in cases {
+            assert_eq!(expected, classify(&e), "{}", e);
+        }
+    }
+}
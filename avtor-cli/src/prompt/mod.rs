@@ -0,0 +1,91 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use avtor_core::secret::Secret;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PromptError {
+    #[error(
+        "{0} is required; pass it as a flag or environment variable when not running interactively"
+    )]
+    NonInteractive(&'static str),
+
+    #[error("the passwords do not match")]
+    Mismatch,
+
+    #[error("could not read from the terminal: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Asks for values that weren't supplied by flags, files or the environment.
+/// Never prompts with `--non-interactive` or when stdin is not a terminal, so
+/// CI runs fail instead of hanging.
+pub struct Prompter {
+    interactive: bool,
+}
+
+impl Prompter {
+    pub fn new(non_interactive: bool) -> Prompter {
+        Prompter {
+            interactive: !non_interactive && std::io::stdin().is_terminal(),
+        }
+    }
+
+    pub fn text(&self, name: &'static str) -> Result<String, PromptError> {
+        if !self.interactive {
+            return Err(PromptError::NonInteractive(name));
+        }
+        eprint!("{}: ", name);
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        Ok(line.trim().to_string())
+    }
+
+    /// True only for an answer starting with `y`.
+    pub fn confirm(&self, question: &str, flag: &'static str) -> Result<bool, PromptError> {
+        if !self.interactive {
+            return Err(PromptError::NonInteractive(flag));
+        }
+        eprint!("{} [y/N]: ", question);
+        std::io::stderr().flush()?;
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        Ok(line.trim().to_lowercase().starts_with('y'))
+    }
+
+    /// Reads a password twice without echoing it.
+    pub fn new_password(&self, name: &'static str) -> Result<Secret<String>, PromptError> {
+        if !self.interactive {
+            return Err(PromptError::NonInteractive(name));
+        }
+        let first = Secret::new(rpassword::prompt_password(format!("{}: ", name))?);
+        let second = Secret::new(rpassword::prompt_password(format!("confirm {}: ", name))?);
+        if first != second {
+            return Err(PromptError::Mismatch);
+        }
+        Ok(first)
+    }
+
+    /// `value` when given, otherwise the answer to a prompt.
+    pub fn text_or_prompt(
+        &self,
+        value: Option<String>,
+        name: &'static str,
+    ) -> Result<String, PromptError> {
+        match value {
+            Some(value) => Ok(value),
+            None => self.text(name),
+        }
+    }
+
+    pub fn password_or_prompt(
+        &self,
+        value: Option<Secret<String>>,
+        name: &'static str,
+    ) -> Result<Secret<String>, PromptError> {
+        match value {
+            Some(value) => Ok(value),
+            None => self.new_password(name),
+        }
+    }
+}
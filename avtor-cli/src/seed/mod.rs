@@ -0,0 +1,40 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::seed::{run_seeders, SeedFile};
+use serde_json::json;
+use tokio_postgres::Client;
+
+use crate::output::Report;
+
+/// Reads a YAML or JSON seed file, chosen by extension.
+pub fn load_seed_file(path: &str) -> Result<SeedFile, anyhow::Error> {
+    let file = std::fs::File::open(path)?;
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("json") => Ok(serde_json::from_reader(file)?),
+        Some("yaml") | Some("yml") => Ok(serde_yaml::from_reader(file)?),
+        _ => anyhow::bail!("seed file {} must end in .yaml, .yml or .json", path),
+    }
+}
+
+/// Seeds everything in `path` in one transaction; running it again only
+/// writes what changed in the file.
+pub async fn run_seed(client: &mut Client, path: &str) -> Result<Report, anyhow::Error> {
+    let seeders = load_seed_file(path)?.seeders(Arc::new(StandardNormalizer::default()));
+    let counts = run_seeders(client, &seeders).await?;
+    let lines = counts
+        .iter()
+        .map(|(name, c)| {
+            format!(
+                "{}: {} inserted, {} updated, {} unchanged",
+                name, c.inserted, c.updated, c.unchanged
+            )
+        })
+        .collect();
+    let data: serde_json::Map<String, serde_json::Value> = counts
+        .into_iter()
+        .map(|(name, c)| (name.to_string(), json!(c)))
+        .collect();
+    Ok(Report::new(lines, data)?)
+}
@@ -0,0 +1,39 @@
+use std::io::BufRead;
+
+use avtor_core::db::with_transaction;
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::models::users::{create_user_with_repos, CreateUserError, UserDto};
+use avtor_core::password::breach::BreachChecker;
+use avtor_core::repo::{PgAccountRepo, PgUserRepo};
+use avtor_core::secret::Secret;
+use tokio_postgres::Client;
+
+/// Reads the first line of stdin without its line ending.
+pub fn read_password_stdin() -> Result<Secret<String>, anyhow::Error> {
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    let password = Secret::from(line.trim_end_matches(&['\r', '\n'][..]));
+    line.clear();
+    Ok(password)
+}
+
+pub async fn create_user(
+    client: &mut Client,
+    breach_checker: Box<dyn BreachChecker>,
+    user_dto: UserDto,
+) -> Result<(), CreateUserError> {
+    with_transaction(client, move |trans| {
+        Box::pin(async move {
+            create_user_with_repos(
+                &PgUserRepo::new(trans),
+                &PgAccountRepo::new(trans),
+                &StandardNormalizer::default(),
+                breach_checker.as_ref(),
+                &user_dto,
+            )
+            .await
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| CreateUserError::RepoError(e.to_string())))
+}
@@ -0,0 +1,85 @@
+use clap::ArgEnum;
+use serde::Serialize;
+use tokio_postgres::Client;
+use uuid::Uuid;
+
+use avtor_core::models::users::{find_users_page, User, UserFilter};
+use avtor_core::postgres_common::core::Cursor;
+
+use crate::output::{self, Report};
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// What `list-users` shows of a user; never the password hash.
+#[derive(Serialize)]
+struct UserRow {
+    id: Uuid,
+    username: String,
+    roles: String,
+    account_id: Uuid,
+}
+
+impl From<&User> for UserRow {
+    fn from(u: &User) -> Self {
+        UserRow {
+            id: u.id().uuid(),
+            username: u.username().to_string(),
+            roles: u.roles().to_string(),
+            account_id: u.account_id(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UsersPage {
+    users: Vec<UserRow>,
+    next_cursor: Option<String>,
+}
+
+fn table(rows: &[UserRow]) -> Vec<String> {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|r| {
+            vec![
+                r.id.to_string(),
+                r.username.clone(),
+                r.roles.clone(),
+                r.account_id.to_string(),
+            ]
+        })
+        .collect();
+    output::table(&["id", "username", "roles", "account_id"], &cells)
+}
+
+/// One page of users. The cursor for the next page goes to stderr in table
+/// format and into `next_cursor` in json.
+pub async fn list_users(
+    client: &Client,
+    filter: UserFilter,
+    after: Option<String>,
+    limit: i64,
+    format: OutputFormat,
+) -> Result<Report, anyhow::Error> {
+    let page = find_users_page(client)(filter, after.map(Cursor::from), limit).await?;
+    let page = UsersPage {
+        users: page.items.iter().map(UserRow::from).collect(),
+        next_cursor: page.next_cursor.map(|c| c.to_string()),
+    };
+    let lines = match format {
+        OutputFormat::Table => table(&page.users),
+        OutputFormat::Json => vec![serde_json::to_string_pretty(&page)?],
+    };
+    let note = match (format, &page.next_cursor) {
+        (OutputFormat::Table, Some(cursor)) => Some(format!("more users: pass --after {}", cursor)),
+        _ => None,
+    };
+    let report = Report::new(lines, page)?;
+    Ok(match note {
+        Some(note) => report.with_note(note),
+        None => report,
+    })
+}
@@ -0,0 +1,38 @@
+//! Builds the filter file read by `passwords.breach_check = "strict-offline"`
+//! from the downloadable HaveIBeenPwned SHA-1 list, one `HASH:COUNT` per line:
+//!
+//! ```sh
+//! cargo run --release -p avtor-core --example build_breach_filter -- \
+//!     pwned-passwords-sha1.txt breached.bloom 1000000000 0.001
+//! ```
+//!
+//! The last two arguments are the number of hashes in the list and the
+//! acceptable false positive rate.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+
+use avtor_core::password::breach::BloomFilter;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, output, expected_items, false_positive_rate) = match args.as_slice() {
+        [input, output, expected_items, false_positive_rate] => (
+            input,
+            output,
+            expected_items.parse()?,
+            false_positive_rate.parse()?,
+        ),
+        _ => return Err("usage: build_breach_filter INPUT OUTPUT EXPECTED_ITEMS FP_RATE".into()),
+    };
+    let filter = BloomFilter::from_hash_lines(
+        BufReader::new(File::open(input)?),
+        expected_items,
+        false_positive_rate,
+    )?;
+    let mut writer = BufWriter::new(File::create(output)?);
+    filter.write_to(&mut writer)?;
+    writer.flush()?;
+    println!("wrote {}", output);
+    Ok(())
+}
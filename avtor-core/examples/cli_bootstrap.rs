@@ -0,0 +1,74 @@
+//! Brings a database up to date and creates the super user, the same steps
+//! `avtor-cli migrate` and `avtor-cli create-super-user` take:
+//!
+//! ```sh
+//! set -a; source config/local.env; set +a
+//! super_user_username=admin super_user_password='!Q2w3e4r5t' \
+//!     cargo run -p avtor-core --example cli_bootstrap
+//! ```
+
+use avtor_core::config::database_url_from_env;
+use avtor_core::db::with_transaction;
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::migrations::{MigrationProgress, Runner};
+use avtor_core::models::users::{
+    create_super_user_with_repos, AccountDto, CreateSuperUserError, UserDto, SUPER_USER_ROLE,
+};
+use avtor_core::password::breach::NoBreachCheck;
+use avtor_core::repo::{PgAccountRepo, PgUserRepo};
+use tokio_postgres::NoTls;
+use uuid::Uuid;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let (mut client, conn) = tokio_postgres::connect(&database_url_from_env()?, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+
+    let applied = Runner::builtin()
+        .on_progress(|progress| {
+            if let MigrationProgress::Applied { name, .. } = progress {
+                println!("applied {}", name);
+            }
+        })
+        .run(&mut client)
+        .await?;
+    println!("{} migrations applied", applied.len());
+
+    let account_dto = AccountDto {
+        id: Uuid::new_v4(),
+        name: "main".to_string(),
+    };
+    let user_dto = UserDto {
+        id: Uuid::new_v4(),
+        username: std::env::var("super_user_username")?,
+        password: std::env::var("super_user_password")?.into(),
+        roles: SUPER_USER_ROLE.to_string(),
+        account_id: account_dto.id,
+        email: None,
+    };
+    let created = with_transaction(&mut client, move |trans| {
+        Box::pin(async move {
+            create_super_user_with_repos(
+                &PgUserRepo::new(trans),
+                &PgAccountRepo::new(trans),
+                &StandardNormalizer::default(),
+                &NoBreachCheck,
+                &user_dto,
+                &account_dto,
+            )
+            .await
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| CreateSuperUserError::RepoError(e.to_string())));
+    match created {
+        Ok(()) => println!("created the super user"),
+        Err(CreateSuperUserError::SuperUserExists) => println!("the super user already exists"),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
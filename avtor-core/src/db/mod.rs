@@ -0,0 +1,59 @@
+use futures::future::BoxFuture;
+use tokio_postgres::{Client, Transaction};
+
+use crate::error::DbError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransactionError<E> {
+    #[error("could not begin transaction: {0}")]
+    Begin(DbError),
+
+    #[error(transparent)]
+    Failed(E),
+
+    #[error("could not commit transaction: {0}")]
+    Commit(DbError),
+
+    #[error("could not roll back after {error}: {rollback}")]
+    Rollback { error: E, rollback: DbError },
+}
+
+impl<E> TransactionError<E> {
+    /// The error from the closure itself, or `f` applied to a begin, commit or
+    /// rollback failure.
+    pub fn into_inner_or(self, f: impl FnOnce(DbError) -> E) -> E {
+        match self {
+            TransactionError::Failed(e) | TransactionError::Rollback { error: e, .. } => e,
+            TransactionError::Begin(e) | TransactionError::Commit(e) => f(e),
+        }
+    }
+}
+
+/// Runs `f` in a new transaction, committing when it returns `Ok` and rolling
+/// back when it returns `Err`. Anything `f` borrows has to outlive every
+/// transaction, so move owned values into the closure.
+pub async fn with_transaction<T, E, F>(client: &mut Client, f: F) -> Result<T, TransactionError<E>>
+where
+    F: for<'t> FnOnce(&'t Transaction<'t>) -> BoxFuture<'t, Result<T, E>>,
+{
+    let trans = client
+        .transaction()
+        .await
+        .map_err(|e| TransactionError::Begin(e.into()))?;
+    match f(&trans).await {
+        Ok(v) => {
+            trans
+                .commit()
+                .await
+                .map_err(|e| TransactionError::Commit(e.into()))?;
+            Ok(v)
+        }
+        Err(error) => match trans.rollback().await {
+            Ok(()) => Err(TransactionError::Failed(error)),
+            Err(e) => Err(TransactionError::Rollback {
+                error,
+                rollback: e.into(),
+            }),
+        },
+    }
+}
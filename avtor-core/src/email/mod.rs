@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::secret::Secret;
+
+pub mod template;
+
+pub use template::{EmailKind, EmailTemplate};
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmailError {
+    #[error("invalid email address {0}")]
+    InvalidAddress(String),
+
+    #[error("invalid email template: {0}")]
+    InvalidTemplate(String),
+
+    #[error("invalid smtp settings: {0}")]
+    Config(String),
+
+    #[error("could not send email: {0}")]
+    Send(String),
+}
+
+/// A rendered plain text email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers emails such as invitations; which one is used is up to the
+/// application.
+#[async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), EmailError>;
+}
+
+/// Drops every email.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoEmail;
+
+#[async_trait]
+impl EmailSender for NoEmail {
+    async fn send(&self, _email: &Email) -> Result<(), EmailError> {
+        Ok(())
+    }
+}
+
+/// Logs emails instead of sending them, for development. The body is logged
+/// too and can hold tokens, so keep it out of production.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogEmail;
+
+#[async_trait]
+impl EmailSender for LogEmail {
+    async fn send(&self, email: &Email) -> Result<(), EmailError> {
+        tracing::info!(to = %email.to, subject = %email.subject, body = %email.body, "email");
+        Ok(())
+    }
+}
+
+/// Sends through an SMTP relay over TLS.
+pub struct SmtpEmail {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpEmail {
+    /// `port` defaults to 465; `credentials` are a username and password.
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        credentials: Option<(String, Secret<String>)>,
+        from: &str,
+    ) -> Result<SmtpEmail, EmailError> {
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+            .map_err(|e| EmailError::Config(e.to_string()))?;
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some((username, password)) = credentials {
+            builder =
+                builder.credentials(Credentials::new(username, password.expose_secret().clone()));
+        }
+        Ok(SmtpEmail {
+            transport: builder.build(),
+            from: mailbox(from)?,
+        })
+    }
+}
+
+fn mailbox(address: &str) -> Result<Mailbox, EmailError> {
+    address
+        .parse()
+        .map_err(|_| EmailError::InvalidAddress(address.to_string()))
+}
+
+#[async_trait]
+impl EmailSender for SmtpEmail {
+    async fn send(&self, email: &Email) -> Result<(), EmailError> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mailbox(&email.to)?)
+            .subject(email.subject.as_str())
+            .body(email.body.clone())
+            .map_err(|e| EmailError::Send(e.to_string()))?;
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| EmailError::Send(e.to_string()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::BTreeMap, str::FromStr};
+
+use super::{Email, EmailError};
+
+/// Sent with `invite_user`'s token when no other template is configured.
+/// Variables: `email`, `account_name`, `roles`, `expires_at` and `link`.
+pub const INVITATION_TEMPLATE: &str = "Subject: You're invited to {{account_name}}
+
+Hello,
+
+you have been invited to join {{account_name}}. Follow the link below to
+choose a username and password:
+
+{{link}}
+
+The link works until {{expires_at}}.
+";
+
+/// Sent with a password reset link when no other template is configured.
+/// Variables: `username`, `account_name`, `expires_at` and `link`.
+pub const PASSWORD_RESET_TEMPLATE: &str = "Subject: Reset your {{account_name}} password
+
+Hello {{username}},
+
+someone asked to reset your password. If it was you, follow the link below
+to choose a new one; otherwise ignore this email.
+
+{{link}}
+
+The link works until {{expires_at}}.
+";
+
+/// What an email is for; each kind has its own template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailKind {
+    Invitation,
+    PasswordReset,
+}
+
+impl EmailKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailKind::Invitation => "invitation",
+            EmailKind::PasswordReset => "password_reset",
+        }
+    }
+
+    fn default_text(&self) -> &'static str {
+        match self {
+            EmailKind::Invitation => INVITATION_TEMPLATE,
+            EmailKind::PasswordReset => PASSWORD_RESET_TEMPLATE,
+        }
+    }
+}
+
+impl FromStr for EmailKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "invitation" => Ok(EmailKind::Invitation),
+            "password_reset" => Ok(EmailKind::PasswordReset),
+            _ => Err(format!("unknown email kind {}", s)),
+        }
+    }
+}
+
+/// A subject and body with `{{name}}` placeholders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmailTemplate {
+    pub subject: String,
+    pub body: String,
+}
+
+impl EmailTemplate {
+    /// Reads a `Subject: ...` line, a blank line and the body, like the
+    /// headers and body of an email.
+    pub fn parse(text: &str) -> Result<EmailTemplate, EmailError> {
+        let text = text.replace("\r\n", "\n");
+        let (header, body) = text.split_once("\n\n").unwrap_or((text.as_str(), ""));
+        let subject = header
+            .strip_prefix("Subject:")
+            .filter(|_| !header.contains('\n'))
+            .ok_or_else(|| {
+                EmailError::InvalidTemplate(
+                    "expected a single `Subject:` line followed by a blank line".to_string(),
+                )
+            })?;
+        Ok(EmailTemplate {
+            subject: subject.trim().to_string(),
+            body: body.to_string(),
+        })
+    }
+
+    /// The built in template for `kind`.
+    pub fn default_for(kind: EmailKind) -> EmailTemplate {
+        EmailTemplate::parse(kind.default_text()).expect("built in templates parse")
+    }
+
+    /// The email to `to` with the placeholders replaced by `vars`.
+    pub fn render(&self, to: &str, vars: &BTreeMap<&str, String>) -> Email {
+        Email {
+            to: to.to_string(),
+            subject: render(&self.subject, vars),
+            body: render(&self.body, vars),
+        }
+    }
+}
+
+/// Replaces `{{name}}` (spaces inside the braces allowed) with `vars[name]`.
+/// Unknown names are left as they are so a typo shows in the email rather
+/// than silently disappearing.
+pub fn render(template: &str, vars: &BTreeMap<&str, String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                match vars.get(after[..end].trim()) {
+                    Some(value) => out.push_str(value),
+                    None => out.push_str(&rest[start..start + 2 + end + 2]),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{render, EmailKind, EmailTemplate};
+
+    #[test]
+    pub fn test_render_replaces_known_placeholders() {
+        let vars = BTreeMap::from([("name", "Ann".to_string())]);
+        assert_eq!("Hi Ann!", render("Hi {{name}}!", &vars));
+        assert_eq!("Hi Ann!", render("Hi {{ name }}!", &vars));
+        assert_eq!("Hi {{other}}", render("Hi {{other}}", &vars));
+        assert_eq!("Hi {{name", render("Hi {{name", &vars));
+    }
+
+    #[test]
+    pub fn test_parse_needs_a_subject_line() {
+        let template = EmailTemplate::parse("Subject: Hi {{name}}\r\n\r\nBody\n").unwrap();
+        assert_eq!("Hi {{name}}", template.subject);
+        assert_eq!("Body\n", template.body);
+        assert!(EmailTemplate::parse("Hello\n\nBody").is_err());
+        assert!(EmailTemplate::parse("Subject: a\nFrom: b\n\nBody").is_err());
+        for kind in [EmailKind::Invitation, EmailKind::PasswordReset] {
+            EmailTemplate::default_for(kind);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use tokio_postgres::error::SqlState;
+
+use crate::postgres_common::core::RowDecodeError;
+
+/// Failures from the database layer, classified by SQLSTATE so callers can tell
+/// a duplicate key from a dropped connection without parsing messages.
+#[derive(Debug, thiserror::Error)]
+pub enum DbError {
+    #[error("unique violation ({}): {message}", .constraint.as_deref().unwrap_or("unknown constraint"))]
+    UniqueViolation {
+        constraint: Option<String>,
+        message: String,
+    },
+
+    #[error("foreign key violation ({}): {message}", .constraint.as_deref().unwrap_or("unknown constraint"))]
+    ForeignKeyViolation {
+        constraint: Option<String>,
+        message: String,
+    },
+
+    #[error("serialization failure: {0}")]
+    SerializationFailure(String),
+
+    #[error("connection lost: {0}")]
+    ConnectionLost(String),
+
+    #[error("timed out: {0}")]
+    Timeout(String),
+
+    #[error(transparent)]
+    Decode(#[from] RowDecodeError),
+
+    #[error("invalid query: {0}")]
+    InvalidQuery(String),
+
+    #[error("database error: {0}")]
+    Other(String),
+}
+
+impl DbError {
+    /// Worth retrying in a fresh transaction, see `postgres_common::retry`.
+    /// A lost connection isn't: the client it happened on stays closed, so
+    /// only reconnecting can help.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DbError::SerializationFailure(_))
+    }
+}
+
+impl From<tokio_postgres::Error> for DbError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        if e.is_closed() {
+            return DbError::ConnectionLost(e.to_string());
+        }
+        let db_error = match e.as_db_error() {
+            Some(db_error) => db_error,
+            None => return DbError::Other(e.to_string()),
+        };
+        let constraint = db_error.constraint().map(|c| c.to_string());
+        let message = db_error.message().to_string();
+        let code = db_error.code();
+        if *code == SqlState::UNIQUE_VIOLATION {
+            DbError::UniqueViolation {
+                constraint,
+                message,
+            }
+        } else if *code == SqlState::FOREIGN_KEY_VIOLATION {
+            DbError::ForeignKeyViolation {
+                constraint,
+                message,
+            }
+        } else if *code == SqlState::T_R_SERIALIZATION_FAILURE
+            || *code == SqlState::T_R_DEADLOCK_DETECTED
+        {
+            DbError::SerializationFailure(message)
+        } else if *code == SqlState::QUERY_CANCELED || *code == SqlState::LOCK_NOT_AVAILABLE {
+            DbError::Timeout(message)
+        } else if code.code().starts_with("08") {
+            DbError::ConnectionLost(message)
+        } else {
+            DbError::Other(message)
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Builders for setting up accounts and users in tests. They persist through
+//! any `AccountRepo`/`UserRepo`, so the same setup works against postgres
+//! with `PgAccountRepo::new(&trans)` or in memory with `MemoryAccountRepo`.
+//! Available to other crates with the `test-util` feature.
+
+use uuid::Uuid;
+
+use crate::models::users::{
+    account_from_dto, user_from_dto, Account, AccountDto, CreateAccountError, CreateSuperUserError,
+    User, UserDto, SUPER_USER_ROLE,
+};
+use crate::password::{hash_password, PasswordHashError};
+use crate::repo::{AccountRepo, UserRepo};
+
+/// Password of every built user unless `UserBuilder::password` is used.
+pub const DEFAULT_PASSWORD: &str = "!Q2w3e4r5t";
+
+/// `prefix_` followed by random hex, so tests sharing a database don't
+/// collide on unique names.
+fn unique(prefix: &str) -> String {
+    let id = Uuid::new_v4().to_simple().to_string();
+    format!("{}_{}", prefix, &id[..12])
+}
+
+#[derive(Clone)]
+pub struct AccountBuilder {
+    dto: AccountDto,
+}
+
+impl Default for AccountBuilder {
+    fn default() -> Self {
+        AccountBuilder::new()
+    }
+}
+
+impl AccountBuilder {
+    /// An account with a random id and a unique name.
+    pub fn new() -> AccountBuilder {
+        AccountBuilder {
+            dto: AccountDto {
+                id: Uuid::new_v4(),
+                name: unique("account"),
+            },
+        }
+    }
+
+    pub fn id(mut self, id: Uuid) -> AccountBuilder {
+        self.dto.id = id;
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> AccountBuilder {
+        self.dto.name = name.into();
+        self
+    }
+
+    pub fn build(self) -> Account {
+        account_from_dto(self.dto)
+    }
+
+    /// Inserts the account and returns it as stored, i.e. with its slug
+    /// suffixed when another account already had it.
+    pub async fn insert(self, repo: &dyn AccountRepo) -> Result<Account, CreateAccountError> {
+        let account = self.build();
+        let id = account.id();
+        repo.insert_account(account).await?;
+        repo.find_account_by_id(id)
+            .await?
+            .ok_or_else(|| CreateAccountError::RepoError("inserted account not found".to_string()))
+    }
+}
+
+#[derive(Clone)]
+pub struct UserBuilder {
+    dto: UserDto,
+}
+
+impl UserBuilder {
+    /// A user in `account_id` with a unique username, `DEFAULT_PASSWORD` and
+    /// the `user` role.
+    pub fn new(account_id: Uuid) -> UserBuilder {
+        UserBuilder {
+            dto: UserDto {
+                id: Uuid::new_v4(),
+                username: unique("user"),
+                password: DEFAULT_PASSWORD.into(),
+                roles: "user".to_string(),
+                account_id,
+                email: None,
+            },
+        }
+    }
+
+    pub fn for_account(account: &Account) -> UserBuilder {
+        UserBuilder::new(account.id().uuid())
+    }
+
+    pub fn id(mut self, id: Uuid) -> UserBuilder {
+        self.dto.id = id;
+        self
+    }
+
+    pub fn username(mut self, username: impl Into<String>) -> UserBuilder {
+        self.dto.username = username.into();
+        self
+    }
+
+    pub fn password(mut self, password: &str) -> UserBuilder {
+        self.dto.password = password.into();
+        self
+    }
+
+    pub fn email(mut self, email: impl Into<String>) -> UserBuilder {
+        self.dto.email = Some(email.into());
+        self
+    }
+
+    pub fn roles(mut self, roles: impl Into<String>) -> UserBuilder {
+        self.dto.roles = roles.into();
+        self
+    }
+
+    pub fn super_user(self) -> UserBuilder {
+        self.roles(SUPER_USER_ROLE)
+    }
+
+    /// The user with its password hashed. Nothing is validated, so tests can
+    /// build users the use cases would reject.
+    pub fn build(self) -> Result<User, PasswordHashError> {
+        Ok(User {
+            password: hash_password(&self.dto.password)?,
+            ..user_from_dto(self.dto)
+        })
+    }
+
+    pub async fn insert(self, repo: &dyn UserRepo) -> Result<User, CreateSuperUserError> {
+        let user = self.build()?;
+        repo.insert_user(user.clone()).await?;
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use crate::models::users::{CreateAccountError, CreateSuperUserError};
+    use crate::password::verify_password;
+    use crate::repo::memory::{MemoryAccountRepo, MemoryUserRepo};
+    use crate::repo::UserRepo;
+    use crate::secret::Secret;
+
+    use super::{AccountBuilder, UserBuilder, DEFAULT_PASSWORD};
+
+    #[test]
+    pub fn test_builders_insert_into_repos() {
+        let accounts = MemoryAccountRepo::default();
+        let users = MemoryUserRepo::default();
+        let first = block_on(AccountBuilder::new().name("Acme").insert(&accounts)).unwrap();
+        let second = block_on(AccountBuilder::new().name("ACME!").insert(&accounts)).unwrap();
+        assert_eq!("acme", first.slug());
+        assert_eq!("acme-2", second.slug());
+        assert!(matches!(
+            block_on(AccountBuilder::new().name("acme").insert(&accounts)),
+            Err(CreateAccountError::NameTaken)
+        ));
+
+        let admin = block_on(UserBuilder::for_account(&first).super_user().insert(&users)).unwrap();
+        assert!(admin.is_super_user());
+        assert_eq!(first.id().uuid(), admin.account_id());
+        assert!(verify_password(
+            &Secret::from(DEFAULT_PASSWORD),
+            admin.password()
+        ));
+        assert!(block_on(users.find_super_user()).unwrap().is_some());
+
+        let taken = UserBuilder::for_account(&second).username(admin.username());
+        assert!(matches!(
+            block_on(taken.insert(&users)),
+            Err(CreateSuperUserError::UsernameTaken)
+        ));
+    }
+}
@@ -0,0 +1,128 @@
+use std::str::FromStr;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Turns usernames and emails into the one spelling that is stored, looked up
+/// and checked for uniqueness, so `Admin`, `admin` and `ａｄｍｉｎ` are the
+/// same user.
+pub trait IdentifierNormalizer: Send + Sync {
+    fn username(&self, username: &str) -> String;
+
+    fn email(&self, email: &str) -> String;
+}
+
+/// Gmail ignores dots in the local part and everything after a `+`.
+const GMAIL_DOMAINS: [&str; 2] = ["gmail.com", "googlemail.com"];
+
+/// NFKC, then lower case, with surrounding whitespace trimmed. NFKC folds
+/// compatibility characters such as fullwidth letters and ligatures into
+/// their plain forms.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardNormalizer {
+    /// Also drop dots and `+tags` from the local part of gmail addresses and
+    /// store them under `gmail.com`.
+    pub gmail_style: bool,
+}
+
+fn fold(s: &str) -> String {
+    s.trim().nfkc().collect::<String>().to_lowercase()
+}
+
+impl IdentifierNormalizer for StandardNormalizer {
+    fn username(&self, username: &str) -> String {
+        fold(username)
+    }
+
+    /// Anything without an `@` is only folded, and left for validation to
+    /// reject.
+    fn email(&self, email: &str) -> String {
+        let email = fold(email);
+        let (local, domain) = match email.rsplit_once('@') {
+            Some(parts) => parts,
+            None => return email,
+        };
+        if !self.gmail_style || !GMAIL_DOMAINS.contains(&domain) {
+            return email;
+        }
+        let local = local.split('+').next().unwrap_or_default().replace('.', "");
+        format!("{}@{}", local, GMAIL_DOMAINS[0])
+    }
+}
+
+/// Two usernames with the same skeleton look alike, e.g. `admin`, `adrnin`
+/// and `аdmin` with a Cyrillic `а`. The UTS #39 skeleton of the folded
+/// username, so case and compatibility forms don't matter either.
+pub fn skeleton(username: &str) -> String {
+    unicode_security::skeleton(&fold(username)).collect()
+}
+
+/// What an account does when a new username looks like one it already has.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfusablePolicy {
+    /// Create the user anyway.
+    Allow,
+    /// Create the user and log a warning.
+    Flag,
+    #[default]
+    Reject,
+}
+
+impl ConfusablePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfusablePolicy::Allow => "allow",
+            ConfusablePolicy::Flag => "flag",
+            ConfusablePolicy::Reject => "reject",
+        }
+    }
+}
+
+impl FromStr for ConfusablePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(ConfusablePolicy::Allow),
+            "flag" => Ok(ConfusablePolicy::Flag),
+            "reject" => Ok(ConfusablePolicy::Reject),
+            _ => Err(format!("unknown confusable policy {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{skeleton, IdentifierNormalizer, StandardNormalizer};
+
+    #[test]
+    pub fn test_usernames_fold_case_and_compatibility_forms() {
+        let n = StandardNormalizer::default();
+        assert_eq!("admin", n.username(" Admin "));
+        assert_eq!("admin", n.username("ＡＤＭＩＮ"));
+        assert_eq!("office", n.username("oﬃce"));
+    }
+
+    #[test]
+    pub fn test_gmail_style_is_opt_in() {
+        let plain = StandardNormalizer::default();
+        let gmail = StandardNormalizer { gmail_style: true };
+        assert_eq!(
+            "j.doe+avtor@gmail.com",
+            plain.email("J.Doe+avtor@Gmail.com")
+        );
+        assert_eq!("jdoe@gmail.com", gmail.email("J.Doe+avtor@Gmail.com"));
+        assert_eq!("jdoe@gmail.com", gmail.email("jdoe@googlemail.com"));
+        assert_eq!("j.doe+x@example.com", gmail.email("J.Doe+x@example.com"));
+        assert_eq!("not-an-email", gmail.email("Not-An-Email"));
+    }
+
+    #[test]
+    pub fn test_lookalikes_share_a_skeleton() {
+        for lookalike in ["Admin", "adrnin", "\u{430}dmin", "\u{430}dm\u{456}n"] {
+            assert_eq!(skeleton("admin"), skeleton(lookalike), "{}", lookalike);
+        }
+        assert_eq!(skeleton("paypal"), skeleton("p\u{430}yp\u{430}1"));
+        assert_eq!(skeleton("paypal"), skeleton("paypa\u{a4f2}"));
+        assert_ne!(skeleton("admin"), skeleton("admins"));
+    }
+}
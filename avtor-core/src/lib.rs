@@ -1,4 +1,20 @@
+// Lets the code `#[derive(Entity)]` generates for `::avtor_core` resolve in
+// this crate too.
+extern crate self as avtor_core;
+
 pub mod common;
+pub mod config;
+pub mod db;
+pub mod email;
+pub mod error;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixtures;
+pub mod identifier;
+pub mod migrations;
 pub mod models;
+pub mod password;
 pub mod postgres_common;
 pub mod repo;
+pub mod secret;
+pub mod seed;
+pub mod signed_url;
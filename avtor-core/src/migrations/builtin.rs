@@ -0,0 +1,327 @@
+use super::runner::MigrationDef;
+
+pub const CREATE_MIGRATIONS_TABLE: &str = "
+create table if not exists migrations (
+  id uuid not null primary key,
+  name varchar(255) not null,
+  seq_order int not null,
+  up text not null,
+  down text not null,
+  applied_on timestamp default current_timestamp,
+  checksum varchar(64)
+);
+
+alter table migrations add column if not exists checksum varchar(64);";
+
+const MIGRATION_01_UP: &str = "
+create table if not exists accounts (
+  id uuid not null primary key,
+  name varchar(255),
+  created_on timestamp default current_timestamp
+);
+
+create table if not exists users (
+  id uuid not null primary key,
+  username varchar(255) not null,
+  password varchar(255) not null,
+  roles text not null,
+  account_id uuid not null references accounts(id),
+  created_on timestamp default current_timestamp
+);";
+
+// The first avtor-cli stored migration_01 before checksums existed, with
+// other spelling than MIGRATION_01_UP; see `LEGACY_UPS`.
+const LEGACY_MIGRATION_01_UP: &str = "
+create table if not exists accounts (
+  id uuid not null primary key,
+  name varchar(255),
+  created_on timestamp default current_timestamp
+);
+
+CREATE table if not exists users (
+  id uuid not null primary key,
+  username varchar(255) not null,
+  password varchar(255) not null,
+  roles text not null,
+  account_id uuid not null references accounts(id),
+  created_on timestamp default current_timestamp
+);";
+
+/// `up` texts, by migration name, that older releases stored for builtin
+/// migrations and that differ from the builtin `up` by more than whitespace.
+/// A migration applied before checksums existed is only adopted when its
+/// stored `up` is the builtin one or one of these.
+pub const LEGACY_UPS: &[(&str, &str)] = &[("migration_01", LEGACY_MIGRATION_01_UP)];
+
+const MIGRATION_01_DOWN: &str = "
+drop table users;
+drop table accounts;";
+
+// At most one row may match the predicate, so two concurrent super user
+// creations can't both pass the existence check and insert.
+const MIGRATION_02_UP: &str = "
+create unique index if not exists users_single_super_user on users ((true))
+  where roles like '%super_user%';";
+
+const MIGRATION_02_DOWN: &str = "
+drop index if exists users_single_super_user;";
+
+// slug stays nullable so binaries from before this migration can still insert
+// accounts; every account written since has one.
+const MIGRATION_03_UP: &str = "
+alter table accounts add column if not exists slug varchar(255);
+
+update accounts
+  set slug = trim(both '-' from regexp_replace(lower(coalesce(name, '')), '[^a-z0-9]+', '-', 'g'))
+    || '-' || substr(id::text, 1, 8)
+  where slug is null;
+
+create unique index if not exists accounts_slug_key on accounts (slug);
+create unique index if not exists accounts_name_key on accounts (lower(name));";
+
+const MIGRATION_03_DOWN: &str = "
+-- allow_destructive: true
+drop index if exists accounts_name_key;
+drop index if exists accounts_slug_key;
+alter table accounts drop column slug;";
+
+// Ids match SYSTEM_ACCOUNT_ID and SYSTEM_USER_ID in models::users. Existing
+// system_info rows, and writers that don't set an actor, get the system user.
+// The account's name and slug are ones slugify never produces, so they can't
+// collide with a tenant's under accounts_name_key or accounts_slug_key.
+const MIGRATION_04_UP: &str = "
+insert into accounts (id, name, slug)
+  values ('00000000-0000-0000-0000-000000000001', '__avtor_system', '__avtor_system')
+  on conflict (id) do nothing;
+
+insert into users (id, username, password, roles, account_id)
+  values ('00000000-0000-0000-0000-000000000002', 'system', '!', 'system',
+    '00000000-0000-0000-0000-000000000001')
+  on conflict (id) do nothing;
+
+alter table system_info add column if not exists actor_id uuid not null
+  default '00000000-0000-0000-0000-000000000002' references users(id);";
+
+const MIGRATION_04_DOWN: &str = "
+-- allow_destructive: true
+alter table system_info drop column actor_id;
+delete from users where id = '00000000-0000-0000-0000-000000000002';
+delete from accounts where id = '00000000-0000-0000-0000-000000000001';";
+
+const MIGRATION_05_UP: &str = "
+create unique index if not exists users_username_key on users (lower(username));";
+
+const MIGRATION_05_DOWN: &str = "
+drop index if exists users_username_key;";
+
+// Entities mark created_on and updated_on `#[entity(read_only)]` and never write
+// either column: created_on has a default and the trigger below keeps
+// updated_on current, whichever statement changes the row.
+const MIGRATION_06_UP: &str = "
+alter table accounts add column if not exists updated_on timestamp default current_timestamp;
+alter table users add column if not exists updated_on timestamp default current_timestamp;
+
+create or replace function avtor_set_updated_on() returns trigger as $$
+begin
+  new.updated_on = current_timestamp;
+  return new;
+end;
+$$ language plpgsql;
+
+drop trigger if exists accounts_set_updated_on on accounts;
+create trigger accounts_set_updated_on before update on accounts
+  for each row execute procedure avtor_set_updated_on();
+
+drop trigger if exists users_set_updated_on on users;
+create trigger users_set_updated_on before update on users
+  for each row execute procedure avtor_set_updated_on();";
+
+const MIGRATION_06_DOWN: &str = "
+-- allow_destructive: true
+drop trigger if exists users_set_updated_on on users;
+drop trigger if exists accounts_set_updated_on on accounts;
+drop function if exists avtor_set_updated_on();
+alter table users drop column updated_on;
+alter table accounts drop column updated_on;";
+
+// New usernames are stored normalized (see `identifier`) and looked up
+// exactly, so older ones are folded the same way: trimmed, NFKC, lower case.
+// `normalize()` needs a UTF8 database, elsewhere only case is folded. Two
+// usernames that fold alike break users_username_key and fail the migration,
+// leaving one to be renamed by hand. The original spelling is overwritten, so
+// this can't be rolled back.
+const MIGRATION_07_UP: &str = "
+-- allow_destructive: true
+do $$
+begin
+  if current_setting('server_encoding') = 'UTF8' then
+    update users set username = lower(normalize(btrim(username, E' \\t\\r\\n'), NFKC))
+      where username <> lower(normalize(btrim(username, E' \\t\\r\\n'), NFKC));
+  else
+    update users set username = lower(btrim(username, E' \\t\\r\\n'))
+      where username <> lower(btrim(username, E' \\t\\r\\n'));
+  end if;
+end
+$$;";
+
+const MIGRATION_07_DOWN: &str = "
+-- irreversible: true
+-- the original spellings of usernames are gone";
+
+// See `ConfusablePolicy`; the names match `ConfusablePolicy::as_str`.
+const MIGRATION_08_UP: &str = "
+alter table accounts add column if not exists confusable_policy text not null default 'reject'
+  check (confusable_policy in ('allow', 'flag', 'reject'));";
+
+const MIGRATION_08_DOWN: &str = "
+-- allow_destructive: true
+alter table accounts drop column confusable_policy;";
+
+// Emails are optional, so the index only has to keep two users of one
+// account from sharing one; nulls never conflict.
+const MIGRATION_09_UP: &str = "
+alter table users add column if not exists email varchar(255);
+
+create unique index if not exists users_account_email_key on users (account_id, email);";
+
+const MIGRATION_09_DOWN: &str = "
+-- allow_destructive: true
+drop index if exists users_account_email_key;
+alter table users drop column email;";
+
+// status holds the stored `InvitationStatus` names; expiry is worked out from
+// expires_at when read, so there is no 'expired'.
+const MIGRATION_10_UP: &str = "
+create table if not exists invitations (
+  id uuid not null primary key,
+  email varchar(255) not null,
+  account_id uuid not null references accounts(id),
+  roles text not null,
+  token_hash varchar(64) not null,
+  status varchar(16) not null default 'pending'
+    check (status in ('pending', 'accepted', 'revoked')),
+  expires_at timestamp not null,
+  created_on timestamp default current_timestamp
+);
+
+create index if not exists invitations_account_id on invitations (account_id);";
+
+const MIGRATION_10_DOWN: &str = "
+-- allow_destructive: true
+drop table invitations;";
+
+const MIGRATION_11_UP: &str = "
+create table if not exists email_templates (
+  id uuid not null primary key,
+  account_id uuid not null references accounts(id),
+  kind varchar(32) not null check (kind in ('invitation', 'password_reset')),
+  subject text not null,
+  body text not null,
+  created_on timestamp default current_timestamp,
+  unique (account_id, kind)
+);";
+
+const MIGRATION_11_DOWN: &str = "
+-- allow_destructive: true
+drop table email_templates;";
+
+/// The schema avtor-core itself needs, in application order.
+pub fn builtin_migrations() -> Vec<MigrationDef> {
+    vec![
+        MigrationDef {
+            seq_order: 1,
+            name: "migration_01".to_string(),
+            up: MIGRATION_01_UP.to_string(),
+            down: MIGRATION_01_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 2,
+            name: "migration_02_single_super_user".to_string(),
+            up: MIGRATION_02_UP.to_string(),
+            down: MIGRATION_02_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 3,
+            name: "migration_03_account_slugs".to_string(),
+            up: MIGRATION_03_UP.to_string(),
+            down: MIGRATION_03_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 4,
+            name: "migration_04_system_principals".to_string(),
+            up: MIGRATION_04_UP.to_string(),
+            down: MIGRATION_04_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 5,
+            name: "migration_05_unique_usernames".to_string(),
+            up: MIGRATION_05_UP.to_string(),
+            down: MIGRATION_05_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 6,
+            name: "migration_06_updated_on".to_string(),
+            up: MIGRATION_06_UP.to_string(),
+            down: MIGRATION_06_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 7,
+            name: "migration_07_normalized_usernames".to_string(),
+            up: MIGRATION_07_UP.to_string(),
+            down: MIGRATION_07_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 8,
+            name: "migration_08_confusable_policy".to_string(),
+            up: MIGRATION_08_UP.to_string(),
+            down: MIGRATION_08_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 9,
+            name: "migration_09_user_emails".to_string(),
+            up: MIGRATION_09_UP.to_string(),
+            down: MIGRATION_09_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 10,
+            name: "migration_10_invitations".to_string(),
+            up: MIGRATION_10_UP.to_string(),
+            down: MIGRATION_10_DOWN.to_string(),
+        },
+        MigrationDef {
+            seq_order: 11,
+            name: "migration_11_email_templates".to_string(),
+            up: MIGRATION_11_UP.to_string(),
+            down: MIGRATION_11_DOWN.to_string(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builtin_migrations, MIGRATION_04_UP};
+    use crate::migrations::lint::check_migration;
+    use crate::models::users::{
+        LOCKED_PASSWORD, SYSTEM_ACCOUNT_ID, SYSTEM_ACCOUNT_NAME, SYSTEM_ROLE, SYSTEM_USER_ID,
+    };
+
+    #[test]
+    pub fn test_builtin_migrations_pass_lint() {
+        for m in builtin_migrations() {
+            assert!(check_migration(&m.name, &m.up).is_ok(), "{}", m.name);
+        }
+    }
+
+    #[test]
+    pub fn test_system_principals_match_constants() {
+        for expected in [
+            format!("'{}'", SYSTEM_ACCOUNT_ID),
+            format!("'{}', '{}'", SYSTEM_ACCOUNT_NAME, SYSTEM_ACCOUNT_NAME),
+            format!("'{}'", SYSTEM_USER_ID),
+            format!("'{}'", LOCKED_PASSWORD),
+            format!("'{}'", SYSTEM_ROLE),
+        ] {
+            assert!(MIGRATION_04_UP.contains(&expected), "{}", expected);
+        }
+    }
+}
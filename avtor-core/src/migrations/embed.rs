@@ -0,0 +1,200 @@
+use std::path::{Path, PathBuf};
+
+/// Name of the file `write_embedded_migrations` writes and `embed_migrations!`
+/// includes from `OUT_DIR`.
+pub const EMBEDDED_MIGRATIONS_FILE: &str = "avtor_migrations.rs";
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmbedError {
+    #[error("could not read migrations from {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("{0} is not named <seq>_<name>.up.sql or <seq>_<name>.down.sql")]
+    BadFileName(String),
+
+    #[error("migration {0} has no {1} file")]
+    Missing(String, &'static str),
+
+    #[error("more than one migration has seq_order {0}")]
+    DuplicateSeqOrder(i32),
+}
+
+/// The up and down files of one migration in a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationFiles {
+    pub seq_order: i32,
+    pub name: String,
+    pub up: PathBuf,
+    pub down: PathBuf,
+}
+
+/// `003_add_email.up.sql` is `(3, "add_email", true)`.
+fn parse_file_name(file_name: &str) -> Option<(i32, String, bool)> {
+    let (stem, up) = match file_name.strip_suffix(".up.sql") {
+        Some(stem) => (stem, true),
+        None => (file_name.strip_suffix(".down.sql")?, false),
+    };
+    let (seq, name) = stem.split_once('_')?;
+    Some((seq.parse().ok()?, name.to_string(), up))
+}
+
+/// Pairs the `<seq>_<name>.up.sql` and `.down.sql` files in `dir`, ordered by
+/// seq. Other files are rejected rather than ignored so a typo can't drop a
+/// migration.
+pub fn collect_migration_files(dir: &Path) -> Result<Vec<MigrationFiles>, EmbedError> {
+    let io_error = |source| EmbedError::Io {
+        path: dir.display().to_string(),
+        source,
+    };
+    let mut ups: Vec<(i32, String, PathBuf)> = vec![];
+    let mut downs: Vec<(i32, String, PathBuf)> = vec![];
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_string();
+        match parse_file_name(&file_name) {
+            Some((seq, name, true)) => ups.push((seq, name, path)),
+            Some((seq, name, false)) => downs.push((seq, name, path)),
+            None => return Err(EmbedError::BadFileName(file_name)),
+        }
+    }
+    ups.sort();
+    let mut files: Vec<MigrationFiles> = vec![];
+    for (seq_order, name, up) in ups {
+        if files.last().map(|f| f.seq_order) == Some(seq_order) {
+            return Err(EmbedError::DuplicateSeqOrder(seq_order));
+        }
+        let down = match downs.iter().position(|d| d.0 == seq_order && d.1 == name) {
+            Some(i) => downs.swap_remove(i).2,
+            None => return Err(EmbedError::Missing(name, "down")),
+        };
+        files.push(MigrationFiles {
+            seq_order,
+            name,
+            up,
+            down,
+        });
+    }
+    match downs.into_iter().next() {
+        Some((_, name, _)) => Err(EmbedError::Missing(name, "up")),
+        None => Ok(files),
+    }
+}
+
+/// For a downstream `build.rs`: writes an expression building the
+/// `Vec<MigrationDef>` for the SQL files in `dir` to `out_file`, with every
+/// file pulled in by `include_str!` so the binary doesn't need them at run
+/// time. Include it with `embed_migrations!()`.
+pub fn write_embedded_migrations(dir: &Path, out_file: &Path) -> Result<(), EmbedError> {
+    let files = collect_migration_files(dir)?;
+    let mut code = String::from("vec![\n");
+    for f in &files {
+        code.push_str(&format!(
+            "    ::avtor_core::migrations::MigrationDef {{\n        seq_order: {},\n        name: {:?}.to_string(),\n        up: include_str!({:?}).to_string(),\n        down: include_str!({:?}).to_string(),\n    }},\n",
+            f.seq_order,
+            f.name,
+            absolute(&f.up),
+            absolute(&f.down),
+        ));
+    }
+    code.push(']');
+    std::fs::write(out_file, code).map_err(|source| EmbedError::Io {
+        path: out_file.display().to_string(),
+        source,
+    })
+}
+
+/// `include_str!` resolves relative paths against the including file, which
+/// lives in `OUT_DIR`, so the generated code only uses absolute ones.
+fn absolute(path: &Path) -> String {
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// The migrations `write_embedded_migrations` generated in this crate's
+/// build script, as a `Vec<MigrationDef>` for `Runner::new`:
+///
+/// ```ignore
+/// // build.rs
+/// let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap())
+///     .join(avtor_core::migrations::embed::EMBEDDED_MIGRATIONS_FILE);
+/// let dir = std::path::Path::new("migrations");
+/// avtor_core::migrations::embed::write_embedded_migrations(dir, &out).unwrap();
+/// println!("cargo:rerun-if-changed=migrations");
+///
+/// // main.rs
+/// let runner = Runner::new(avtor_core::embed_migrations!());
+/// ```
+#[macro_export]
+macro_rules! embed_migrations {
+    () => {
+        include!(concat!(env!("OUT_DIR"), "/avtor_migrations.rs"))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{collect_migration_files, write_embedded_migrations, EmbedError};
+
+    fn dir_with(files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("avtor-embed-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        for f in files {
+            std::fs::write(dir.join(f), format!("-- {}", f)).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    pub fn test_collects_pairs_in_order() {
+        let dir = dir_with(&[
+            "010_add_email.up.sql",
+            "010_add_email.down.sql",
+            "002_widgets.up.sql",
+            "002_widgets.down.sql",
+        ]);
+        let files = collect_migration_files(&dir).unwrap();
+        assert_eq!(
+            vec![2, 10],
+            files.iter().map(|f| f.seq_order).collect::<Vec<i32>>()
+        );
+        assert_eq!("add_email", files[1].name);
+
+        let out = dir.join("generated.rs");
+        write_embedded_migrations(&dir, &out).unwrap();
+        let code = std::fs::read_to_string(&out).unwrap();
+        assert!(
+            code.contains("002_widgets.up.sql\").to_string()"),
+            "{}",
+            code
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_rejects_unpaired_and_unknown_files() {
+        let dir = dir_with(&["001_a.up.sql"]);
+        assert!(matches!(
+            collect_migration_files(&dir),
+            Err(EmbedError::Missing(_, "down"))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let dir = dir_with(&["001_a.up.sql", "001_a.down.sql", "notes.txt"]);
+        assert!(matches!(
+            collect_migration_files(&dir),
+            Err(EmbedError::BadFileName(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
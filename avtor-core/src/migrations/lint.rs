@@ -0,0 +1,194 @@
+use std::fmt::{self, Display};
+
+/// A migration containing this marker (usually in a `--` comment) opts in to
+/// statements that break code still running against the previous schema.
+pub const ALLOW_DESTRUCTIVE_MARKER: &str = "allow_destructive: true";
+
+/// A `down` containing this marker can't undo its migration, so `rollback`
+/// refuses to run it.
+pub const IRREVERSIBLE_MARKER: &str = "irreversible: true";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintRule {
+    DropTable,
+    DropColumn,
+    RenameTableOrColumn,
+    AlterColumnType,
+    NotNullWithoutDefault,
+}
+
+impl Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            LintRule::DropTable => "drops a table",
+            LintRule::DropColumn => "drops a column",
+            LintRule::RenameTableOrColumn => "renames a table or column",
+            LintRule::AlterColumnType => "changes a column type",
+            LintRule::NotNullWithoutDefault => "adds a not null constraint without a default",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintViolation {
+    pub rule: LintRule,
+    pub statement: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("migration {name} is not backwards compatible ({}); add `-- {}` to apply it anyway", describe(.violations), ALLOW_DESTRUCTIVE_MARKER)]
+pub struct MigrationLintError {
+    pub name: String,
+    pub violations: Vec<LintViolation>,
+}
+
+fn describe(violations: &[LintViolation]) -> String {
+    violations
+        .iter()
+        .map(|v| format!("{}: {}", v.rule, v.statement))
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+fn strip_comments(sql: &str) -> String {
+    sql.lines()
+        .map(|l| match l.find("--") {
+            Some(i) => &l[..i],
+            None => l,
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn normalize(statement: &str) -> String {
+    statement
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Number of statements in `sql`, ignoring comments and empty statements.
+pub fn statement_count(sql: &str) -> usize {
+    strip_comments(sql)
+        .split(';')
+        .filter(|s| !s.trim().is_empty())
+        .count()
+}
+
+fn statement_rules(statement: &str) -> Vec<LintRule> {
+    let mut rules = vec![];
+    if statement.starts_with("drop table") {
+        rules.push(LintRule::DropTable);
+    }
+    if statement.starts_with("alter table") {
+        let drops_column = statement.contains(" drop column ")
+            || (statement.contains(" drop ")
+                && !statement.contains(" drop constraint ")
+                && !statement.contains(" drop default")
+                && !statement.contains(" drop not null"));
+        if drops_column {
+            rules.push(LintRule::DropColumn);
+        }
+        if statement.contains(" rename ") {
+            rules.push(LintRule::RenameTableOrColumn);
+        }
+        if statement.contains(" type ") {
+            rules.push(LintRule::AlterColumnType);
+        }
+        let adds_not_null = statement.contains(" add ") && statement.contains(" not null");
+        if statement.contains(" set not null")
+            || (adds_not_null && !statement.contains(" default "))
+        {
+            rules.push(LintRule::NotNullWithoutDefault);
+        }
+    }
+    rules
+}
+
+/// Flags statements in `sql` that would break readers or writers still on the
+/// previous schema during a rolling deploy.
+pub fn lint_migration(sql: &str) -> Vec<LintViolation> {
+    strip_comments(sql)
+        .split(';')
+        .map(normalize)
+        .filter(|s| !s.is_empty())
+        .flat_map(|statement| {
+            statement_rules(&statement)
+                .into_iter()
+                .map(move |rule| LintViolation {
+                    rule,
+                    statement: statement.clone(),
+                })
+                .collect::<Vec<LintViolation>>()
+        })
+        .collect()
+}
+
+pub fn check_migration(name: &str, sql: &str) -> Result<(), MigrationLintError> {
+    if sql.contains(ALLOW_DESTRUCTIVE_MARKER) {
+        return Ok(());
+    }
+    let violations = lint_migration(sql);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(MigrationLintError {
+            name: name.to_string(),
+            violations,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_migration, lint_migration, statement_count, LintRule};
+
+    fn rules(sql: &str) -> Vec<LintRule> {
+        lint_migration(sql).into_iter().map(|v| v.rule).collect()
+    }
+
+    #[test]
+    pub fn test_additive_migration_passes() {
+        let sql = "
+        create table if not exists accounts (id uuid not null primary key);
+        alter table users add column email varchar(255) not null default '';
+        alter table users add column nickname varchar(255);";
+        assert!(rules(sql).is_empty());
+        assert_eq!(3, statement_count(&format!("{}\n-- done;\n", sql)));
+    }
+
+    #[test]
+    pub fn test_destructive_statements_are_flagged() {
+        assert_eq!(vec![LintRule::DropTable], rules("drop table users;"));
+        assert_eq!(
+            vec![LintRule::DropColumn],
+            rules("ALTER TABLE users\n  DROP COLUMN roles;")
+        );
+        assert_eq!(
+            vec![LintRule::AlterColumnType],
+            rules("alter table users alter column roles type jsonb")
+        );
+        assert_eq!(
+            vec![LintRule::NotNullWithoutDefault],
+            rules("alter table users add column email text not null")
+        );
+        assert_eq!(
+            vec![LintRule::NotNullWithoutDefault],
+            rules("alter table users alter column email set not null")
+        );
+        assert_eq!(
+            vec![LintRule::RenameTableOrColumn],
+            rules("alter table users rename column roles to role_names")
+        );
+    }
+
+    #[test]
+    pub fn test_marker_allows_destructive_migration() {
+        let sql = "drop table users;";
+        assert!(check_migration("m", sql).is_err());
+        let marked = "-- allow_destructive: true\ndrop table users;";
+        assert!(check_migration("m", marked).is_ok());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod builtin;
+pub mod embed;
+pub mod lint;
+pub mod runner;
+
+pub use runner::{
+    MigrationDef, MigrationError, MigrationProgress, Runner, DEFAULT_RUN_LOCK_WAIT,
+    MIGRATION_LOCK_KEY,
+};
@@ -0,0 +1,723 @@
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use tokio_postgres::{Client, Transaction};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::error::DbError;
+use crate::models::{
+    migration_runs::{
+        ensure_migration_runs_table, insert_migration_run, MigrationRun, DIRECTION_DOWN,
+        DIRECTION_UP,
+    },
+    migrations::{
+        create, delete_migration, ensure_schema_compatible, find_all, find_one, Migration,
+        MigrationCriteria, SchemaVersionError,
+    },
+    system_info::{
+        current_version_info, ensure_system_info_table, insert_system_info, AVTOR_VERSION,
+        EVENT_BASELINE, EVENT_MIGRATION, EVENT_ROLLBACK,
+    },
+};
+
+use super::{
+    builtin::{builtin_migrations, CREATE_MIGRATIONS_TABLE, LEGACY_UPS},
+    lint::{check_migration, statement_count, MigrationLintError, IRREVERSIBLE_MARKER},
+};
+
+#[derive(Debug, Clone)]
+pub struct MigrationDef {
+    pub seq_order: i32,
+    pub name: String,
+    pub up: String,
+    pub down: String,
+}
+
+impl MigrationDef {
+    /// Hex SHA-256 of `up`, stored when the migration is applied.
+    pub fn checksum(&self) -> String {
+        checksum(&self.up)
+    }
+
+    /// Whether `up`, stored without a checksum, is this migration's: the
+    /// same but for whitespace, or a text listed in `LEGACY_UPS`.
+    fn is_legacy_up(&self, up: &str) -> bool {
+        let up = collapse_whitespace(up);
+        up == collapse_whitespace(&self.up)
+            || LEGACY_UPS
+                .iter()
+                .any(|(name, legacy)| *name == self.name && up == collapse_whitespace(legacy))
+    }
+}
+
+/// `sql` with every run of whitespace made a single space.
+fn collapse_whitespace(sql: &str) -> String {
+    sql.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub enum MigrationProgress {
+    AlreadyApplied {
+        seq_order: i32,
+        name: String,
+    },
+    Applying {
+        seq_order: i32,
+        name: String,
+    },
+    Applied {
+        seq_order: i32,
+        name: String,
+    },
+    RollingBack {
+        seq_order: i32,
+        name: String,
+    },
+    RolledBack {
+        seq_order: i32,
+        name: String,
+    },
+    /// Recorded as applied by `baseline` without running its `up`.
+    Baselined {
+        seq_order: i32,
+        name: String,
+    },
+    /// Applied with different `up` SQL than this runner has; only reported
+    /// with `allow_drift`, otherwise `run` fails.
+    Drifted {
+        seq_order: i32,
+        name: String,
+    },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Lint(#[from] MigrationLintError),
+
+    #[error("more than one migration has {field} {value}")]
+    Duplicate { field: &'static str, value: String },
+
+    #[error("applied migrations were edited after they ran: {}", .0.join(", "))]
+    Drift(Vec<String>),
+
+    #[error("no migration has seq_order {0}")]
+    UnknownSeqOrder(i32),
+
+    #[error("another migration is in progress")]
+    InProgress,
+
+    #[error("migrations can't be rolled back: {}", .0.join(", "))]
+    Irreversible(Vec<String>),
+
+    #[error(transparent)]
+    SchemaVersion(#[from] SchemaVersionError),
+
+    #[error("Migration {name} failed: {message}")]
+    Failed { name: String, message: String },
+
+    #[error("Repo Error: {0}")]
+    RepoError(String),
+}
+
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MigrationError::RepoError(e.to_string())
+    }
+}
+
+impl From<DbError> for MigrationError {
+    fn from(e: DbError) -> Self {
+        MigrationError::RepoError(e.to_string())
+    }
+}
+
+/// Key of the session level advisory lock held for a whole `run` or
+/// `rollback`, so two deploys migrating at once can't interleave.
+pub const MIGRATION_LOCK_KEY: i64 = 0x6176_746f_725f_6d69;
+
+/// How long `run` and `rollback` wait for another runner by default.
+pub const DEFAULT_RUN_LOCK_WAIT: Duration = Duration::from_secs(60);
+
+const RUN_LOCK_POLL: Duration = Duration::from_millis(250);
+
+pub type ProgressCallback = Box<dyn Fn(&MigrationProgress) + Send + Sync>;
+
+/// Applies an ordered list of migrations, each in its own transaction, skipping
+/// the ones already recorded in the migrations table.
+pub struct Runner {
+    migrations: Vec<MigrationDef>,
+    on_progress: Option<ProgressCallback>,
+    lock_timeout: Option<Duration>,
+    run_lock_wait: Duration,
+    allow_drift: bool,
+}
+
+impl Runner {
+    pub fn new(mut migrations: Vec<MigrationDef>) -> Runner {
+        migrations.sort_by_key(|m| m.seq_order);
+        Runner {
+            migrations,
+            on_progress: None,
+            lock_timeout: None,
+            run_lock_wait: DEFAULT_RUN_LOCK_WAIT,
+            allow_drift: false,
+        }
+    }
+
+    pub fn builtin() -> Runner {
+        Runner::new(builtin_migrations())
+    }
+
+    pub fn on_progress(mut self, f: impl Fn(&MigrationProgress) + Send + Sync + 'static) -> Runner {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+
+    /// Bounds how long each migration waits on table locks held by other
+    /// sessions before failing.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Runner {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Bounds how long `run` and `rollback` wait for another runner holding
+    /// `MIGRATION_LOCK_KEY` before failing with `MigrationError::InProgress`.
+    pub fn run_lock_wait(mut self, wait: Duration) -> Runner {
+        self.run_lock_wait = wait;
+        self
+    }
+
+    /// Apply pending migrations even when applied ones were edited, reporting
+    /// them as `MigrationProgress::Drifted` instead of failing.
+    pub fn allow_drift(mut self, allow: bool) -> Runner {
+        self.allow_drift = allow;
+        self
+    }
+
+    pub fn migrations(&self) -> &[MigrationDef] {
+        &self.migrations
+    }
+
+    /// Highest `seq_order` known to this runner, i.e. the schema version the
+    /// running code expects.
+    pub fn expected_schema_version(&self) -> i32 {
+        self.migrations
+            .iter()
+            .map(|m| m.seq_order)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Lints every migration and rejects two with the same `seq_order` or
+    /// name, since only the first of them would ever be applied.
+    pub fn validate(&self) -> Result<(), MigrationError> {
+        for (i, migration) in self.migrations.iter().enumerate() {
+            check_migration(&migration.name, &migration.up)?;
+            let earlier = &self.migrations[..i];
+            if earlier.iter().any(|m| m.seq_order == migration.seq_order) {
+                return Err(MigrationError::Duplicate {
+                    field: "seq_order",
+                    value: migration.seq_order.to_string(),
+                });
+            }
+            if earlier.iter().any(|m| m.name == migration.name) {
+                return Err(MigrationError::Duplicate {
+                    field: "name",
+                    value: migration.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn report(&self, progress: MigrationProgress) {
+        if let Some(f) = &self.on_progress {
+            f(&progress)
+        }
+    }
+
+    async fn set_lock_timeout<'a>(&self, trans: &Transaction<'a>) -> Result<(), MigrationError> {
+        if let Some(timeout) = self.lock_timeout {
+            let stmt = format!("set local lock_timeout = '{}ms'", timeout.as_millis());
+            trans.batch_execute(&stmt).await?;
+        }
+        Ok(())
+    }
+
+    /// Polls `pg_try_advisory_lock` rather than blocking in
+    /// `pg_advisory_lock` so the wait can be bounded.
+    async fn acquire_run_lock(&self, client: &Client) -> Result<(), MigrationError> {
+        let deadline = Instant::now() + self.run_lock_wait;
+        loop {
+            let row = client
+                .query_one("select pg_try_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])
+                .await?;
+            if row.get::<_, bool>(0) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(MigrationError::InProgress);
+            }
+            tokio::time::sleep(RUN_LOCK_POLL).await;
+        }
+    }
+
+    async fn release_run_lock(client: &Client) -> Result<(), MigrationError> {
+        client
+            .execute("select pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])
+            .await?;
+        Ok(())
+    }
+
+    async fn is_applied<'a>(
+        trans: &Transaction<'a>,
+        migration: &MigrationDef,
+    ) -> Result<bool, MigrationError> {
+        let crit = vec![MigrationCriteria::SeqOrderEq(migration.seq_order)];
+        Ok(find_one(trans)(crit).await?.is_some())
+    }
+
+    async fn ensure_tables(&self, client: &mut Client) -> Result<(), MigrationError> {
+        let trans = client.transaction().await?;
+        self.set_lock_timeout(&trans).await?;
+        trans.batch_execute(CREATE_MIGRATIONS_TABLE).await?;
+        ensure_system_info_table(&trans).await?;
+        ensure_migration_runs_table(&trans).await?;
+        trans.commit().await?;
+        Ok(())
+    }
+
+    /// Migrations not yet recorded in the database, without applying them.
+    pub async fn pending(&self, client: &mut Client) -> Result<Vec<MigrationDef>, MigrationError> {
+        self.ensure_tables(client).await?;
+        let trans = client.transaction().await?;
+        let mut pending = vec![];
+        for migration in self.migrations.iter() {
+            if !Runner::is_applied(&trans, migration).await? {
+                pending.push(migration.clone());
+            }
+        }
+        trans.commit().await?;
+        Ok(pending)
+    }
+
+    /// Applied migrations whose `up` differs from this runner's. One applied
+    /// before checksums were stored is compared by its stored `up`, see
+    /// `MigrationDef::is_legacy_up`; `run` adopts the ones that match.
+    pub async fn drifted(&self, client: &mut Client) -> Result<Vec<MigrationDef>, MigrationError> {
+        self.ensure_tables(client).await?;
+        let applied = find_all(&*client)().await?;
+        Ok(self
+            .migrations
+            .iter()
+            .filter(|m| {
+                applied
+                    .iter()
+                    .find(|a| a.seq_order == m.seq_order)
+                    .is_some_and(|a| match &a.checksum {
+                        Some(stored) => *stored != m.checksum(),
+                        None => !m.is_legacy_up(&a.up),
+                    })
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// Stores this runner's checksum on applied migrations recorded before
+    /// checksums were whose stored `up` is this runner's, all in one
+    /// transaction. The others keep no checksum and stay drifted.
+    async fn adopt_legacy_checksums(&self, client: &mut Client) -> Result<(), MigrationError> {
+        self.ensure_tables(client).await?;
+        let trans = client.transaction().await?;
+        let applied = find_all(&trans)().await?;
+        let stmt = "update migrations set checksum = $1 where id = $2 and checksum is null";
+        for migration in self.migrations.iter() {
+            let legacy = applied.iter().find(|a| {
+                a.seq_order == migration.seq_order
+                    && a.checksum.is_none()
+                    && migration.is_legacy_up(&a.up)
+            });
+            if let Some(legacy) = legacy {
+                trans
+                    .execute(stmt, &[&migration.checksum(), &legacy.id])
+                    .await?;
+            }
+        }
+        trans.commit().await?;
+        Ok(())
+    }
+
+    /// Applies `migration` unless it already is, returning whether it was.
+    async fn apply(
+        &self,
+        client: &mut Client,
+        migration: &MigrationDef,
+    ) -> Result<bool, MigrationError> {
+        let trans = client.transaction().await?;
+        self.set_lock_timeout(&trans).await?;
+        if Runner::is_applied(&trans, migration).await? {
+            self.report(MigrationProgress::AlreadyApplied {
+                seq_order: migration.seq_order,
+                name: migration.name.clone(),
+            });
+            trans.commit().await?;
+            return Ok(false);
+        }
+        self.report(MigrationProgress::Applying {
+            seq_order: migration.seq_order,
+            name: migration.name.clone(),
+        });
+        let started_on = Utc::now().naive_utc();
+        let started = Instant::now();
+        let result = trans.batch_execute(&migration.up).await;
+        let run = new_run(
+            &migration.name,
+            migration.seq_order,
+            DIRECTION_UP,
+            &migration.up,
+            started_on,
+            started.elapsed(),
+            result.as_ref().err(),
+        );
+        if let Err(e) = result {
+            trans.rollback().await?;
+            record_failed_run(client, run).await?;
+            return Err(MigrationError::Failed {
+                name: migration.name.clone(),
+                message: e.to_string(),
+            });
+        }
+        create(&trans)(Migration {
+            id: Uuid::new_v4(),
+            name: migration.name.clone(),
+            seq_order: migration.seq_order,
+            up: migration.up.clone(),
+            down: migration.down.clone(),
+            applied_on: Utc::now().naive_utc(),
+            checksum: Some(migration.checksum()),
+        })
+        .await?;
+        insert_system_info(&trans)(current_version_info(EVENT_MIGRATION, &migration.name)).await?;
+        insert_migration_run(&trans)(run).await?;
+        trans.commit().await?;
+        self.report(MigrationProgress::Applied {
+            seq_order: migration.seq_order,
+            name: migration.name.clone(),
+        });
+        Ok(true)
+    }
+
+    /// Applies all pending migrations in order, holding the migration lock,
+    /// and returns the ones applied. Refuses a database with migrations
+    /// newer than this runner's.
+    pub async fn run(&self, client: &mut Client) -> Result<Vec<MigrationDef>, MigrationError> {
+        self.validate()?;
+        self.acquire_run_lock(client).await?;
+        let result = self.run_locked(client).await;
+        let released = Runner::release_run_lock(client).await;
+        let applied = result?;
+        released?;
+        Ok(applied)
+    }
+
+    async fn run_locked(&self, client: &mut Client) -> Result<Vec<MigrationDef>, MigrationError> {
+        ensure_schema_compatible(client, self.expected_schema_version()).await?;
+        self.adopt_legacy_checksums(client).await?;
+        let drifted = self.drifted(client).await?;
+        if !drifted.is_empty() && !self.allow_drift {
+            return Err(MigrationError::Drift(
+                drifted.into_iter().map(|m| m.name).collect(),
+            ));
+        }
+        for migration in drifted {
+            tracing::warn!(name = %migration.name, "applied migration was edited");
+            self.report(MigrationProgress::Drifted {
+                seq_order: migration.seq_order,
+                name: migration.name,
+            });
+        }
+        let mut applied = vec![];
+        for migration in self.migrations.iter() {
+            let span = migration_span(&migration.name, migration.seq_order, DIRECTION_UP);
+            if self.apply(client, migration).instrument(span).await? {
+                applied.push(migration.clone());
+            }
+        }
+        Ok(applied)
+    }
+
+    async fn roll_back(
+        &self,
+        client: &mut Client,
+        migration: &Migration,
+    ) -> Result<(), MigrationError> {
+        let trans = client.transaction().await?;
+        self.set_lock_timeout(&trans).await?;
+        self.report(MigrationProgress::RollingBack {
+            seq_order: migration.seq_order,
+            name: migration.name.clone(),
+        });
+        let started_on = Utc::now().naive_utc();
+        let started = Instant::now();
+        let result = trans.batch_execute(&migration.down).await;
+        let run = new_run(
+            &migration.name,
+            migration.seq_order,
+            DIRECTION_DOWN,
+            &migration.down,
+            started_on,
+            started.elapsed(),
+            result.as_ref().err(),
+        );
+        if let Err(e) = result {
+            trans.rollback().await?;
+            record_failed_run(client, run).await?;
+            return Err(MigrationError::Failed {
+                name: migration.name.clone(),
+                message: e.to_string(),
+            });
+        }
+        delete_migration(&trans)(migration.id).await?;
+        insert_system_info(&trans)(current_version_info(EVENT_ROLLBACK, &migration.name)).await?;
+        insert_migration_run(&trans)(run).await?;
+        trans.commit().await?;
+        self.report(MigrationProgress::RolledBack {
+            seq_order: migration.seq_order,
+            name: migration.name.clone(),
+        });
+        Ok(())
+    }
+
+    /// Rolls back every applied migration after `to`, or only the latest one
+    /// when `to` is `None`, newest first and each in its own transaction.
+    /// Runs the `down` stored with the applied migration rather than the one
+    /// in this runner, so unlike `run` it also works against a schema newer
+    /// than this runner's, e.g. to undo a release before redeploying the old
+    /// one. Nothing is rolled back when any of them is marked irreversible.
+    pub async fn rollback(
+        &self,
+        client: &mut Client,
+        to: Option<i32>,
+    ) -> Result<Vec<Migration>, MigrationError> {
+        self.acquire_run_lock(client).await?;
+        let result = self.rollback_locked(client, to).await;
+        let released = Runner::release_run_lock(client).await;
+        let rolled_back = result?;
+        released?;
+        Ok(rolled_back)
+    }
+
+    /// Whether the stored `down` of `applied`, or that of this runner's
+    /// migration of the same name, is marked irreversible. The runner's
+    /// counts too, as rows applied before a migration was marked keep the
+    /// `down` of the time.
+    fn is_irreversible(&self, applied: &Migration) -> bool {
+        applied.down.contains(IRREVERSIBLE_MARKER)
+            || self
+                .migrations
+                .iter()
+                .any(|m| m.name == applied.name && m.down.contains(IRREVERSIBLE_MARKER))
+    }
+
+    async fn rollback_locked(
+        &self,
+        client: &mut Client,
+        to: Option<i32>,
+    ) -> Result<Vec<Migration>, MigrationError> {
+        self.ensure_tables(client).await?;
+        let mut applied = find_all(&*client)().await?;
+        applied.reverse();
+        let targets: Vec<Migration> = match to {
+            Some(to) => applied.into_iter().filter(|m| m.seq_order > to).collect(),
+            None => applied.into_iter().take(1).collect(),
+        };
+        let irreversible: Vec<String> = targets
+            .iter()
+            .filter(|m| self.is_irreversible(m))
+            .map(|m| m.name.clone())
+            .collect();
+        if !irreversible.is_empty() {
+            return Err(MigrationError::Irreversible(irreversible));
+        }
+        for migration in targets.iter() {
+            let span = migration_span(&migration.name, migration.seq_order, DIRECTION_DOWN);
+            self.roll_back(client, migration).instrument(span).await?;
+        }
+        Ok(targets)
+    }
+
+    /// Records every migration up to and including `to` as applied without
+    /// running it, for adopting a database whose schema was created by hand
+    /// or by another tool. All of them are recorded in one transaction, with
+    /// the checksum of this runner's `up` so later drift checks work.
+    pub async fn baseline(
+        &self,
+        client: &mut Client,
+        to: i32,
+    ) -> Result<Vec<MigrationDef>, MigrationError> {
+        self.validate()?;
+        if !self.migrations.iter().any(|m| m.seq_order == to) {
+            return Err(MigrationError::UnknownSeqOrder(to));
+        }
+        self.acquire_run_lock(client).await?;
+        let result = self.baseline_locked(client, to).await;
+        let released = Runner::release_run_lock(client).await;
+        let baselined = result?;
+        released?;
+        Ok(baselined)
+    }
+
+    async fn baseline_locked(
+        &self,
+        client: &mut Client,
+        to: i32,
+    ) -> Result<Vec<MigrationDef>, MigrationError> {
+        self.ensure_tables(client).await?;
+        let trans = client.transaction().await?;
+        self.set_lock_timeout(&trans).await?;
+        let mut baselined = vec![];
+        for migration in self.migrations.iter().filter(|m| m.seq_order <= to) {
+            if Runner::is_applied(&trans, migration).await? {
+                self.report(MigrationProgress::AlreadyApplied {
+                    seq_order: migration.seq_order,
+                    name: migration.name.clone(),
+                });
+                continue;
+            }
+            create(&trans)(Migration {
+                id: Uuid::new_v4(),
+                name: migration.name.clone(),
+                seq_order: migration.seq_order,
+                up: migration.up.clone(),
+                down: migration.down.clone(),
+                applied_on: Utc::now().naive_utc(),
+                checksum: Some(migration.checksum()),
+            })
+            .await?;
+            insert_system_info(&trans)(current_version_info(EVENT_BASELINE, &migration.name))
+                .await?;
+            baselined.push(migration.clone());
+        }
+        trans.commit().await?;
+        for migration in baselined.iter() {
+            self.report(MigrationProgress::Baselined {
+                seq_order: migration.seq_order,
+                name: migration.name.clone(),
+            });
+        }
+        Ok(baselined)
+    }
+}
+
+fn migration_span(name: &str, seq_order: i32, direction: &'static str) -> tracing::Span {
+    tracing::info_span!("migration", name, seq_order, direction)
+}
+
+/// The `migration_runs` row for one attempt, also emitted as a tracing event.
+fn new_run(
+    name: &str,
+    seq_order: i32,
+    direction: &str,
+    sql: &str,
+    started_on: NaiveDateTime,
+    elapsed: Duration,
+    error: Option<&tokio_postgres::Error>,
+) -> MigrationRun {
+    let statements = statement_count(sql) as i32;
+    let duration_ms = elapsed.as_millis() as i64;
+    match error {
+        Some(e) => tracing::warn!(statements, duration_ms, error = %e, "migration failed"),
+        None => tracing::info!(statements, duration_ms, "migration finished"),
+    }
+    MigrationRun {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        seq_order,
+        direction: direction.to_string(),
+        statements,
+        duration_ms,
+        error: error.map(|e| e.to_string()),
+        avtor_version: AVTOR_VERSION.to_string(),
+        started_on,
+    }
+}
+
+/// Records a failed attempt in a transaction of its own, as the one the
+/// migration ran in has been rolled back.
+async fn record_failed_run(client: &mut Client, run: MigrationRun) -> Result<(), MigrationError> {
+    let trans = client.transaction().await?;
+    insert_migration_run(&trans)(run).await?;
+    trans.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, MigrationDef, MigrationError, Runner};
+
+    fn def(seq_order: i32, name: &str) -> MigrationDef {
+        MigrationDef {
+            seq_order,
+            name: name.to_string(),
+            up: "create table if not exists t (id int);".to_string(),
+            down: "drop table t;".to_string(),
+        }
+    }
+
+    #[test]
+    pub fn test_legacy_up_matches_only_reformatted_or_listed_text() {
+        let m = def(1, "a");
+        assert!(m.is_legacy_up("create table  if not exists t\n  (id int);\n"));
+        assert!(!m.is_legacy_up("create table if not exists t (id bigint);"));
+        let builtin = Runner::builtin();
+        let first = &builtin.migrations()[0];
+        let legacy = first.up.replacen(
+            "create table if not exists users",
+            "CREATE table if not exists users",
+            1,
+        );
+        assert!(first.is_legacy_up(&legacy));
+        assert!(!first.is_legacy_up(&legacy.replace("CREATE table", "CREATE TABLE")));
+    }
+
+    #[test]
+    pub fn test_validate_rejects_duplicates() {
+        assert!(Runner::builtin().validate().is_ok());
+        let r = Runner::new(vec![def(2, "b"), def(1, "a"), def(2, "c")]).validate();
+        assert!(matches!(
+            r,
+            Err(MigrationError::Duplicate {
+                field: "seq_order",
+                ..
+            })
+        ));
+        let r = Runner::new(vec![def(1, "a"), def(2, "a")]).validate();
+        assert!(matches!(
+            r,
+            Err(MigrationError::Duplicate { field: "name", .. })
+        ));
+    }
+
+    #[test]
+    pub fn test_checksum_is_hex_sha256_of_up() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            checksum("")
+        );
+        let mut edited = def(1, "a");
+        edited
+            .up
+            .push_str("\ncreate index if not exists t_id on t (id);");
+        assert_ne!(def(1, "a").checksum(), edited.checksum());
+    }
+}
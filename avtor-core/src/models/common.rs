@@ -1,5 +1,3 @@
-
-
 pub fn field_names_without_id(fields: &[&str]) -> Vec<String> {
     fields
         .iter()
@@ -7,3 +5,25 @@ pub fn field_names_without_id(fields: &[&str]) -> Vec<String> {
         .filter(|x| x != &"id".to_string())
         .collect()
 }
+
+/// Every statement the postgres_common helpers generate for an entity, one
+/// per line, so snapshot tests catch changes to the macro or SQL builders.
+#[cfg(test)]
+pub fn entity_sql(table: &str, field_names: &[&str], fields: &[String]) -> String {
+    use crate::postgres_common::core::{
+        create_insert_many_sql, create_insert_sql, create_update_sql, create_upsert_sql,
+        generate_select,
+    };
+
+    let table = table.to_string();
+    let id = "id".to_string();
+    let (select, _) = generate_select(&table, field_names, &vec![], &[], None, None).unwrap();
+    [
+        create_insert_sql(&table, &id, fields),
+        create_insert_many_sql(&table, &id, fields, 2),
+        create_update_sql(&table, &id, fields).unwrap(),
+        create_upsert_sql(&table, &id, fields),
+        select,
+    ]
+    .join("\n")
+}
@@ -0,0 +1,247 @@
+use chrono::NaiveDateTime;
+use futures::future::BoxFuture;
+use tokio_postgres::Transaction;
+use uuid::Uuid;
+
+use crate::email::{EmailKind, EmailTemplate};
+use crate::error::DbError;
+use crate::models::users::{AccountId, CreateAccountError};
+use crate::postgres_common::core::Entity;
+use crate::repo::{AccountRepo, EmailTemplateRepo};
+
+/// An account's own template for one `EmailKind`, replacing the built in
+/// one. At most one per account and kind.
+#[derive(Debug, Clone, Entity)]
+#[entity(table = "email_templates")]
+pub struct AccountEmailTemplate {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    /// An `EmailKind`.
+    pub kind: String,
+    pub subject: String,
+    pub body: String,
+    #[entity(read_only)]
+    pub created_on: Option<NaiveDateTime>,
+}
+
+impl AccountEmailTemplate {
+    pub fn template(&self) -> EmailTemplate {
+        EmailTemplate {
+            subject: self.subject.clone(),
+            body: self.body.clone(),
+        }
+    }
+}
+
+pub fn find_email_template<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(AccountId, EmailKind) -> BoxFuture<'a, Result<Option<AccountEmailTemplate>, DbError>>
+{
+    move |account_id: AccountId, kind: EmailKind| {
+        Box::pin(async move {
+            let crit = [
+                AccountEmailTemplateCriteria::AccountIdEq(account_id.uuid()),
+                AccountEmailTemplateCriteria::KindEq(kind.as_str().to_string()),
+            ];
+            AccountEmailTemplate::find_where(client, &crit)
+                .await
+                .map(|templates| templates.into_iter().next())
+        })
+    }
+}
+
+pub fn insert_email_template<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(AccountEmailTemplate) -> BoxFuture<'a, Result<(), DbError>> {
+    move |template: AccountEmailTemplate| Box::pin(async move { template.insert(client).await })
+}
+
+pub fn update_email_template<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(&'a AccountEmailTemplate) -> BoxFuture<'a, Result<(), DbError>> {
+    move |template: &'a AccountEmailTemplate| Box::pin(async move { template.update(client).await })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmailTemplateError {
+    #[error("Account not found")]
+    AccountNotFound,
+
+    #[error("Email template invalid: {0}")]
+    Invalid(String),
+
+    #[error("Repo Error: {0}")]
+    RepoError(String),
+}
+
+impl From<DbError> for EmailTemplateError {
+    fn from(e: DbError) -> Self {
+        EmailTemplateError::RepoError(e.to_string())
+    }
+}
+
+impl From<CreateAccountError> for EmailTemplateError {
+    fn from(e: CreateAccountError) -> Self {
+        EmailTemplateError::RepoError(e.to_string())
+    }
+}
+
+/// Stores `template` as the account's `kind` template, replacing any
+/// earlier one.
+pub async fn set_email_template(
+    template_repo: &dyn EmailTemplateRepo,
+    account_repo: &dyn AccountRepo,
+    account_id: AccountId,
+    kind: EmailKind,
+    template: &EmailTemplate,
+) -> Result<(), EmailTemplateError> {
+    if template.subject.trim().is_empty() || template.subject.contains('\n') {
+        return Err(EmailTemplateError::Invalid(
+            "the subject must be a single non empty line".to_string(),
+        ));
+    }
+    if template.body.trim().is_empty() {
+        return Err(EmailTemplateError::Invalid("the body is empty".to_string()));
+    }
+    account_repo
+        .find_account_by_id(account_id)
+        .await?
+        .ok_or(EmailTemplateError::AccountNotFound)?;
+    match template_repo.find_email_template(account_id, kind).await? {
+        Some(stored) => {
+            let updated = AccountEmailTemplate {
+                subject: template.subject.clone(),
+                body: template.body.clone(),
+                ..stored
+            };
+            template_repo.update_email_template(&updated).await?;
+        }
+        None => {
+            template_repo
+                .insert_email_template(AccountEmailTemplate {
+                    id: Uuid::new_v4(),
+                    account_id: account_id.uuid(),
+                    kind: kind.as_str().to_string(),
+                    subject: template.subject.clone(),
+                    body: template.body.clone(),
+                    created_on: None,
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// The account's `kind` template, else `fallback`, else the built in one.
+pub async fn email_template_for(
+    template_repo: &dyn EmailTemplateRepo,
+    account_id: AccountId,
+    kind: EmailKind,
+    fallback: Option<EmailTemplate>,
+) -> Result<EmailTemplate, DbError> {
+    Ok(
+        match template_repo.find_email_template(account_id, kind).await? {
+            Some(stored) => stored.template(),
+            None => fallback.unwrap_or_else(|| EmailTemplate::default_for(kind)),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    use super::{email_template_for, set_email_template, AccountEmailTemplate, EmailTemplateError};
+    use crate::email::{EmailKind, EmailTemplate};
+    use crate::models::common::entity_sql;
+    use crate::models::users::{account_from_dto, AccountDto, AccountId};
+    use crate::repo::memory::{MemoryAccountRepo, MemoryEmailTemplateRepo};
+    use crate::repo::AccountRepo;
+
+    #[test]
+    pub fn test_email_template_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            AccountEmailTemplate::table_name(),
+            AccountEmailTemplate::field_names(),
+            &AccountEmailTemplate::write_field_names()
+        ));
+    }
+
+    #[test]
+    pub fn test_account_template_replaces_the_default() {
+        let (templates, accounts) = (
+            MemoryEmailTemplateRepo::default(),
+            MemoryAccountRepo::default(),
+        );
+        let account_id = Uuid::new_v4();
+        block_on(accounts.insert_account(account_from_dto(AccountDto {
+            id: account_id,
+            name: "acme".to_string(),
+        })))
+        .unwrap();
+        let account_id = AccountId::from(account_id);
+        let template_for = |fallback: Option<EmailTemplate>| {
+            block_on(email_template_for(
+                &templates,
+                account_id,
+                EmailKind::Invitation,
+                fallback,
+            ))
+            .unwrap()
+        };
+        let branded = |subject: &str| EmailTemplate {
+            subject: subject.to_string(),
+            body: "Join us: {{link}}\n".to_string(),
+        };
+        let set = |template: &EmailTemplate| {
+            block_on(set_email_template(
+                &templates,
+                &accounts,
+                account_id,
+                EmailKind::Invitation,
+                template,
+            ))
+        };
+
+        assert_eq!(
+            EmailTemplate::default_for(EmailKind::Invitation),
+            template_for(None)
+        );
+        assert_eq!(
+            branded("configured"),
+            template_for(Some(branded("configured")))
+        );
+        set(&branded("Welcome to Acme")).unwrap();
+        set(&branded("Welcome aboard")).unwrap();
+        assert_eq!(
+            branded("Welcome aboard"),
+            template_for(Some(branded("configured")))
+        );
+        assert_eq!(
+            EmailTemplate::default_for(EmailKind::PasswordReset),
+            block_on(email_template_for(
+                &templates,
+                account_id,
+                EmailKind::PasswordReset,
+                None,
+            ))
+            .unwrap()
+        );
+
+        assert!(matches!(
+            set(&branded("")),
+            Err(EmailTemplateError::Invalid(_))
+        ));
+        assert!(matches!(
+            block_on(set_email_template(
+                &templates,
+                &accounts,
+                AccountId::from(Uuid::new_v4()),
+                EmailKind::Invitation,
+                &branded("lost"),
+            )),
+            Err(EmailTemplateError::AccountNotFound)
+        ));
+    }
+}
@@ -1,15 +1,701 @@
+use std::{collections::BTreeMap, collections::HashMap, str::FromStr};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use futures::future::BoxFuture;
 use postgres_derive::FromSql;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio_postgres::Transaction;
 use uuid::Uuid;
+use validator::Validate;
+
+use crate::email::{EmailError, EmailSender, EmailTemplate};
+use crate::error::DbError;
+use crate::identifier::IdentifierNormalizer;
+use crate::models::users::{
+    create_user_with_repos, hash_map_from_validation_errors, roles_contain, AccountId,
+    CreateAccountError, CreateUserError, UserDto, SUPER_USER_ROLE, SYSTEM_ROLE,
+};
+use crate::password::breach::BreachChecker;
+use crate::postgres_common::core::Entity;
+use crate::repo::{AccountRepo, InvitationRepo, UserRepo};
+use crate::secret::Secret;
+use crate::signed_url::{sign_url, verify_url_with_leeway, SignedUrlError};
 
-use crate::postgres_common::core::{entity, QueryCondition};
+/// Path of the signed url an invitation token is; a web front end would
+/// serve its accept page here.
+pub const ACCEPT_PATH: &str = "/invitations/accept";
 
-#[derive(Debug, Clone, Copy, Deserialize, postgres_derive::ToSql, FromSql)]
+const INVITATION_CLAIM: &str = "invitation";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, postgres_derive::ToSql, FromSql)]
+#[postgres(transparent)]
 pub struct InvitationId(Uuid);
 
-entity! {
-    pub struct Invitation {
-        id: InvitationId,
-        email: String,
+impl InvitationId {
+    pub fn uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl From<Uuid> for InvitationId {
+    fn from(id: Uuid) -> Self {
+        InvitationId(id)
+    }
+}
+
+#[derive(Debug, Clone, Entity)]
+#[entity(table = "invitations")]
+pub struct Invitation {
+    pub id: InvitationId,
+    /// Normalized like user emails; the accepted user gets it.
+    pub email: String,
+    pub account_id: Uuid,
+    pub roles: String,
+    /// Hex sha256 of the token. The token itself is only handed to whoever
+    /// sent the invitation.
+    pub token_hash: String,
+    /// An `InvitationStatus`, never `expired`; see `Invitation::status_at`.
+    pub status: String,
+    pub expires_at: NaiveDateTime,
+    #[entity(read_only)]
+    pub created_on: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Revoked,
+    /// Pending past `expires_at`; worked out when read rather than stored.
+    Expired,
+}
+
+impl InvitationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvitationStatus::Pending => "pending",
+            InvitationStatus::Accepted => "accepted",
+            InvitationStatus::Revoked => "revoked",
+            InvitationStatus::Expired => "expired",
+        }
+    }
+}
+
+impl FromStr for InvitationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(InvitationStatus::Pending),
+            "accepted" => Ok(InvitationStatus::Accepted),
+            "revoked" => Ok(InvitationStatus::Revoked),
+            "expired" => Ok(InvitationStatus::Expired),
+            _ => Err(format!("unknown invitation status {}", s)),
+        }
+    }
+}
+
+impl Invitation {
+    pub fn id(&self) -> InvitationId {
+        self.id
+    }
+
+    /// The stored status, with pending invitations more than `leeway` past
+    /// their expiry reported as expired.
+    pub fn status_at(&self, now: DateTime<Utc>, leeway: Duration) -> InvitationStatus {
+        let status = self.status.parse().unwrap_or(InvitationStatus::Revoked);
+        if status == InvitationStatus::Pending && self.expires_at + leeway <= now.naive_utc() {
+            InvitationStatus::Expired
+        } else {
+            status
+        }
+    }
+
+    fn with_status(self, status: InvitationStatus) -> Invitation {
+        Invitation {
+            status: status.as_str().to_string(),
+            ..self
+        }
+    }
+}
+
+pub fn find_invitation_by_id<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(InvitationId) -> BoxFuture<'a, Result<Option<Invitation>, DbError>> {
+    move |id: InvitationId| Box::pin(async move { Invitation::find_by_id(client, &id).await })
+}
+
+/// Like `find_invitation_by_id`, but the row stays locked until the
+/// transaction ends, so a concurrent accept waits and then sees it accepted.
+pub fn lock_invitation_by_id<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(InvitationId) -> BoxFuture<'a, Result<Option<Invitation>, DbError>> {
+    move |id: InvitationId| {
+        Box::pin(async move {
+            let sql = format!(
+                "select {} from {} where id = $1 for update",
+                Invitation::field_names().join(", "),
+                Invitation::table_name()
+            );
+            let row = client.query_opt(sql.as_str(), &[&id]).await?;
+            Ok(row.map(Invitation::from_row).transpose()?)
+        })
+    }
+}
+
+pub fn find_invitations_by_account<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(AccountId) -> BoxFuture<'a, Result<Vec<Invitation>, DbError>> {
+    move |account_id: AccountId| {
+        Box::pin(async move {
+            Invitation::find_where(
+                client,
+                &[InvitationCriteria::AccountIdEq(account_id.uuid())],
+            )
+            .await
+        })
+    }
+}
+
+pub fn insert_invitation<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(Invitation) -> BoxFuture<'a, Result<(), DbError>> {
+    move |invitation: Invitation| Box::pin(async move { invitation.insert(client).await })
+}
+
+pub fn update_invitation<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(&'a Invitation) -> BoxFuture<'a, Result<(), DbError>> {
+    move |invitation: &'a Invitation| Box::pin(async move { invitation.update(client).await })
+}
+
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InvitationError {
+    #[error("Invitation invalid")]
+    Invalid(HashMap<String, String>),
+
+    #[error("Role {0} can't be given by invitation")]
+    ReservedRole(String),
+
+    #[error("Account not found")]
+    AccountNotFound,
+
+    #[error("{0} already has a pending invitation")]
+    AlreadyInvited(String),
+
+    #[error("Invitation not found")]
+    NotFound,
+
+    #[error("Invitation token is invalid")]
+    InvalidToken,
+
+    #[error("Invitation expired")]
+    Expired,
+
+    #[error("Invitation is {0}")]
+    NotPending(&'static str),
+
+    #[error(transparent)]
+    CreateUser(#[from] CreateUserError),
+
+    #[error("Repo Error: {0}")]
+    RepoError(String),
+}
+
+impl From<DbError> for InvitationError {
+    fn from(e: DbError) -> Self {
+        InvitationError::RepoError(e.to_string())
+    }
+}
+
+impl From<CreateAccountError> for InvitationError {
+    fn from(e: CreateAccountError) -> Self {
+        InvitationError::RepoError(e.to_string())
+    }
+}
+
+#[derive(Debug, Validate, Deserialize, Clone)]
+pub struct InvitationDto {
+    #[validate(email(message = "email_invalid"))]
+    pub email: String,
+    pub account_id: Uuid,
+    #[validate(length(min = 1, message = "roles_required"))]
+    pub roles: String,
+}
+
+/// A stored invitation and the token to send to the invitee.
+#[derive(Debug)]
+pub struct IssuedInvitation {
+    pub invitation: Invitation,
+    pub token: Secret<String>,
+    pub account_name: String,
+}
+
+/// Invites `dto.email` into an existing account with `dto.roles`. The token
+/// is a url signed with `key` that expires `ttl` after `now`; only its hash
+/// is stored. `leeway` is the clock skew allowed when deciding whether an
+/// earlier invitation is still pending.
+#[allow(clippy::too_many_arguments)]
+pub async fn invite_user(
+    invitation_repo: &dyn InvitationRepo,
+    account_repo: &dyn AccountRepo,
+    normalizer: &dyn IdentifierNormalizer,
+    key: &Secret<String>,
+    ttl: Duration,
+    now: DateTime<Utc>,
+    leeway: Duration,
+    dto: &InvitationDto,
+) -> Result<IssuedInvitation, InvitationError> {
+    let dto = InvitationDto {
+        email: normalizer.email(&dto.email),
+        ..dto.clone()
+    };
+    dto.validate()
+        .map_err(|e| InvitationError::Invalid(hash_map_from_validation_errors(e)))?;
+    if let Some(role) = [SUPER_USER_ROLE, SYSTEM_ROLE]
+        .into_iter()
+        .find(|r| roles_contain(&dto.roles, r))
+    {
+        return Err(InvitationError::ReservedRole(role.to_string()));
+    }
+    let account = account_repo
+        .find_account_by_id(AccountId::from(dto.account_id))
+        .await?
+        .ok_or(InvitationError::AccountNotFound)?;
+    if invitation_repo
+        .find_invitations_by_account(account.id())
+        .await?
+        .iter()
+        .any(|i| i.email == dto.email && i.status_at(now, leeway) == InvitationStatus::Pending)
+    {
+        return Err(InvitationError::AlreadyInvited(dto.email));
+    }
+
+    let id = InvitationId(Uuid::new_v4());
+    let expires = now + ttl;
+    let claims = BTreeMap::from([(INVITATION_CLAIM.to_string(), id.0.to_string())]);
+    let token = sign_url(
+        key.expose_secret().as_bytes(),
+        ACCEPT_PATH,
+        expires,
+        &claims,
+    )
+    .map_err(|_| InvitationError::InvalidToken)?;
+    let invitation = Invitation {
+        id,
+        email: dto.email,
+        account_id: dto.account_id,
+        roles: dto.roles,
+        token_hash: token_hash(&token),
+        status: InvitationStatus::Pending.as_str().to_string(),
+        expires_at: expires.naive_utc(),
+        created_on: None,
+    };
+    invitation_repo
+        .insert_invitation(invitation.clone())
+        .await?;
+    Ok(IssuedInvitation {
+        invitation,
+        token: Secret::new(token),
+        account_name: account.name,
+    })
+}
+
+/// Emails `issued` to the invitee. `link` is where the token is accepted,
+/// usually the token appended to the front end's base url.
+pub async fn send_invitation(
+    sender: &dyn EmailSender,
+    template: &EmailTemplate,
+    issued: &IssuedInvitation,
+    link: &str,
+) -> Result<(), EmailError> {
+    let invitation = &issued.invitation;
+    let vars = BTreeMap::from([
+        ("email", invitation.email.clone()),
+        ("account_name", issued.account_name.clone()),
+        ("roles", invitation.roles.clone()),
+        (
+            "expires_at",
+            invitation
+                .expires_at
+                .format("%Y-%m-%d %H:%M UTC")
+                .to_string(),
+        ),
+        ("link", link.to_string()),
+    ]);
+    sender
+        .send(&template.render(&invitation.email, &vars))
+        .await
+}
+
+/// The pending invitation `token` was issued for.
+async fn pending_invitation(
+    invitation_repo: &dyn InvitationRepo,
+    key: &Secret<String>,
+    now: DateTime<Utc>,
+    leeway: Duration,
+    token: &Secret<String>,
+) -> Result<Invitation, InvitationError> {
+    let signed = verify_url_with_leeway(
+        key.expose_secret().as_bytes(),
+        token.expose_secret(),
+        now,
+        leeway,
+    )
+    .map_err(|e| match e {
+        SignedUrlError::Expired(_) => InvitationError::Expired,
+        _ => InvitationError::InvalidToken,
+    })?;
+    if let Some(past_expiry) = signed.past_expiry {
+        tracing::warn!(
+            past_expiry_ms = past_expiry.num_milliseconds(),
+            "invitation token accepted within the clock skew leeway"
+        );
+    }
+    let id = signed
+        .claims
+        .get(INVITATION_CLAIM)
+        .and_then(|id| Uuid::from_str(id).ok())
+        .ok_or(InvitationError::InvalidToken)?;
+    let invitation = invitation_repo
+        .lock_invitation(InvitationId(id))
+        .await?
+        .ok_or(InvitationError::NotFound)?;
+    if invitation.token_hash != token_hash(token.expose_secret()) {
+        return Err(InvitationError::InvalidToken);
+    }
+    match invitation.status_at(now, leeway) {
+        InvitationStatus::Pending => Ok(invitation),
+        InvitationStatus::Expired => Err(InvitationError::Expired),
+        status => Err(InvitationError::NotPending(status.as_str())),
+    }
+}
+
+/// Creates the invited user with the invitation's account, roles and email
+/// and marks the invitation accepted. Run it with repos sharing one
+/// transaction so a failed update doesn't leave the user behind; the
+/// invitation stays locked until that transaction ends, so of two concurrent
+/// accepts of one token only the first creates a user. Returns the new
+/// user's id.
+#[allow(clippy::too_many_arguments)]
+pub async fn accept_invitation(
+    invitation_repo: &dyn InvitationRepo,
+    user_repo: &dyn UserRepo,
+    account_repo: &dyn AccountRepo,
+    normalizer: &dyn IdentifierNormalizer,
+    breach_checker: &dyn BreachChecker,
+    key: &Secret<String>,
+    now: DateTime<Utc>,
+    leeway: Duration,
+    token: &Secret<String>,
+    username: &str,
+    password: Secret<String>,
+) -> Result<Uuid, InvitationError> {
+    let invitation = pending_invitation(invitation_repo, key, now, leeway, token).await?;
+    let user_dto = UserDto {
+        id: Uuid::new_v4(),
+        username: username.to_string(),
+        password,
+        roles: invitation.roles.clone(),
+        account_id: invitation.account_id,
+        email: Some(invitation.email.clone()),
+    };
+    create_user_with_repos(
+        user_repo,
+        account_repo,
+        normalizer,
+        breach_checker,
+        &user_dto,
+    )
+    .await?;
+    invitation_repo
+        .update_invitation(&invitation.with_status(InvitationStatus::Accepted))
+        .await?;
+    Ok(user_dto.id)
+}
+
+/// Stops a pending invitation from being accepted.
+pub async fn revoke_invitation(
+    invitation_repo: &dyn InvitationRepo,
+    now: DateTime<Utc>,
+    leeway: Duration,
+    id: InvitationId,
+) -> Result<Invitation, InvitationError> {
+    let invitation = invitation_repo
+        .find_invitation_by_id(id)
+        .await?
+        .ok_or(InvitationError::NotFound)?;
+    match invitation.status_at(now, leeway) {
+        InvitationStatus::Pending => {}
+        status => return Err(InvitationError::NotPending(status.as_str())),
+    }
+    let revoked = invitation.with_status(InvitationStatus::Revoked);
+    invitation_repo.update_invitation(&revoked).await?;
+    Ok(revoked)
+}
+
+/// The account's invitations, only those with `status` at `now` when given.
+pub async fn find_invitations(
+    invitation_repo: &dyn InvitationRepo,
+    account_id: AccountId,
+    status: Option<InvitationStatus>,
+    now: DateTime<Utc>,
+    leeway: Duration,
+) -> Result<Vec<Invitation>, InvitationError> {
+    Ok(invitation_repo
+        .find_invitations_by_account(account_id)
+        .await?
+        .into_iter()
+        .filter(|i| status.is_none_or(|s| i.status_at(now, leeway) == s))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+    use chrono::{Duration, TimeZone, Utc};
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    use super::{
+        accept_invitation, find_invitations, invite_user, revoke_invitation, send_invitation,
+        Invitation, InvitationDto, InvitationError, InvitationStatus,
+    };
+    use crate::email::{Email, EmailError, EmailKind, EmailSender, EmailTemplate};
+    use crate::identifier::StandardNormalizer;
+    use crate::models::common::entity_sql;
+    use crate::models::users::{account_from_dto, AccountDto, AccountId, CreateUserError};
+    use crate::password::breach::NoBreachCheck;
+    use crate::repo::memory::{MemoryAccountRepo, MemoryInvitationRepo, MemoryUserRepo};
+    use crate::repo::{AccountRepo, UserRepo};
+    use crate::secret::Secret;
+    use crate::signed_url::DEFAULT_LEEWAY_SECONDS;
+
+    #[test]
+    pub fn test_invitation_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            Invitation::table_name(),
+            Invitation::field_names(),
+            &Invitation::write_field_names()
+        ));
+    }
+
+    struct Repos {
+        invitations: MemoryInvitationRepo,
+        users: MemoryUserRepo,
+        accounts: MemoryAccountRepo,
+        account_id: Uuid,
+    }
+
+    fn repos() -> Repos {
+        let accounts = MemoryAccountRepo::default();
+        let account_id = Uuid::new_v4();
+        block_on(accounts.insert_account(account_from_dto(AccountDto {
+            id: account_id,
+            name: "acme".to_string(),
+        })))
+        .unwrap();
+        Repos {
+            invitations: MemoryInvitationRepo::default(),
+            users: MemoryUserRepo::default(),
+            accounts,
+            account_id,
+        }
+    }
+
+    fn leeway() -> Duration {
+        Duration::seconds(DEFAULT_LEEWAY_SECONDS)
+    }
+
+    fn invite(repos: &Repos, email: &str) -> Result<super::IssuedInvitation, InvitationError> {
+        block_on(invite_user(
+            &repos.invitations,
+            &repos.accounts,
+            &StandardNormalizer::default(),
+            &Secret::from("key"),
+            Duration::days(7),
+            Utc::now(),
+            leeway(),
+            &InvitationDto {
+                email: email.to_string(),
+                account_id: repos.account_id,
+                roles: "user".to_string(),
+            },
+        ))
+    }
+
+    fn accept(
+        repos: &Repos,
+        token: &Secret<String>,
+        username: &str,
+    ) -> Result<Uuid, InvitationError> {
+        accept_at(repos, token, username, Utc::now())
+    }
+
+    fn accept_at(
+        repos: &Repos,
+        token: &Secret<String>,
+        username: &str,
+        now: chrono::DateTime<Utc>,
+    ) -> Result<Uuid, InvitationError> {
+        block_on(accept_invitation(
+            &repos.invitations,
+            &repos.users,
+            &repos.accounts,
+            &StandardNormalizer::default(),
+            &NoBreachCheck,
+            &Secret::from("key"),
+            now,
+            leeway(),
+            token,
+            username,
+            "!Q2w3e4r5t".into(),
+        ))
+    }
+
+    #[test]
+    pub fn test_accepting_creates_the_user_once() {
+        let repos = repos();
+        let issued = invite(&repos, "New.Hire@Example.com").unwrap();
+        assert_eq!("new.hire@example.com", issued.invitation.email);
+        assert!(matches!(
+            invite(&repos, "new.hire@example.com"),
+            Err(InvitationError::AlreadyInvited(_))
+        ));
+
+        accept(&repos, &issued.token, "newhire").unwrap();
+        let user = block_on(repos.users.find_user_by_username("newhire".to_string()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some("new.hire@example.com"), user.email());
+        assert_eq!("user", user.roles());
+        assert!(matches!(
+            accept(&repos, &issued.token, "again"),
+            Err(InvitationError::NotPending("accepted"))
+        ));
+
+        let forged = Secret::from(
+            issued
+                .token
+                .expose_secret()
+                .replace("&signature=", "&x=1&signature="),
+        );
+        assert!(matches!(
+            accept(&repos, &forged, "forged"),
+            Err(InvitationError::InvalidToken)
+        ));
+    }
+
+    #[test]
+    pub fn test_revoked_and_expired_invitations_are_refused() {
+        let repos = repos();
+        let revoked = invite(&repos, "revoked@example.com").unwrap();
+        let expiring = invite(&repos, "expiring@example.com").unwrap();
+        block_on(revoke_invitation(
+            &repos.invitations,
+            Utc::now(),
+            leeway(),
+            revoked.invitation.id(),
+        ))
+        .unwrap();
+        assert!(matches!(
+            accept(&repos, &revoked.token, "revoked"),
+            Err(InvitationError::NotPending("revoked"))
+        ));
+        let later = Utc::now() + Duration::days(8);
+        assert!(matches!(
+            accept_at(&repos, &expiring.token, "expiring", later),
+            Err(InvitationError::Expired)
+        ));
+        let just_expired =
+            Utc.from_utc_datetime(&expiring.invitation.expires_at) + Duration::seconds(5);
+        assert_eq!(
+            InvitationStatus::Pending,
+            expiring.invitation.status_at(just_expired, leeway())
+        );
+        assert_eq!(
+            InvitationStatus::Expired,
+            expiring
+                .invitation
+                .status_at(just_expired, Duration::zero())
+        );
+
+        let account_id = AccountId::from(repos.account_id);
+        let statuses = |status, now| {
+            block_on(find_invitations(
+                &repos.invitations,
+                account_id,
+                Some(status),
+                now,
+                leeway(),
+            ))
+            .unwrap()
+            .len()
+        };
+        assert_eq!(1, statuses(InvitationStatus::Pending, Utc::now()));
+        assert_eq!(1, statuses(InvitationStatus::Revoked, Utc::now()));
+        assert_eq!(1, statuses(InvitationStatus::Expired, later));
+        assert!(matches!(
+            invite(&repos, "expiring@example.com").map(|_| ()),
+            Err(InvitationError::AlreadyInvited(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_invitee_username_is_still_checked() {
+        let repos = repos();
+        let first = invite(&repos, "first@example.com").unwrap();
+        let second = invite(&repos, "second@example.com").unwrap();
+        accept(&repos, &first.token, "taken").unwrap();
+        assert!(matches!(
+            accept(&repos, &second.token, "Taken"),
+            Err(InvitationError::CreateUser(CreateUserError::UsernameTaken))
+        ));
+        assert!(matches!(
+            invite(&repos, "not-an-email"),
+            Err(InvitationError::Invalid(_))
+        ));
+    }
+
+    #[derive(Default)]
+    struct Outbox(Mutex<Vec<Email>>);
+
+    #[async_trait]
+    impl EmailSender for Outbox {
+        async fn send(&self, email: &Email) -> Result<(), EmailError> {
+            self.0.lock().unwrap().push(email.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_invitation_email_links_the_token() {
+        let repos = repos();
+        let issued = invite(&repos, "invitee@example.com").unwrap();
+        let link = format!("https://auth.example.com{}", issued.token.expose_secret());
+        let outbox = Outbox::default();
+        block_on(send_invitation(
+            &outbox,
+            &EmailTemplate::default_for(EmailKind::Invitation),
+            &issued,
+            &link,
+        ))
+        .unwrap();
+        let sent = outbox.0.into_inner().unwrap();
+        assert_eq!(1, sent.len());
+        assert_eq!("invitee@example.com", sent[0].to);
+        assert_eq!("You're invited to acme", sent[0].subject);
+        assert!(sent[0].body.contains(&link), "{}", sent[0].body);
+        assert!(!sent[0].body.contains("{{"), "{}", sent[0].body);
     }
 }
@@ -0,0 +1,94 @@
+use chrono::NaiveDateTime;
+use futures::future::BoxFuture;
+use tokio_postgres::{Client, Transaction};
+use uuid::Uuid;
+
+use crate::error::DbError;
+use crate::postgres_common::core::{select_all, Entity, Sort};
+
+pub const DIRECTION_UP: &str = "up";
+pub const DIRECTION_DOWN: &str = "down";
+
+/// One row per attempt to apply or roll back a migration, kept even when the
+/// attempt failed, unlike the `migrations` row.
+pub const CREATE_MIGRATION_RUNS_TABLE: &str = "
+create table if not exists migration_runs (
+  id uuid not null primary key,
+  name varchar(255) not null,
+  seq_order int not null,
+  direction varchar(8) not null,
+  statements int not null,
+  duration_ms bigint not null,
+  error text,
+  avtor_version varchar(64) not null,
+  started_on timestamp not null
+);";
+
+#[derive(Debug, Clone, Entity)]
+#[entity(table = "migration_runs")]
+pub struct MigrationRun {
+    pub id: Uuid,
+    pub name: String,
+    pub seq_order: i32,
+    pub direction: String,
+    pub statements: i32,
+    pub duration_ms: i64,
+    /// `None` when the run succeeded.
+    pub error: Option<String>,
+    pub avtor_version: String,
+    pub started_on: NaiveDateTime,
+}
+
+pub fn migration_runs_table() -> String {
+    MigrationRun::table_name().to_string()
+}
+
+pub async fn ensure_migration_runs_table<'a>(client: &Transaction<'a>) -> Result<(), DbError> {
+    client.batch_execute(CREATE_MIGRATION_RUNS_TABLE).await?;
+    Ok(())
+}
+
+pub fn insert_migration_run<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(MigrationRun) -> BoxFuture<'a, Result<(), DbError>> {
+    move |run: MigrationRun| Box::pin(async move { run.insert(client).await })
+}
+
+/// Every recorded run of the migration called `name`, latest first.
+pub fn find_runs_by_name<'a>(
+    client: &'a Client,
+) -> impl FnOnce(String) -> BoxFuture<'a, Result<Vec<MigrationRun>, DbError>> {
+    move |name: String| {
+        Box::pin(async move {
+            let crit = MigrationRunCriteria::NameEq(name);
+            let cond = vec![crit.to_query_condition()];
+            let sorts: Vec<Sort> = vec![MigrationRunSort::StartedOnDesc.to_sort()];
+            select_all(
+                client,
+                &migration_runs_table(),
+                MigrationRun::field_names(),
+                &cond,
+                &sorts,
+                None,
+                None,
+                MigrationRun::from_row,
+            )
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migration_runs_table, MigrationRun};
+    use crate::models::common::entity_sql;
+
+    #[test]
+    pub fn test_migration_run_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            &migration_runs_table(),
+            MigrationRun::field_names(),
+            &MigrationRun::write_field_names()
+        ));
+    }
+}
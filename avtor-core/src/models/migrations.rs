@@ -3,15 +3,14 @@ use std::str::FromStr;
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use futures::{future::BoxFuture, stream::BoxStream, Future, Stream, StreamExt};
 use postgres_derive::{FromSql, ToSql};
-use tokio_postgres::{Client, Row, Transaction};
+use tokio_postgres::{Client, GenericClient, Transaction};
 use uuid::Uuid;
 
+use crate::error::DbError;
 use crate::postgres_common::core::{
-    entity, insert, select, select_all, select_raw, QueryCondition,
+    delete_by_id, select_all, select_all_stream, Entity, QueryCondition, Sort,
 };
 
-use super::common::field_names_without_id;
-
 pub async fn blah() {
     let secret = "blah";
     let client = stripe::Client::new(secret);
@@ -55,21 +54,25 @@ impl Default for MyTimeStamp {
 }
 
 #[derive(Debug, Default, ToSql, FromSql)]
+#[postgres(transparent)]
 pub struct MigrationId(pub Uuid);
 
-entity! {
-  pub struct Migration {
-    pub id : Uuid,
+#[derive(Debug, Clone, Entity)]
+#[entity(table = "migrations")]
+pub struct Migration {
+    pub id: Uuid,
     pub name: String,
     pub seq_order: i32,
     pub up: String,
     pub down: String,
     pub applied_on: NaiveDateTime,
-  }
+    /// Hex SHA-256 of `up`; `None` for migrations applied before checksums
+    /// were recorded.
+    pub checksum: Option<String>,
 }
 
 fn migration_table() -> String {
-    "migrations".to_string()
+    Migration::table_name().to_string()
 }
 
 pub fn default_migration() -> Migration {
@@ -80,66 +83,82 @@ pub fn default_migration() -> Migration {
         up: "".to_string(),
         down: "".to_string(),
         applied_on: NaiveDateTime::from_timestamp(0, 0),
-    }
-}
-
-fn map_migration_with_err(res: Result<Row, tokio_postgres::Error>) -> Migration {
-    match res {
-        Ok(r) => Migration::from_row(r),
-        Err(_) => default_migration(),
+        checksum: None,
     }
 }
 
 pub fn find_one<'a>(
     client: &'a Transaction,
-) -> impl FnOnce(Vec<MigrationCriteria>) -> BoxFuture<'a, Result<Option<Migration>, anyhow::Error>>
-{
+) -> impl FnOnce(Vec<MigrationCriteria>) -> BoxFuture<'a, Result<Option<Migration>, DbError>> {
     move |crit: Vec<MigrationCriteria>| {
         Box::pin(async move {
-            let cond = crit.iter().map(|x| x.to_query_condition()).collect();
-            select(client, &migration_table(), &cond, Migration::from_row).await
+            Migration::find_where(client, &crit)
+                .await
+                .map(|migrations| migrations.into_iter().next())
         })
     }
 }
 
-pub fn find_all<'a>(
-    client: &'a Client,
-) -> impl FnOnce() -> BoxFuture<'a, Result<Vec<Migration>, anyhow::Error>> {
+pub fn find_all<'a, C: GenericClient + Sync>(
+    client: &'a C,
+) -> impl FnOnce() -> BoxFuture<'a, Result<Vec<Migration>, DbError>> {
     move || {
         Box::pin(async move {
             let cond: Vec<QueryCondition> = vec![];
-            select_all(client, &migration_table(), &cond, Migration::from_row).await
+            let sorts: Vec<Sort> = vec![MigrationSort::SeqOrderAsc.to_sort()];
+            select_all(
+                client,
+                &migration_table(),
+                Migration::field_names(),
+                &cond,
+                &sorts,
+                None,
+                None,
+                Migration::from_row,
+            )
+            .await
         })
     }
 }
 
 pub fn create<'a>(
     client: &'a Transaction,
-) -> impl FnOnce(Migration) -> BoxFuture<'a, Result<(), anyhow::Error>> {
-    move |migration: Migration| {
+) -> impl FnOnce(Migration) -> BoxFuture<'a, Result<(), DbError>> {
+    move |migration: Migration| Box::pin(async move { migration.insert(client).await })
+}
+
+pub fn delete_migration<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(Uuid) -> BoxFuture<'a, Result<(), DbError>> {
+    move |id: Uuid| {
         Box::pin(async move {
-            let fields = field_names_without_id(Migration::field_names());
-            insert(
-                client,
-                &migration_table(),
-                &"id".to_string(),
-                fields.as_slice(),
-                &migration.id,
-                &migration.to_params_x(),
-            )
-            .await
+            delete_by_id(client, &migration_table(), &"id".to_string(), &id)
+                .await
+                .map(|_| ())
         })
     }
 }
 
+pub type MigrationStream<'a> = BoxStream<'a, Result<Migration, DbError>>;
+
 pub fn find_all_stream<'a>(
     client: &'a Client,
-) -> impl FnOnce(Vec<MigrationCriteria>) -> BoxFuture<'a, Result<BoxStream<'a, Migration>, anyhow::Error>>
-{
+) -> impl FnOnce(Vec<MigrationCriteria>) -> BoxFuture<'a, Result<MigrationStream<'a>, DbError>> {
     move |crit| {
         Box::pin(async move {
             let conds: Vec<QueryCondition> = crit.iter().map(|c| c.to_query_condition()).collect();
-            let r = select_raw(client, &migration_table(), &conds, map_migration_with_err).await;
+            let sorts = vec![MigrationSort::SeqOrderAsc.to_sort()];
+            let r = select_all_stream(
+                client,
+                &migration_table(),
+                Migration::field_names(),
+                &conds,
+                &sorts,
+                None,
+                None,
+                Migration::from_row,
+            )
+            .await;
             match r {
                 Ok(m) => Ok(m.boxed()),
                 Err(e) => Err(e),
@@ -148,8 +167,54 @@ pub fn find_all_stream<'a>(
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum SchemaVersionError {
+    #[error("database schema version {database} is newer than version {binary} supported by this binary")]
+    DatabaseNewer { database: i32, binary: i32 },
+
+    #[error("Repo Error: {0}")]
+    RepoError(String),
+}
+
+/// Highest applied `seq_order`, or `None` when the migrations table is missing
+/// or empty.
+pub async fn find_latest_seq_order(client: &Client) -> Result<Option<i32>, DbError> {
+    let table_exists: bool = client
+        .query_one("select to_regclass($1) is not null", &[&migration_table()])
+        .await?
+        .get(0);
+    if !table_exists {
+        return Ok(None);
+    }
+    let stmt = format!("select max(seq_order) from {}", migration_table());
+    let row = client.query_one(stmt.as_str(), &[]).await?;
+    Ok(row.get(0))
+}
+
+pub fn check_schema_version(expected: i32, applied: Option<i32>) -> Result<(), SchemaVersionError> {
+    match applied {
+        Some(database) if database > expected => Err(SchemaVersionError::DatabaseNewer {
+            database,
+            binary: expected,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Refuses to proceed when the database has migrations this binary doesn't know
+/// about, so an older replica can't write data in an outdated shape.
+pub async fn ensure_schema_compatible(
+    client: &Client,
+    expected: i32,
+) -> Result<(), SchemaVersionError> {
+    let applied = find_latest_seq_order(client)
+        .await
+        .map_err(|e| SchemaVersionError::RepoError(e.to_string()))?;
+    check_schema_version(expected, applied)
+}
+
 /*
-pub fn find_all<'a>(client: &'a Client, table: &String) -> Result<BoxStream<'static, Migration>, anyhow::Error> {
+pub fn find_all<'a>(client: &'a Client, table: &String) -> Result<BoxStream<'static, Migration>, DbError> {
   move || {
     Box::pin(async move {
       let r = select_raw(client, &migration_table(), &vec![], map_migration_with_err).await;
@@ -161,3 +226,38 @@ pub fn find_all<'a>(client: &'a Client, table: &String) -> Result<BoxStream<'sta
   }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::{migration_table, Migration, MigrationCriteria};
+    use crate::models::common::entity_sql;
+    use crate::postgres_common::core::query_cond_to_string;
+
+    #[test]
+    pub fn test_migration_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            &migration_table(),
+            Migration::field_names(),
+            &Migration::write_field_names()
+        ));
+    }
+
+    #[test]
+    pub fn test_option_criteria_compare_none_with_is_null() {
+        let cases = [
+            (MigrationCriteria::ChecksumEq(None), "checksum is null"),
+            (MigrationCriteria::ChecksumNeq(None), "checksum is not null"),
+            (
+                MigrationCriteria::ChecksumEq(Some("abc".to_string())),
+                "checksum = $1",
+            ),
+            (MigrationCriteria::SeqOrderEq(1), "seq_order = $1"),
+        ];
+        for (crit, expected) in cases {
+            assert_eq!(
+                expected,
+                query_cond_to_string(&crit.to_query_condition(), 1)
+            );
+        }
+    }
+}
@@ -1,4 +1,7 @@
+pub mod common;
+pub mod email_templates;
 pub mod invitations;
-pub mod users;
+pub mod migration_runs;
 pub mod migrations;
-pub mod common;
\ No newline at end of file
+pub mod system_info;
+pub mod users;
@@ -0,0 +1,99 @@
+use chrono::{NaiveDateTime, Utc};
+use futures::future::BoxFuture;
+use tokio_postgres::{Client, Transaction};
+use uuid::Uuid;
+
+use crate::error::DbError;
+use crate::postgres_common::core::{select_all, Entity, Sort};
+
+pub const AVTOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const EVENT_MIGRATION: &str = "migration";
+pub const EVENT_BOOTSTRAP: &str = "bootstrap";
+pub const EVENT_ROLLBACK: &str = "rollback";
+pub const EVENT_BASELINE: &str = "baseline";
+
+pub const CREATE_SYSTEM_INFO_TABLE: &str = "
+create table if not exists system_info (
+  id uuid not null primary key,
+  event varchar(64) not null,
+  subject varchar(255) not null,
+  avtor_version varchar(64) not null,
+  recorded_on timestamp not null default current_timestamp
+);";
+
+#[derive(Debug, Clone, Entity)]
+#[entity(table = "system_info")]
+pub struct SystemInfo {
+    pub id: Uuid,
+    pub event: String,
+    pub subject: String,
+    pub avtor_version: String,
+    pub recorded_on: NaiveDateTime,
+}
+
+pub fn system_info_table() -> String {
+    SystemInfo::table_name().to_string()
+}
+
+/// A record stating that the running avtor-core version performed `event` on
+/// `subject` (e.g. a migration name).
+pub fn current_version_info(event: &str, subject: &str) -> SystemInfo {
+    SystemInfo {
+        id: Uuid::new_v4(),
+        event: event.to_string(),
+        subject: subject.to_string(),
+        avtor_version: AVTOR_VERSION.to_string(),
+        recorded_on: Utc::now().naive_utc(),
+    }
+}
+
+pub async fn ensure_system_info_table<'a>(client: &Transaction<'a>) -> Result<(), DbError> {
+    client.batch_execute(CREATE_SYSTEM_INFO_TABLE).await?;
+    Ok(())
+}
+
+pub fn insert_system_info<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(SystemInfo) -> BoxFuture<'a, Result<(), DbError>> {
+    move |info: SystemInfo| Box::pin(async move { info.insert(client).await })
+}
+
+pub fn find_latest_by_event<'a>(
+    client: &'a Client,
+) -> impl FnOnce(String) -> BoxFuture<'a, Result<Option<SystemInfo>, DbError>> {
+    move |event: String| {
+        Box::pin(async move {
+            let crit = SystemInfoCriteria::EventEq(event);
+            let cond = vec![crit.to_query_condition()];
+            let sorts: Vec<Sort> = vec![SystemInfoSort::RecordedOnDesc.to_sort()];
+            let found = select_all(
+                client,
+                &system_info_table(),
+                SystemInfo::field_names(),
+                &cond,
+                &sorts,
+                Some(1),
+                None,
+                SystemInfo::from_row,
+            )
+            .await?;
+            Ok(found.into_iter().next())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{system_info_table, SystemInfo};
+    use crate::models::common::entity_sql;
+
+    #[test]
+    pub fn test_system_info_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            &system_info_table(),
+            SystemInfo::field_names(),
+            &SystemInfo::write_field_names()
+        ));
+    }
+}
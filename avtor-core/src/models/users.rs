@@ -1,5 +1,16 @@
-use crate::postgres_common::core::{entity, insert, select, update, QueryCondition};
+use crate::error::DbError;
+use crate::identifier::{skeleton, ConfusablePolicy, IdentifierNormalizer};
+use crate::password::{
+    breach::{BreachCheckError, BreachChecker},
+    hash_password, PasswordHashError,
+};
+use crate::postgres_common::core::{
+    delete_by_id, insert_many, select_all, select_page, upsert, Cursor, CursorPage, Entity,
+};
+use crate::repo::{AccountRepo, UserRepo};
+use crate::secret::Secret;
 
+use chrono::NaiveDateTime;
 use futures::{future::BoxFuture, TryFutureExt};
 use postgres_derive::FromSql;
 use serde::{Deserialize, Serialize};
@@ -9,54 +20,199 @@ use std::{
     future::Future,
     hash::Hash,
 };
-use tokio_postgres::{Client, Row, Transaction};
+use tokio_postgres::{types::ToSql, Client, GenericClient, Transaction};
 use uuid::Uuid;
 use validator::{Validate, ValidationError, ValidationErrors};
 
-use super::common::field_names_without_id;
-
 #[derive(Debug, Clone, Copy, Deserialize, postgres_derive::ToSql, FromSql, Default)]
+#[postgres(transparent)]
 pub struct UserId(Uuid);
 
-entity! {
-    #[derive(Debug, Default)]
-    pub struct User {
-        id: UserId,
-        username: String,
-        password: String,
-        roles: String,
-        account_id: Uuid,
+impl UserId {
+    pub fn uuid(&self) -> Uuid {
+        self.0
     }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, postgres_derive::ToSql, FromSql, Default)]
+#[derive(Debug, Default, Clone, Entity)]
+#[entity(table = "users")]
+pub struct User {
+    pub id: UserId,
+    pub username: String,
+    pub password: Secret<String>,
+    pub roles: String,
+    pub account_id: Uuid,
+    /// Normalized like the username; unique within the account.
+    pub email: Option<String>,
+    #[entity(read_only)]
+    pub created_on: Option<NaiveDateTime>,
+    #[entity(read_only)]
+    pub updated_on: Option<NaiveDateTime>,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, postgres_derive::ToSql, FromSql, Default,
+)]
+#[postgres(transparent)]
 pub struct AccountId(Uuid);
 
-entity! {
-    #[derive(Debug, Default)]
-    pub struct Account {
-        id: AccountId,
-        name: String,
+impl AccountId {
+    pub fn uuid(&self) -> Uuid {
+        self.0
     }
 }
 
-pub fn map_from_result(result: Result<Row, tokio_postgres::Error>) -> User {
-    match result {
-        Ok(u) => User::from_row(u),
-        Err(_) => User::default(),
+impl From<Uuid> for AccountId {
+    fn from(id: Uuid) -> Self {
+        AccountId(id)
+    }
+}
+
+#[derive(Debug, Default, Clone, Entity)]
+#[entity(table = "accounts")]
+pub struct Account {
+    pub id: AccountId,
+    pub name: String,
+    pub slug: String,
+    /// A `ConfusablePolicy`, see `Account::confusable_policy`.
+    pub confusable_policy: String,
+    #[entity(read_only)]
+    pub created_on: Option<NaiveDateTime>,
+    #[entity(read_only)]
+    pub updated_on: Option<NaiveDateTime>,
+}
+
+impl User {
+    pub fn id(&self) -> UserId {
+        self.id
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    pub fn roles(&self) -> &str {
+        &self.roles
+    }
+
+    pub fn account_id(&self) -> Uuid {
+        self.account_id
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.email.as_deref()
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        roles_contain(&self.roles, role)
+    }
+
+    /// The stored argon2 hash, or `LOCKED_PASSWORD`.
+    pub fn password(&self) -> &Secret<String> {
+        &self.password
+    }
+
+    pub fn is_super_user(&self) -> bool {
+        self.roles.contains(SUPER_USER_ROLE)
+    }
+
+    pub fn is_system_user(&self) -> bool {
+        self.id.0 == SYSTEM_USER_ID
+    }
+
+    /// False for the system user and anyone else whose password is locked.
+    pub fn can_login(&self) -> bool {
+        !self.is_system_user() && self.password.expose_secret() != LOCKED_PASSWORD
     }
 }
 
+impl Account {
+    pub fn id(&self) -> AccountId {
+        self.id
+    }
+
+    pub fn slug(&self) -> &str {
+        &self.slug
+    }
+
+    pub fn with_slug(self, slug: String) -> Account {
+        Account { slug, ..self }
+    }
+
+    /// The default policy when the stored one is unknown.
+    pub fn confusable_policy(&self) -> ConfusablePolicy {
+        self.confusable_policy.parse().unwrap_or_default()
+    }
+}
+
+const MAX_SLUG_LEN: usize = 200;
+
+/// Lower case ascii letters and digits separated by single dashes, e.g.
+/// "Acme Corp." becomes "acme-corp".
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_SLUG_LEN);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "account".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// `base` if nobody has it yet, otherwise the first free `base-2`, `base-3`...
+pub fn next_free_slug(base: &str, taken: &[String]) -> String {
+    if !taken.iter().any(|t| t == base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{}-{}", base, n))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
 #[derive(Debug, Validate, Deserialize, Clone)]
 pub struct UserDto {
     pub id: Uuid,
     #[validate(length(min = 3, message = "username_required"))]
     pub username: String,
-    #[validate(length(min = 8, max = 18, message = "password_between_8_and_18_chars"))]
-    pub password: String,
+    #[validate(custom(
+        function = "validate_password_length",
+        message = "password_between_8_and_18_chars"
+    ))]
+    pub password: Secret<String>,
     #[validate(length(min = 1, message = "roles_required"))]
     pub roles: String,
     pub account_id: Uuid,
+    #[validate(email(message = "email_invalid"))]
+    pub email: Option<String>,
+}
+
+impl UserDto {
+    /// The dto with its username and email in the form that is stored and
+    /// looked up.
+    pub fn normalized(&self, normalizer: &dyn IdentifierNormalizer) -> UserDto {
+        UserDto {
+            username: normalizer.username(&self.username),
+            email: self.email.as_deref().map(|e| normalizer.email(e)),
+            ..self.clone()
+        }
+    }
+}
+
+// The length validator only accepts plain strings.
+fn validate_password_length(password: &Secret<String>) -> Result<(), ValidationError> {
+    if (8..=18).contains(&password.expose_secret().chars().count()) {
+        return Ok(());
+    }
+    Err(ValidationError::new("length"))
 }
 
 pub fn user_from_dto(dto: UserDto) -> User {
@@ -66,6 +222,9 @@ pub fn user_from_dto(dto: UserDto) -> User {
         password: dto.password,
         roles: dto.roles,
         account_id: dto.account_id,
+        email: dto.email,
+        created_on: None,
+        updated_on: None,
     }
 }
 
@@ -140,15 +299,33 @@ pub enum CreateSuperUserError {
 
     #[error("Account exits")]
     AccountExists,
+
+    #[error("Account name is taken")]
+    AccountNameTaken,
+
+    #[error("Username is taken")]
+    UsernameTaken,
+
+    #[error("Email is taken")]
+    EmailTaken,
+
+    #[error("Password is known from a data breach")]
+    PasswordBreached,
+
+    #[error(transparent)]
+    BreachCheck(#[from] BreachCheckError),
+
+    #[error(transparent)]
+    PasswordHash(#[from] PasswordHashError),
 }
 
-impl From<anyhow::Error> for CreateSuperUserError {
-    fn from(_: anyhow::Error) -> Self {
-        CreateSuperUserError::UnknownError
+impl From<DbError> for CreateSuperUserError {
+    fn from(e: DbError) -> Self {
+        CreateSuperUserError::RepoError(e.to_string())
     }
 }
 
-fn hash_map_from_validation_errors(e: ValidationErrors) -> HashMap<String, String> {
+pub(crate) fn hash_map_from_validation_errors(e: ValidationErrors) -> HashMap<String, String> {
     let field_errors = e.field_errors();
     field_errors
         .into_iter()
@@ -184,8 +361,143 @@ pub fn concat_validation_errors(v_errs_opt: Option<&&Vec<ValidationError>>) -> S
 
 pub const USER_TABLE: &'static str = "users";
 
+pub const SUPER_USER_ROLE: &str = "super_user";
+
+pub const SYSTEM_ROLE: &str = "system";
+
+/// The account owning the system user, created by the builtin migrations.
+pub const SYSTEM_ACCOUNT_ID: Uuid = Uuid::from_u128(1);
+
+/// Name and slug of the system account; `slugify` never yields a leading
+/// underscore, so no tenant's slug can match it.
+pub const SYSTEM_ACCOUNT_NAME: &str = "__avtor_system";
+
+/// The actor recorded for automated operations such as migrations, so audit
+/// rows never lack a user.
+pub const SYSTEM_USER_ID: Uuid = Uuid::from_u128(2);
+
+/// Stored instead of a hash for users that must not log in; no hash ever
+/// equals it.
+pub const LOCKED_PASSWORD: &str = "!";
+
+/// Whether the comma separated `roles` include `role` itself, so "user" does
+/// not match "super_user".
+pub fn roles_contain(roles: &str, role: &str) -> bool {
+    roles.split(',').any(|r| r.trim() == role)
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct UserFilter {
+    pub account_id: Option<Uuid>,
+    pub role: Option<String>,
+}
+
+/// Users ordered by username, at most `limit` per page. The role filter is
+/// applied to whole roles after fetching, so a page can hold fewer than
+/// `limit` users; keep following `next_cursor` until it is `None`.
+pub fn find_users_page<'a>(
+    client: &'a Client,
+) -> impl FnOnce(UserFilter, Option<Cursor>, i64) -> BoxFuture<'a, Result<CursorPage<User>, DbError>>
+{
+    move |filter: UserFilter, after: Option<Cursor>, limit: i64| {
+        Box::pin(async move {
+            let mut crits = vec![];
+            if let Some(account_id) = filter.account_id {
+                crits.push(UserCriteria::AccountIdEq(account_id));
+            }
+            if let Some(role) = &filter.role {
+                crits.push(UserCriteria::RolesLike(format!("%{}%", role)));
+            }
+            let conds = crits.iter().map(|c| c.to_query_condition()).collect();
+            let mut page = select_page(
+                client,
+                &user_table(),
+                User::field_names(),
+                &conds,
+                &"username".to_string(),
+                &"id".to_string(),
+                after.as_ref(),
+                limit,
+                User::from_row,
+                |u: &User| (u.username.clone(), u.id.0),
+            )
+            .await?;
+            if let Some(role) = &filter.role {
+                page.items.retain(|u| u.has_role(role));
+            }
+            Ok(page)
+        })
+    }
+}
+
+/// Unique index on `lower(username)`, see migration 05.
+const ACCOUNT_NAME_UNIQUE_INDEX: &str = "accounts_name_key";
+const USERNAME_UNIQUE_INDEX: &str = "users_username_key";
+const EMAIL_UNIQUE_INDEX: &str = "users_account_email_key";
+/// Partial unique index on super users, see migration 02.
+const SUPER_USER_UNIQUE_INDEX: &str = "users_single_super_user";
+
+/// Names the unique indexes a user write can break; any other violation,
+/// e.g. of the primary key, is a repo error naming its constraint.
+fn user_write_error(e: DbError) -> CreateSuperUserError {
+    let constraint = match &e {
+        DbError::UniqueViolation { constraint, .. } => constraint.as_deref(),
+        _ => None,
+    };
+    match constraint {
+        Some(USERNAME_UNIQUE_INDEX) => CreateSuperUserError::UsernameTaken,
+        Some(EMAIL_UNIQUE_INDEX) => CreateSuperUserError::EmailTaken,
+        Some(SUPER_USER_UNIQUE_INDEX) => CreateSuperUserError::SuperUserExists,
+        _ => CreateSuperUserError::RepoError(e.to_string()),
+    }
+}
+
 pub fn user_table() -> String {
-    "users".to_string()
+    User::table_name().to_string()
+}
+
+/// Exact match on the stored, normalized username.
+pub fn find_user_by_username<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(String) -> BoxFuture<'a, Result<Option<User>, CreateSuperUserError>> {
+    move |username: String| {
+        Box::pin(async move {
+            User::find_where(client, &[UserCriteria::UsernameEq(username)])
+                .await
+                .map(|users| users.into_iter().next())
+                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
+        })
+    }
+}
+
+/// Exact match on the stored, normalized email within one account.
+pub fn find_user_by_email<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(AccountId, String) -> BoxFuture<'a, Result<Option<User>, CreateSuperUserError>> {
+    move |account_id: AccountId, email: String| {
+        Box::pin(async move {
+            let crit = [
+                UserCriteria::AccountIdEq(account_id.uuid()),
+                UserCriteria::EmailEq(Some(email)),
+            ];
+            User::find_where(client, &crit)
+                .await
+                .map(|users| users.into_iter().next())
+                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
+        })
+    }
+}
+
+pub fn find_users_by_account<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(AccountId) -> BoxFuture<'a, Result<Vec<User>, CreateSuperUserError>> {
+    move |account_id: AccountId| {
+        Box::pin(async move {
+            User::find_where(client, &[UserCriteria::AccountIdEq(account_id.uuid())])
+                .await
+                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
+        })
+    }
 }
 
 pub fn find_super_user<'a>(
@@ -193,22 +505,29 @@ pub fn find_super_user<'a>(
 ) -> impl FnOnce() -> BoxFuture<'a, Result<Option<User>, CreateSuperUserError>> {
     move || {
         Box::pin(async move {
-            let rol_crit = UserCriteria::RolesLike("%super_admin%".to_string());
-            let crit = vec![rol_crit.to_query_condition()];
-            select(client, &user_table(), &crit, User::from_row)
+            let crit = UserCriteria::RolesLike(format!("%{}%", SUPER_USER_ROLE));
+            User::find_where(client, &[crit])
                 .await
-                .map_err(|_| CreateSuperUserError::RepoError("".to_string()))
+                .map(|users| users.into_iter().next())
+                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
         })
     }
 }
 
 pub fn insert_user<'a>(
     client: &'a Transaction,
+) -> impl FnOnce(User) -> BoxFuture<'a, Result<(), CreateSuperUserError>> {
+    move |user: User| Box::pin(async move { user.insert(client).await.map_err(user_write_error) })
+}
+
+/// Inserts `user`, or overwrites every field of the user with its id.
+pub fn upsert_user<'a>(
+    client: &'a Transaction,
 ) -> impl FnOnce(User) -> BoxFuture<'a, Result<(), CreateSuperUserError>> {
     move |user: User| {
         Box::pin(async move {
-            let fields = field_names_without_id(User::field_names());
-            insert(
+            let fields = User::write_field_names();
+            upsert(
                 client,
                 &user_table(),
                 &"id".to_string(),
@@ -217,27 +536,56 @@ pub fn insert_user<'a>(
                 &user.to_params_x(),
             )
             .await
-            .map_err(|_| CreateSuperUserError::RepoError("".to_string()))
+            .map_err(user_write_error)
         })
     }
 }
 
-pub fn update_user<'a, 'b>(
-    client: &'a Client,
-) -> impl FnOnce(&'a User) -> BoxFuture<'a, Result<(), CreateSuperUserError>> {
-    move |user: &'a User| {
+pub fn insert_users<'a, C: GenericClient + Send>(
+    client: &'a mut C,
+) -> impl FnOnce(Vec<User>) -> BoxFuture<'a, Result<(), CreateSuperUserError>> {
+    move |users: Vec<User>| {
         Box::pin(async move {
-            let fields = field_names_without_id(User::field_names());
-            update(
+            let fields = User::write_field_names();
+            let rows: Vec<Vec<&(dyn ToSql + Sync)>> = users
+                .iter()
+                .map(|u| [vec![&u.id as &(dyn ToSql + Sync)], u.to_params_x()].concat())
+                .collect();
+            insert_many(
                 client,
                 &user_table(),
                 &"id".to_string(),
                 fields.as_slice(),
-                &user.id,
-                &user.to_params_x(),
+                rows.as_slice(),
             )
             .await
-            .map_err(|_| CreateSuperUserError::RepoError("".to_string()))
+            .map(|_| ())
+            .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
+        })
+    }
+}
+
+pub fn update_user<'a, 'b>(
+    client: &'a Client,
+) -> impl FnOnce(&'a User) -> BoxFuture<'a, Result<(), CreateSuperUserError>> {
+    move |user: &'a User| {
+        Box::pin(async move {
+            user.update(client)
+                .await
+                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
+        })
+    }
+}
+
+pub fn delete_user<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(UserId) -> BoxFuture<'a, Result<(), CreateSuperUserError>> {
+    move |user_id: UserId| {
+        Box::pin(async move {
+            delete_by_id(client, &user_table(), &"id".to_string(), &user_id)
+                .await
+                .map(|_| ())
+                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))
         })
     }
 }
@@ -249,6 +597,32 @@ pub struct AccountDto {
     pub name: String,
 }
 
+/// The account for `dto`, with its slug derived from the name.
+pub fn account_from_dto(dto: AccountDto) -> Account {
+    Account {
+        id: AccountId(dto.id),
+        slug: slugify(&dto.name),
+        name: dto.name,
+        confusable_policy: ConfusablePolicy::default().as_str().to_string(),
+        created_on: None,
+        updated_on: None,
+    }
+}
+
+fn validate_super_user(
+    user_dto: &UserDto,
+    account_dto: &AccountDto,
+) -> Result<(), CreateSuperUserError> {
+    user_dto.validate().map_err(|e| {
+        let hash_map = hash_map_from_validation_errors(e);
+        CreateSuperUserError::UserInvalid(hash_map)
+    })?;
+    account_dto.validate().map_err(|e| {
+        let hash_map = hash_map_from_validation_errors(e);
+        CreateSuperUserError::AccountInvalid(hash_map)
+    })
+}
+
 pub async fn create_super_user<FA, FB, FC, FD>(
     find_super_user: impl FnOnce() -> FA,
     insert: impl FnOnce(User) -> FB,
@@ -263,32 +637,21 @@ where
     FC: Future<Output = Result<(), CreateAccountError>>,
     FD: Future<Output = Result<Option<Account>, CreateAccountError>>,
 {
-    let _ = user_dto.validate().map_err(|e| {
-        let hash_map = hash_map_from_validation_errors(e);
-        CreateSuperUserError::UserInvalid(hash_map)
-    })?;
-    let _ = account_dto.validate().map_err(|e| {
-        let hash_map = hash_map_from_validation_errors(e);
-        CreateSuperUserError::AccountInvalid(hash_map)
-    })?;
-    let user = user_from_dto(user_dto.clone());
+    validate_super_user(user_dto, account_dto)?;
+    let user = User {
+        password: hash_password(&user_dto.password)?,
+        ..user_from_dto(user_dto.clone())
+    };
     let maybe_existing_user = find_super_user().await?;
     match maybe_existing_user {
         Some(_) => Err(CreateSuperUserError::SuperUserExists),
         None => {
-            let maybe_existing_account = find_account_by_id(AccountId(account_dto.id))
-                .await
-                .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))?;
+            let maybe_existing_account = find_account_by_id(AccountId(account_dto.id)).await?;
             match maybe_existing_account {
                 Some(_) => Err(CreateSuperUserError::AccountExists),
                 None => {
-                    let account = Account {
-                        id: AccountId(account_dto.id),
-                        name: account_dto.clone().name,
-                    };
-                    let _ = insert_account(account)
-                        .await
-                        .map_err(|e| CreateSuperUserError::RepoError(e.to_string()))?;
+                    let account = account_from_dto(account_dto.clone());
+                    let _ = insert_account(account).await?;
                     let ins_res = insert(user).await;
                     match ins_res {
                         Ok(_) => Ok(()),
@@ -300,18 +663,195 @@ where
     }
 }
 
+/// `create_super_user` over repository trait objects instead of closures,
+/// with the username normalized and, once the input is valid, the password
+/// checked against `breach_checker`.
+pub async fn create_super_user_with_repos(
+    user_repo: &dyn UserRepo,
+    account_repo: &dyn AccountRepo,
+    normalizer: &dyn IdentifierNormalizer,
+    breach_checker: &dyn BreachChecker,
+    user_dto: &UserDto,
+    account_dto: &AccountDto,
+) -> Result<(), CreateSuperUserError> {
+    let user_dto = &user_dto.normalized(normalizer);
+    // invalid input is refused without a possibly remote breach check
+    validate_super_user(user_dto, account_dto)?;
+    if breach_checker.is_breached(&user_dto.password).await? {
+        return Err(CreateSuperUserError::PasswordBreached);
+    }
+    create_super_user(
+        || user_repo.find_super_user(),
+        |user| user_repo.insert_user(user),
+        |account| account_repo.insert_account(account),
+        |account_id| account_repo.find_account_by_id(account_id),
+        user_dto,
+        account_dto,
+    )
+    .await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CreateUserError {
+    #[error("User invalid")]
+    UserInvalid(HashMap<String, String>),
+
+    #[error("Role {0} can't be given to regular users")]
+    ReservedRole(String),
+
+    #[error("Account not found")]
+    AccountNotFound,
+
+    #[error("Username is taken")]
+    UsernameTaken,
+
+    #[error("Username looks like {0}, which the account already has")]
+    UsernameConfusable(String),
+
+    #[error("Email is taken")]
+    EmailTaken,
+
+    #[error("Password is known from a data breach")]
+    PasswordBreached,
+
+    #[error(transparent)]
+    BreachCheck(#[from] BreachCheckError),
+
+    #[error(transparent)]
+    PasswordHash(#[from] PasswordHashError),
+
+    #[error("Repo Error: {0}")]
+    RepoError(String),
+}
+
+impl From<CreateSuperUserError> for CreateUserError {
+    fn from(e: CreateSuperUserError) -> Self {
+        match e {
+            CreateSuperUserError::UsernameTaken => CreateUserError::UsernameTaken,
+            CreateSuperUserError::EmailTaken => CreateUserError::EmailTaken,
+            CreateSuperUserError::PasswordBreached => CreateUserError::PasswordBreached,
+            CreateSuperUserError::BreachCheck(e) => CreateUserError::BreachCheck(e),
+            e => CreateUserError::RepoError(e.to_string()),
+        }
+    }
+}
+
+impl From<CreateAccountError> for CreateUserError {
+    fn from(e: CreateAccountError) -> Self {
+        CreateUserError::RepoError(e.to_string())
+    }
+}
+
+/// Creates a regular user in an existing account. The super user and system
+/// roles are reserved for `create_super_user` and the builtin migrations.
+pub async fn create_user_with_repos(
+    user_repo: &dyn UserRepo,
+    account_repo: &dyn AccountRepo,
+    normalizer: &dyn IdentifierNormalizer,
+    breach_checker: &dyn BreachChecker,
+    user_dto: &UserDto,
+) -> Result<(), CreateUserError> {
+    let user_dto = &user_dto.normalized(normalizer);
+    user_dto
+        .validate()
+        .map_err(|e| CreateUserError::UserInvalid(hash_map_from_validation_errors(e)))?;
+    if let Some(role) = [SUPER_USER_ROLE, SYSTEM_ROLE]
+        .into_iter()
+        .find(|r| roles_contain(&user_dto.roles, r))
+    {
+        return Err(CreateUserError::ReservedRole(role.to_string()));
+    }
+    let account = account_repo
+        .find_account_by_id(AccountId(user_dto.account_id))
+        .await?
+        .ok_or(CreateUserError::AccountNotFound)?;
+    if user_repo
+        .find_user_by_username(user_dto.username.clone())
+        .await?
+        .is_some()
+    {
+        return Err(CreateUserError::UsernameTaken);
+    }
+    if let Some(email) = &user_dto.email {
+        if user_repo
+            .find_user_by_email(account.id(), email.clone())
+            .await?
+            .is_some()
+        {
+            return Err(CreateUserError::EmailTaken);
+        }
+    }
+    let policy = account.confusable_policy();
+    if policy != ConfusablePolicy::Allow {
+        let wanted = skeleton(&user_dto.username);
+        let lookalike = user_repo
+            .find_users_by_account(account.id())
+            .await?
+            .into_iter()
+            .find(|u| skeleton(u.username()) == wanted);
+        match lookalike {
+            Some(u) if policy == ConfusablePolicy::Reject => {
+                return Err(CreateUserError::UsernameConfusable(u.username));
+            }
+            Some(u) => {
+                tracing::warn!(username = %user_dto.username, lookalike = %u.username, "username looks like an existing one");
+            }
+            None => {}
+        }
+    }
+    // last, as it may be a network call
+    if breach_checker.is_breached(&user_dto.password).await? {
+        return Err(CreateUserError::PasswordBreached);
+    }
+    let user = User {
+        password: hash_password(&user_dto.password)?,
+        ..user_from_dto(user_dto.clone())
+    };
+    user_repo.insert_user(user).await?;
+    Ok(())
+}
+
+/// The user with `email` in the account, for linking an accepted
+/// invitation to an existing user.
+pub async fn find_user_by_email_with_repos(
+    user_repo: &dyn UserRepo,
+    normalizer: &dyn IdentifierNormalizer,
+    account_id: AccountId,
+    email: &str,
+) -> Result<Option<User>, CreateSuperUserError> {
+    user_repo
+        .find_user_by_email(account_id, normalizer.email(email))
+        .await
+}
+
 pub fn account_table() -> String {
-    "accounts".to_string()
+    Account::table_name().to_string()
 }
 
+#[derive(Debug)]
 pub enum CreateAccountError {
     RepoError(String),
+    AccountExists,
+    /// Another account has the name but for case.
+    NameTaken,
 }
 
 impl ToString for CreateAccountError {
     fn to_string(&self) -> String {
         match self {
             Self::RepoError(es) => es.to_owned(),
+            Self::AccountExists => "Account exists".to_string(),
+            Self::NameTaken => "Account name is taken".to_string(),
+        }
+    }
+}
+
+impl From<CreateAccountError> for CreateSuperUserError {
+    fn from(e: CreateAccountError) -> Self {
+        match e {
+            CreateAccountError::AccountExists => CreateSuperUserError::AccountExists,
+            CreateAccountError::NameTaken => CreateSuperUserError::AccountNameTaken,
+            CreateAccountError::RepoError(es) => CreateSuperUserError::RepoError(es),
         }
     }
 }
@@ -328,13 +868,56 @@ pub fn blah() {
     Blah { x: &foo };
 }
 
+/// A name clash is reported as such; any other unique violation means a
+/// concurrent create won the race between a lookup and the write.
+fn account_write_error(e: DbError) -> CreateAccountError {
+    match e {
+        DbError::UniqueViolation { constraint, .. }
+            if constraint.as_deref() == Some(ACCOUNT_NAME_UNIQUE_INDEX) =>
+        {
+            CreateAccountError::NameTaken
+        }
+        DbError::UniqueViolation { .. } => CreateAccountError::AccountExists,
+        e => CreateAccountError::RepoError(e.to_string()),
+    }
+}
+
+/// Inserts `account`, suffixing its slug when another account already has it.
 pub fn insert_account<'a>(
     client: &'a Transaction,
 ) -> impl FnOnce(Account) -> BoxFuture<'a, Result<(), CreateAccountError>> {
     move |account: Account| {
         Box::pin(async move {
-            let fields = field_names_without_id(Account::field_names());
-            insert(
+            let crit = AccountCriteria::SlugLike(format!("{}%", account.slug));
+            let cond = vec![crit.to_query_condition()];
+            let taken = select_all(
+                client,
+                &account_table(),
+                &["slug"],
+                &cond,
+                &[],
+                None,
+                None,
+                |row| row.try_get::<_, String>("slug"),
+            )
+            .await
+            .map_err(|e| CreateAccountError::RepoError(e.to_string()))?;
+            let slug = next_free_slug(&account.slug, &taken);
+            let account = account.with_slug(slug);
+            account.insert(client).await.map_err(account_write_error)
+        })
+    }
+}
+
+/// Inserts `account`, or overwrites the name and slug of the account with its
+/// id. Unlike `insert_account` the slug is kept as given.
+pub fn upsert_account<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(Account) -> BoxFuture<'a, Result<(), CreateAccountError>> {
+    move |account: Account| {
+        Box::pin(async move {
+            let fields = Account::write_field_names();
+            upsert(
                 client,
                 &account_table(),
                 &"id".to_string(),
@@ -343,8 +926,7 @@ pub fn insert_account<'a>(
                 &account.to_params_x(),
             )
             .await
-            // todo: put a real error here
-            .map_err(|_| CreateAccountError::RepoError("".to_string()))
+            .map_err(account_write_error)
         })
     }
 }
@@ -354,15 +936,39 @@ pub fn find_account_by_id<'a>(
 ) -> impl FnOnce(AccountId) -> BoxFuture<'a, Result<Option<Account>, CreateAccountError>> {
     move |account_id: AccountId| {
         Box::pin(async move {
-            let id_crit = AccountCriteria::IdEq(account_id);
-            let cond = vec![id_crit.to_query_condition()];
-            select(client, &account_table(), &cond, Account::from_row)
+            Account::find_by_id(client, &account_id)
                 .await
                 .map_err(|e| CreateAccountError::RepoError(e.to_string()))
         })
     }
 }
 
+pub fn find_account_by_slug<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(String) -> BoxFuture<'a, Result<Option<Account>, CreateAccountError>> {
+    move |slug: String| {
+        Box::pin(async move {
+            Account::find_where(client, &[AccountCriteria::SlugEq(slug)])
+                .await
+                .map(|accounts| accounts.into_iter().next())
+                .map_err(|e| CreateAccountError::RepoError(e.to_string()))
+        })
+    }
+}
+
+pub fn delete_account<'a>(
+    client: &'a Transaction,
+) -> impl FnOnce(AccountId) -> BoxFuture<'a, Result<(), CreateAccountError>> {
+    move |account_id: AccountId| {
+        Box::pin(async move {
+            delete_by_id(client, &account_table(), &"id".to_string(), &account_id)
+                .await
+                .map(|_| ())
+                .map_err(|e| CreateAccountError::RepoError(e.to_string()))
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -370,20 +976,26 @@ mod tests {
     use futures::{executor::block_on, future::BoxFuture};
     use uuid::Uuid;
 
-    use crate::models::users::hash_map_to_string;
+    use crate::models::common::entity_sql;
+    use crate::models::users::{
+        account_table, hash_map_to_string, next_free_slug, roles_contain, slugify, user_table,
+    };
 
     use super::{
-        create_super_user, Account, AccountDto, AccountId, CreateAccountError,
-        CreateSuperUserError, User, UserDto,
+        account_write_error, create_super_user, user_write_error, Account, AccountDto, AccountId,
+        CreateAccountError, CreateSuperUserError, User, UserDto, UserId, LOCKED_PASSWORD,
+        SYSTEM_USER_ID,
     };
+    use crate::error::DbError;
 
     fn user_dto() -> UserDto {
         UserDto {
             id: Uuid::from_str("9acd36f9-b9f4-4fd1-840c-c161a9fd3c41").unwrap(),
             username: "someusername".to_string(),
-            password: "!Q2w3e4r5t".to_string(),
+            password: "!Q2w3e4r5t".into(),
             roles: "super_user".to_string(),
             account_id: Uuid::from_str("a304f299-b547-4d3d-bd42-732f617b258a").unwrap(),
+            email: None,
         }
     }
 
@@ -425,6 +1037,8 @@ mod tests {
         Account {
             id: AccountId(Uuid::from_str("ac41d7b5-248c-415c-8728-9cb3bd91a6fb").unwrap()),
             name: "fake".to_string(),
+            slug: "fake".to_string(),
+            ..Account::default()
         }
     }
 
@@ -476,7 +1090,7 @@ mod tests {
     pub fn test_create_super_user_fails_with_invalid_user() {
         let dto = UserDto {
             username: "".to_string(),
-            password: "".to_string(),
+            password: "".into(),
             ..user_dto()
         };
         let mut find_su_count: u8 = 0;
@@ -495,6 +1109,8 @@ mod tests {
             Ok(_) => assert!(false, "Ok encountered where Err expected"),
             Err(e) => match e {
                 CreateSuperUserError::UserInvalid(map) => {
+                    // validation errors carry the offending value
+                    assert!(map["password"].contains("********"), "{:?}", map);
                     println!("{}", hash_map_to_string(map));
                     assert_eq!(0, find_su_count);
                     assert_eq!(0, insert_count);
@@ -531,6 +1147,25 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn test_create_super_user_maps_concurrent_account_insert() {
+        let mut find_su_count: u8 = 0;
+        let mut insert_count: u8 = 0;
+        let mut find_account_by_id_count: u8 = 0;
+
+        let insert_account_raced = |_: Account| async { Err(CreateAccountError::AccountExists) };
+        let res = block_on(create_super_user(
+            find_existing_super_user(&mut find_su_count),
+            insert_user_mock(&mut insert_count),
+            insert_account_raced,
+            find_account_by_id(&mut find_account_by_id_count),
+            &user_dto(),
+            &account_dto(),
+        ));
+        assert!(matches!(res, Err(CreateSuperUserError::AccountExists)));
+        assert_eq!(0, insert_count);
+    }
+
     #[test]
     pub fn test_create_super_user_fails_with_account_insert_error() {
         let mut find_su_count: u8 = 0;
@@ -558,4 +1193,105 @@ mod tests {
             },
         }
     }
+
+    #[test]
+    pub fn test_system_user_cannot_login() {
+        let system = User {
+            id: UserId(SYSTEM_USER_ID),
+            password: "a real hash".into(),
+            ..User::default()
+        };
+        let locked = User {
+            password: LOCKED_PASSWORD.into(),
+            ..User::default()
+        };
+        let normal = User {
+            password: "a real hash".into(),
+            ..User::default()
+        };
+        assert!(system.is_system_user());
+        assert!(!system.can_login());
+        assert!(!locked.can_login());
+        assert!(normal.can_login());
+    }
+
+    #[test]
+    pub fn test_roles_contain_whole_roles() {
+        assert!(roles_contain("user, billing", "billing"));
+        assert!(roles_contain("user", "user"));
+        assert!(!roles_contain("super_user", "user"));
+        assert!(!roles_contain("", "user"));
+    }
+
+    #[test]
+    pub fn test_slugify() {
+        assert_eq!("acme-corp", slugify("  Acme Corp. "));
+        assert_eq!("a-b-c", slugify("A--b__c"));
+        assert_eq!("account", slugify("!!!"));
+    }
+
+    #[test]
+    pub fn test_next_free_slug() {
+        let taken = vec!["acme".to_string(), "acme-2".to_string()];
+        assert_eq!("other", next_free_slug("other", &taken));
+        assert_eq!("acme-3", next_free_slug("acme", &taken));
+    }
+
+    #[test]
+    pub fn test_user_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            &user_table(),
+            User::field_names(),
+            &User::write_field_names()
+        ));
+    }
+
+    #[test]
+    pub fn test_account_sql_snapshot() {
+        insta::assert_snapshot!(entity_sql(
+            &account_table(),
+            Account::field_names(),
+            &Account::write_field_names()
+        ));
+    }
+
+    #[test]
+    pub fn test_user_write_errors_name_the_broken_constraint() {
+        let violation = |constraint: &str| DbError::UniqueViolation {
+            constraint: Some(constraint.to_string()),
+            message: "duplicate key value".to_string(),
+        };
+        assert!(matches!(
+            user_write_error(violation("users_single_super_user")),
+            CreateSuperUserError::SuperUserExists
+        ));
+        assert!(matches!(
+            user_write_error(violation("users_username_key")),
+            CreateSuperUserError::UsernameTaken
+        ));
+        match user_write_error(violation("users_pkey")) {
+            CreateSuperUserError::RepoError(message) => {
+                assert!(message.contains("users_pkey"), "{}", message)
+            }
+            e => panic!("expected a repo error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    pub fn test_account_write_errors_name_the_broken_constraint() {
+        let violation = |constraint: &str| DbError::UniqueViolation {
+            constraint: Some(constraint.to_string()),
+            message: "duplicate key value".to_string(),
+        };
+        let name_taken = account_write_error(violation("accounts_name_key"));
+        assert!(matches!(name_taken, CreateAccountError::NameTaken));
+        assert!(matches!(
+            CreateSuperUserError::from(name_taken),
+            CreateSuperUserError::AccountNameTaken
+        ));
+        assert!(matches!(
+            account_write_error(violation("accounts_slug_key")),
+            CreateAccountError::AccountExists
+        ));
+    }
 }
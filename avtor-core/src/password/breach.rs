@@ -0,0 +1,441 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Read, Write},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+#[cfg(feature = "breach-hibp")]
+use hyper::{client::HttpConnector, Body, Client, Request};
+#[cfg(feature = "breach-hibp")]
+use hyper_tls::HttpsConnector;
+use sha1::{Digest, Sha1};
+
+use crate::secret::Secret;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BreachCheckError {
+    #[error("breached password check unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("invalid breached password filter: {0}")]
+    InvalidFilter(String),
+}
+
+/// Tells whether a password is known from a breach, so it can be refused
+/// before it is ever hashed and stored.
+#[async_trait]
+pub trait BreachChecker: Send + Sync {
+    async fn is_breached(&self, password: &Secret<String>) -> Result<bool, BreachCheckError>;
+}
+
+/// Accepts every password.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoBreachCheck;
+
+#[async_trait]
+impl BreachChecker for NoBreachCheck {
+    async fn is_breached(&self, _password: &Secret<String>) -> Result<bool, BreachCheckError> {
+        Ok(false)
+    }
+}
+
+fn sha1_digest(bytes: &[u8]) -> [u8; 20] {
+    Sha1::digest(bytes).into()
+}
+
+fn sha1_hex(password: &Secret<String>) -> String {
+    sha1_digest(password.expose_secret().as_bytes())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect()
+}
+
+/// Answers a k-anonymity range query: the `SUFFIX:COUNT` lines of every
+/// breached SHA-1 starting with a five hex digit prefix.
+#[async_trait]
+pub trait RangeSource: Send + Sync {
+    async fn range(&self, prefix: &str) -> Result<String, BreachCheckError>;
+}
+
+#[cfg(feature = "breach-hibp")]
+pub const HIBP_RANGE_URL: &str = "https://api.pwnedpasswords.com/range/";
+
+/// The HaveIBeenPwned range api. Only the prefix leaves the process, and
+/// responses are padded so their size doesn't give the password away either.
+#[cfg(feature = "breach-hibp")]
+pub struct HibpRangeApi {
+    client: Client<HttpsConnector<HttpConnector>>,
+    base_url: String,
+    timeout: Duration,
+}
+
+#[cfg(feature = "breach-hibp")]
+impl Default for HibpRangeApi {
+    fn default() -> Self {
+        HibpRangeApi::new(HIBP_RANGE_URL, Duration::from_secs(5))
+    }
+}
+
+#[cfg(feature = "breach-hibp")]
+impl HibpRangeApi {
+    pub fn new(base_url: &str, timeout: Duration) -> HibpRangeApi {
+        HibpRangeApi {
+            client: Client::builder().build(HttpsConnector::new()),
+            base_url: base_url.to_string(),
+            timeout,
+        }
+    }
+}
+
+#[cfg(feature = "breach-hibp")]
+#[async_trait]
+impl RangeSource for HibpRangeApi {
+    async fn range(&self, prefix: &str) -> Result<String, BreachCheckError> {
+        let unavailable = |e: &dyn std::fmt::Display| BreachCheckError::Unavailable(e.to_string());
+        let request = Request::get(format!("{}{}", self.base_url, prefix))
+            .header("User-Agent", concat!("avtor/", env!("CARGO_PKG_VERSION")))
+            .header("Add-Padding", "true")
+            .body(Body::empty())
+            .map_err(|e| unavailable(&e))?;
+        let response = tokio::time::timeout(self.timeout, self.client.request(request))
+            .await
+            .map_err(|e| unavailable(&e))?
+            .map_err(|e| unavailable(&e))?;
+        if !response.status().is_success() {
+            return Err(unavailable(&response.status()));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| unavailable(&e))?;
+        String::from_utf8(body.to_vec()).map_err(|e| unavailable(&e))
+    }
+}
+
+/// Looks passwords up with range queries, keeping each answer for `ttl`.
+/// Padding lines have a count of 0 and never match.
+pub struct RangeChecker {
+    source: Box<dyn RangeSource>,
+    ttl: Duration,
+    capacity: usize,
+    cache: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl RangeChecker {
+    pub fn new(source: Box<dyn RangeSource>, ttl: Duration) -> RangeChecker {
+        RangeChecker {
+            source,
+            ttl,
+            capacity: 1024,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, prefix: &str) -> Option<String> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(prefix)
+            .filter(|(fetched, _)| fetched.elapsed() < self.ttl)
+            .map(|(_, range)| range.clone())
+    }
+
+    fn remember(&self, prefix: &str, range: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.capacity {
+            cache.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        }
+        if cache.len() >= self.capacity {
+            cache.clear();
+        }
+        cache.insert(prefix.to_string(), (Instant::now(), range.to_string()));
+    }
+}
+
+#[async_trait]
+impl BreachChecker for RangeChecker {
+    async fn is_breached(&self, password: &Secret<String>) -> Result<bool, BreachCheckError> {
+        let hash = sha1_hex(password);
+        let (prefix, suffix) = hash.split_at(5);
+        let range = match self.cached(prefix) {
+            Some(range) => range,
+            None => {
+                let range = self.source.range(prefix).await?;
+                self.remember(prefix, &range);
+                range
+            }
+        };
+        Ok(range.lines().any(|line| match line.trim().split_once(':') {
+            Some((s, count)) => s.eq_ignore_ascii_case(suffix) && count.trim() != "0",
+            None => false,
+        }))
+    }
+}
+
+const FILTER_MAGIC: &[u8; 8] = b"AVTORBF1";
+
+/// A bloom filter over the SHA-1 of breached passwords, for checking without
+/// network access. False positives refuse a few good passwords; a breached
+/// password in the filter is always found.
+///
+/// The file is `AVTORBF1`, the number of hashes as a little endian `u32`,
+/// the number of bits as a little endian `u64`, then the bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    hashes: u32,
+    bits: Vec<u8>,
+}
+
+/// Largest filter `BloomFilter::new` builds, 8 GiB; the whole HaveIBeenPwned
+/// list at a rate of 0.001 takes under 2.
+pub const MAX_FILTER_BITS: u64 = 1 << 36;
+
+impl BloomFilter {
+    /// Sized for `expected_items` at about `false_positive_rate`, which must
+    /// be between 0 and 1 exclusive. Fails rather than allocate more than
+    /// `MAX_FILTER_BITS`.
+    pub fn new(
+        expected_items: u64,
+        false_positive_rate: f64,
+    ) -> Result<BloomFilter, BreachCheckError> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(BreachCheckError::InvalidFilter(format!(
+                "false positive rate {} is not between 0 and 1",
+                false_positive_rate
+            )));
+        }
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(8.0);
+        if bits > MAX_FILTER_BITS as f64 {
+            return Err(BreachCheckError::InvalidFilter(format!(
+                "{} items at a false positive rate of {} need more than {} bits",
+                expected_items, false_positive_rate, MAX_FILTER_BITS
+            )));
+        }
+        let bits = bits as u64;
+        let hashes = ((bits as f64 / n) * ln2).round().max(1.0) as u32;
+        Ok(BloomFilter {
+            hashes,
+            bits: vec![0; bits.div_ceil(8) as usize],
+        })
+    }
+
+    /// Double hashing over the first 16 bytes of the digest, which are
+    /// already uniformly distributed.
+    fn positions(&self, digest: &[u8; 20]) -> impl Iterator<Item = usize> {
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap()) | 1;
+        let len = self.bits.len() as u64 * 8;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn insert(&mut self, digest: &[u8; 20]) {
+        for p in self.positions(digest).collect::<Vec<usize>>() {
+            self.bits[p / 8] |= 1 << (p % 8);
+        }
+    }
+
+    pub fn contains(&self, digest: &[u8; 20]) -> bool {
+        self.positions(digest)
+            .all(|p| self.bits[p / 8] & (1 << (p % 8)) != 0)
+    }
+
+    /// Builds a filter from lines starting with a hex SHA-1, such as the
+    /// `HASH:COUNT` lines of the downloadable HaveIBeenPwned list.
+    pub fn from_hash_lines(
+        reader: impl BufRead,
+        expected_items: u64,
+        false_positive_rate: f64,
+    ) -> Result<BloomFilter, BreachCheckError> {
+        let mut filter = BloomFilter::new(expected_items, false_positive_rate)?;
+        for (n, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| BreachCheckError::InvalidFilter(e.to_string()))?;
+            let hex = line.split(':').next().unwrap_or_default().trim();
+            if hex.is_empty() {
+                continue;
+            }
+            let digest = parse_sha1(hex).ok_or_else(|| {
+                BreachCheckError::InvalidFilter(format!("line {} is not a SHA-1", n + 1))
+            })?;
+            filter.insert(&digest);
+        }
+        Ok(filter)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writer.write_all(FILTER_MAGIC)?;
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        writer.write_all(&(self.bits.len() as u64 * 8).to_le_bytes())?;
+        writer.write_all(&self.bits)
+    }
+
+    pub fn read_from(mut reader: impl Read) -> Result<BloomFilter, BreachCheckError> {
+        let invalid = |e: &dyn std::fmt::Display| BreachCheckError::InvalidFilter(e.to_string());
+        let mut header = [0u8; 20];
+        reader.read_exact(&mut header).map_err(|e| invalid(&e))?;
+        if &header[0..8] != FILTER_MAGIC {
+            return Err(invalid(&"not an avtor bloom filter"));
+        }
+        let hashes = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let len = u64::from_le_bytes(header[12..20].try_into().unwrap());
+        if hashes == 0 || len == 0 || len % 8 != 0 {
+            return Err(invalid(&"bad header"));
+        }
+        let mut bits = vec![];
+        reader.read_to_end(&mut bits).map_err(|e| invalid(&e))?;
+        if bits.len() as u64 * 8 != len {
+            return Err(invalid(&format!(
+                "expected {} bytes of bits, found {}",
+                len / 8,
+                bits.len()
+            )));
+        }
+        Ok(BloomFilter { hashes, bits })
+    }
+
+    pub fn load(path: &str) -> Result<BloomFilter, BreachCheckError> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| BreachCheckError::InvalidFilter(format!("{}: {}", path, e)))?;
+        BloomFilter::read_from(std::io::BufReader::new(file))
+    }
+}
+
+fn parse_sha1(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0u8; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[async_trait]
+impl BreachChecker for BloomFilter {
+    async fn is_breached(&self, password: &Secret<String>) -> Result<bool, BreachCheckError> {
+        Ok(self.contains(&sha1_digest(password.expose_secret().as_bytes())))
+    }
+}
+
+/// Where breached passwords are looked up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BreachCheckMode {
+    #[default]
+    Off,
+    /// HaveIBeenPwned range queries, cached for an hour. Needs the
+    /// `breach-hibp` feature.
+    Online,
+    /// Only the local filter file, never the network; a missing or unreadable
+    /// filter is an error instead of letting every password through.
+    StrictOffline,
+}
+
+impl FromStr for BreachCheckMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(BreachCheckMode::Off),
+            "online" => Ok(BreachCheckMode::Online),
+            "strict-offline" => Ok(BreachCheckMode::StrictOffline),
+            _ => Err(format!("unknown breach check mode {}", s)),
+        }
+    }
+}
+
+/// The checker for `mode`. `filter_path` is only read in strict offline mode.
+pub fn breach_checker(
+    mode: BreachCheckMode,
+    filter_path: Option<&str>,
+) -> Result<Box<dyn BreachChecker>, BreachCheckError> {
+    match mode {
+        BreachCheckMode::Off => Ok(Box::new(NoBreachCheck)),
+        #[cfg(feature = "breach-hibp")]
+        BreachCheckMode::Online => Ok(Box::new(RangeChecker::new(
+            Box::new(HibpRangeApi::default()),
+            Duration::from_secs(60 * 60),
+        ))),
+        #[cfg(not(feature = "breach-hibp"))]
+        BreachCheckMode::Online => Err(BreachCheckError::Unavailable(
+            "avtor-core was built without the breach-hibp feature".into(),
+        )),
+        BreachCheckMode::StrictOffline => {
+            let path = filter_path.ok_or_else(|| {
+                BreachCheckError::InvalidFilter("strict offline mode needs a filter file".into())
+            })?;
+            Ok(Box::new(BloomFilter::load(path)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use futures::executor::block_on;
+
+    use super::{
+        sha1_hex, BloomFilter, BreachCheckError, BreachChecker, RangeChecker, RangeSource,
+    };
+    use crate::secret::Secret;
+
+    const PASSWORD_SHA1: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+
+    struct CountingSource(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl RangeSource for CountingSource {
+        async fn range(&self, prefix: &str) -> Result<String, BreachCheckError> {
+            assert_eq!(5, prefix.len());
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(format!(
+                "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n{}:3861493\r\n{}:0\r\n",
+                &PASSWORD_SHA1[5..],
+                &sha1_hex(&Secret::from("padded"))[5..]
+            ))
+        }
+    }
+
+    #[test]
+    pub fn test_range_query_matches_suffix_and_caches() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let checker = RangeChecker::new(
+            Box::new(CountingSource(requests.clone())),
+            Duration::from_secs(60),
+        );
+        assert_eq!(PASSWORD_SHA1, sha1_hex(&Secret::from("password")));
+        assert!(block_on(checker.is_breached(&Secret::from("password"))).unwrap());
+        assert!(!block_on(checker.is_breached(&Secret::from("padded"))).unwrap());
+        assert!(!block_on(checker.is_breached(&Secret::from("!Q2w3e4r5t"))).unwrap());
+        assert!(block_on(checker.is_breached(&Secret::from("password"))).unwrap());
+        assert_eq!(3, requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    pub fn test_bloom_filter_round_trip() {
+        let lines = format!("{}:3861493\n\n", PASSWORD_SHA1);
+        let filter = BloomFilter::from_hash_lines(lines.as_bytes(), 1000, 0.001).unwrap();
+        let mut file = vec![];
+        filter.write_to(&mut file).unwrap();
+        let filter = BloomFilter::read_from(file.as_slice()).unwrap();
+        assert!(block_on(filter.is_breached(&Secret::from("password"))).unwrap());
+        assert!(!block_on(filter.is_breached(&Secret::from("!Q2w3e4r5t"))).unwrap());
+        assert!(BloomFilter::read_from(&file[..file.len() - 1]).is_err());
+        assert!(BloomFilter::from_hash_lines("nope:1".as_bytes(), 10, 0.01).is_err());
+        for rate in [0.0, 1.0, -0.5, f64::NAN] {
+            assert!(BloomFilter::new(10, rate).is_err(), "{}", rate);
+        }
+        assert!(BloomFilter::new(u64::MAX, 0.001).is_err());
+        assert!(BloomFilter::new(10, f64::MIN_POSITIVE).is_ok());
+    }
+}
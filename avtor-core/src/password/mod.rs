@@ -0,0 +1,470 @@
+pub mod breach;
+
+use std::sync::OnceLock;
+
+use argon2::{
+    password_hash::{
+        rand_core::OsRng, PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString,
+    },
+    Algorithm, Argon2, Params, Version,
+};
+use scrypt::Scrypt;
+
+use crate::secret::Secret;
+
+#[derive(Debug, thiserror::Error)]
+#[error("could not hash password: {0}")]
+pub struct PasswordHashError(String);
+
+/// One password hashing algorithm. Hashes are PHC strings starting with
+/// `$<algorithm>$` so the algorithm that made a stored hash can be told apart
+/// from the others, see `PasswordHashers`.
+pub trait PasswordHasher: Send + Sync {
+    /// PHC identifier written at the start of every hash, e.g. `argon2id`.
+    fn algorithm(&self) -> &'static str;
+
+    fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError>;
+
+    fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool;
+
+    /// Whether hashes tagged `algorithm` are this hasher's. Only differs from
+    /// `algorithm()` for algorithms with several tags.
+    fn recognizes(&self, algorithm: &str) -> bool {
+        algorithm == self.algorithm()
+    }
+}
+
+/// Argon2id, by default with the argon2 crate's recommended cost.
+#[derive(Debug, Clone, Default)]
+pub struct Argon2Hasher {
+    params: Params,
+}
+
+impl Argon2Hasher {
+    /// `memory_kib` of memory, `iterations` passes and `parallelism` lanes.
+    pub fn new(
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Result<Argon2Hasher, PasswordHashError> {
+        let params = Params::new(memory_kib, iterations, parallelism, None)
+            .map_err(|e| PasswordHashError(e.to_string()))?;
+        Ok(Argon2Hasher { params })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordHasher for Argon2Hasher {
+    fn algorithm(&self) -> &'static str {
+        "argon2id"
+    }
+
+    fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()
+            .hash_password(password.expose_secret().as_bytes(), &salt)
+            .map(|hash| Secret::new(hash.to_string()))
+            .map_err(|e| PasswordHashError(e.to_string()))
+    }
+
+    /// Uses the cost stored in `hash`, not `self.params`, so hashes made
+    /// before a cost change still verify.
+    fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool {
+        match PasswordHash::new(hash.expose_secret()) {
+            Ok(parsed) => self
+                .argon2()
+                .verify_password(password.expose_secret().as_bytes(), &parsed)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// bcrypt, for verifying hashes carried over from other systems. Only the
+/// first 72 bytes of a password count, so prefer the others for new hashes.
+#[derive(Debug, Clone)]
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl Default for BcryptHasher {
+    fn default() -> Self {
+        BcryptHasher {
+            cost: bcrypt::DEFAULT_COST,
+        }
+    }
+}
+
+impl BcryptHasher {
+    /// `cost` is the base 2 logarithm of the number of rounds, 4 to 31.
+    pub fn new(cost: u32) -> BcryptHasher {
+        BcryptHasher { cost }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn algorithm(&self) -> &'static str {
+        "2b"
+    }
+
+    fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+        bcrypt::hash(password.expose_secret(), self.cost)
+            .map(Secret::new)
+            .map_err(|e| PasswordHashError(e.to_string()))
+    }
+
+    fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool {
+        bcrypt::verify(password.expose_secret(), hash.expose_secret()).unwrap_or(false)
+    }
+
+    /// Older bcrypt implementations wrote `2a`, `2x` or `2y`.
+    fn recognizes(&self, algorithm: &str) -> bool {
+        matches!(algorithm, "2a" | "2b" | "2x" | "2y")
+    }
+}
+
+/// scrypt, by default with the scrypt crate's recommended cost.
+#[derive(Debug, Clone, Default)]
+pub struct ScryptHasher {
+    params: scrypt::Params,
+}
+
+impl ScryptHasher {
+    /// `2^log_n` iterations, block size `r` and parallelism `p`.
+    pub fn new(log_n: u8, r: u32, p: u32) -> Result<ScryptHasher, PasswordHashError> {
+        let params = scrypt::Params::new(log_n, r, p, scrypt::Params::RECOMMENDED_LEN)
+            .map_err(|e| PasswordHashError(e.to_string()))?;
+        Ok(ScryptHasher { params })
+    }
+}
+
+impl PasswordHasher for ScryptHasher {
+    fn algorithm(&self) -> &'static str {
+        "scrypt"
+    }
+
+    fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Scrypt
+            .hash_password_customized(
+                password.expose_secret().as_bytes(),
+                None,
+                None,
+                self.params,
+                &salt,
+            )
+            .map(|hash| Secret::new(hash.to_string()))
+            .map_err(|e| PasswordHashError(e.to_string()))
+    }
+
+    /// Uses the cost stored in `hash`.
+    fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool {
+        match PasswordHash::new(hash.expose_secret()) {
+            Ok(parsed) => Scrypt
+                .verify_password(password.expose_secret().as_bytes(), &parsed)
+                .is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// A key management service that computes MACs with a key that never leaves
+/// it. Implement it for a cloud KMS or an HSM to use `KmsHasher`.
+pub trait PasswordKms: Send + Sync {
+    /// MAC of `data` under the key named `key_id`.
+    fn mac(&self, key_id: &str, data: &[u8]) -> Result<Vec<u8>, PasswordHashError>;
+}
+
+/// Peppers passwords with a KMS held key before hashing them with `inner`,
+/// so a leaked database alone isn't enough to guess passwords offline.
+/// Hashes look like `$kms$<key id>$<inner hash>`, which keeps verifying
+/// after the key is rotated to a new id as long as the old key exists.
+pub struct KmsHasher {
+    kms: Box<dyn PasswordKms>,
+    key_id: String,
+    inner: Box<dyn PasswordHasher>,
+}
+
+impl KmsHasher {
+    /// `key_id` must not contain `$`.
+    pub fn new(
+        kms: Box<dyn PasswordKms>,
+        key_id: &str,
+        inner: Box<dyn PasswordHasher>,
+    ) -> KmsHasher {
+        KmsHasher {
+            kms,
+            key_id: key_id.to_string(),
+            inner,
+        }
+    }
+
+    fn peppered(
+        &self,
+        key_id: &str,
+        password: &Secret<String>,
+    ) -> Result<Secret<String>, PasswordHashError> {
+        let mac = self.kms.mac(key_id, password.expose_secret().as_bytes())?;
+        Ok(Secret::new(
+            mac.iter().map(|b| format!("{:02x}", b)).collect(),
+        ))
+    }
+}
+
+impl PasswordHasher for KmsHasher {
+    fn algorithm(&self) -> &'static str {
+        "kms"
+    }
+
+    fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+        let inner = self.inner.hash(&self.peppered(&self.key_id, password)?)?;
+        Ok(Secret::new(format!(
+            "$kms${}{}",
+            self.key_id,
+            inner.expose_secret()
+        )))
+    }
+
+    fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool {
+        let rest = match hash.expose_secret().strip_prefix("$kms$") {
+            Some(rest) => rest,
+            None => return false,
+        };
+        let (key_id, inner) = match rest.find('$') {
+            Some(i) => (&rest[..i], Secret::new(rest[i..].to_string())),
+            None => return false,
+        };
+        match self.peppered(key_id, password) {
+            Ok(peppered) => self.inner.verify(&peppered, &inner),
+            Err(_) => false,
+        }
+    }
+}
+
+/// The hasher configured by `name`: `argon2id`, `bcrypt` or `scrypt`, each
+/// with its default cost.
+pub fn hasher_named(name: &str) -> Result<Box<dyn PasswordHasher>, PasswordHashError> {
+    match name {
+        "argon2id" => Ok(Box::new(Argon2Hasher::default())),
+        "bcrypt" => Ok(Box::new(BcryptHasher::default())),
+        "scrypt" => Ok(Box::new(ScryptHasher::default())),
+        _ => Err(PasswordHashError(format!(
+            "unknown password hasher {}",
+            name
+        ))),
+    }
+}
+
+/// The `algorithm` of a PHC string, `None` for anything else.
+pub fn algorithm_of(hash: &Secret<String>) -> Option<&str> {
+    let mut parts = hash.expose_secret().split('$');
+    match (parts.next(), parts.next()) {
+        (Some(""), Some(algorithm)) if !algorithm.is_empty() => Some(algorithm),
+        _ => None,
+    }
+}
+
+/// Hashes new passwords with one algorithm while still verifying hashes made
+/// by the others, so stored hashes can move to a new algorithm as users log
+/// in.
+pub struct PasswordHashers {
+    default: Box<dyn PasswordHasher>,
+    previous: Vec<Box<dyn PasswordHasher>>,
+}
+
+impl Default for PasswordHashers {
+    fn default() -> Self {
+        PasswordHashers::new(Box::new(Argon2Hasher::default()))
+    }
+}
+
+impl PasswordHashers {
+    pub fn new(default: Box<dyn PasswordHasher>) -> PasswordHashers {
+        PasswordHashers {
+            default,
+            previous: vec![],
+        }
+    }
+
+    /// `default` for new hashes, also accepting hashes made by the
+    /// `accepted` ones; names as for `hasher_named`.
+    pub fn from_names(
+        default: &str,
+        accepted: &[&str],
+    ) -> Result<PasswordHashers, PasswordHashError> {
+        let mut hashers = PasswordHashers::new(hasher_named(default)?);
+        for name in accepted.iter().filter(|name| **name != default) {
+            hashers = hashers.with_previous(hasher_named(name)?);
+        }
+        Ok(hashers)
+    }
+
+    /// Also accept hashes made by `hasher`, without using it for new ones.
+    pub fn with_previous(mut self, hasher: Box<dyn PasswordHasher>) -> PasswordHashers {
+        self.previous.push(hasher);
+        self
+    }
+
+    pub fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+        self.default.hash(password)
+    }
+
+    /// Verifies with the hasher named by the hash's algorithm tag; false when
+    /// no configured hasher has that tag.
+    pub fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool {
+        let algorithm = match algorithm_of(hash) {
+            Some(algorithm) => algorithm,
+            None => return false,
+        };
+        std::iter::once(&self.default)
+            .chain(&self.previous)
+            .find(|h| h.recognizes(algorithm))
+            .is_some_and(|h| h.verify(password, hash))
+    }
+
+    /// True when `hash` was not made by the default algorithm and should be
+    /// replaced after the next successful verify.
+    pub fn needs_rehash(&self, hash: &Secret<String>) -> bool {
+        !algorithm_of(hash).is_some_and(|algorithm| self.default.recognizes(algorithm))
+    }
+}
+
+static INSTALLED_HASHERS: OnceLock<PasswordHashers> = OnceLock::new();
+
+/// Makes `hashers` the ones `hash_password`, `verify_password` and
+/// `needs_rehash` use for the rest of the process, e.g. as configured at
+/// startup. Only the first call has an effect; it returns whether it was the
+/// first.
+pub fn install_hashers(hashers: PasswordHashers) -> bool {
+    INSTALLED_HASHERS.set(hashers).is_ok()
+}
+
+fn installed_hashers() -> &'static PasswordHashers {
+    INSTALLED_HASHERS.get_or_init(PasswordHashers::default)
+}
+
+/// A PHC string (`$argon2id$v=19$...` unless other hashers were installed)
+/// that carries its own salt and parameters.
+pub fn hash_password(password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+    installed_hashers().hash(password)
+}
+
+/// False for a wrong password and for anything that isn't a PHC string,
+/// including `LOCKED_PASSWORD`.
+pub fn verify_password(password: &Secret<String>, hash: &Secret<String>) -> bool {
+    installed_hashers().verify(password, hash)
+}
+
+/// See `PasswordHashers::needs_rehash`.
+pub fn needs_rehash(hash: &Secret<String>) -> bool {
+    installed_hashers().needs_rehash(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        algorithm_of, hash_password, verify_password, BcryptHasher, KmsHasher, PasswordHashError,
+        PasswordHasher, PasswordHashers, PasswordKms, ScryptHasher,
+    };
+    use crate::models::users::LOCKED_PASSWORD;
+    use crate::secret::Secret;
+
+    /// Stands in for an older algorithm; never use anything like it for real.
+    struct Reversed;
+
+    impl PasswordHasher for Reversed {
+        fn algorithm(&self) -> &'static str {
+            "reversed"
+        }
+
+        fn hash(&self, password: &Secret<String>) -> Result<Secret<String>, PasswordHashError> {
+            let reversed: String = password.expose_secret().chars().rev().collect();
+            Ok(Secret::new(format!("$reversed${}", reversed)))
+        }
+
+        fn verify(&self, password: &Secret<String>, hash: &Secret<String>) -> bool {
+            self.hash(password).is_ok_and(|h| &h == hash)
+        }
+    }
+
+    #[test]
+    pub fn test_hash_and_verify() {
+        let password = Secret::from("!Q2w3e4r5t");
+        let hash = hash_password(&password).unwrap();
+        assert!(hash.expose_secret().starts_with("$argon2id$"));
+        assert_ne!(hash, hash_password(&password).unwrap());
+        assert!(verify_password(&password, &hash));
+        assert!(!verify_password(&Secret::from("wrong"), &hash));
+        assert!(!verify_password(&password, &Secret::from(LOCKED_PASSWORD)));
+    }
+
+    #[test]
+    pub fn test_mixed_algorithms() {
+        let password = Secret::from("!Q2w3e4r5t");
+        let old_hash = Reversed.hash(&password).unwrap();
+        assert_eq!(Some("reversed"), algorithm_of(&old_hash));
+        assert!(!verify_password(&password, &old_hash));
+
+        let hashers = PasswordHashers::default().with_previous(Box::new(Reversed));
+        assert!(hashers.verify(&password, &old_hash));
+        assert!(!hashers.verify(&Secret::from("wrong"), &old_hash));
+        assert!(hashers.needs_rehash(&old_hash));
+
+        let new_hash = hashers.hash(&password).unwrap();
+        assert_eq!(Some("argon2id"), algorithm_of(&new_hash));
+        assert!(!hashers.needs_rehash(&new_hash));
+    }
+
+    /// Prefixes data with the key id; a real KMS would MAC it.
+    struct FakeKms;
+
+    impl PasswordKms for FakeKms {
+        fn mac(&self, key_id: &str, data: &[u8]) -> Result<Vec<u8>, PasswordHashError> {
+            Ok([key_id.as_bytes(), data].concat())
+        }
+    }
+
+    #[test]
+    pub fn test_configured_hashers_verify_each_other() {
+        let password = Secret::from("!Q2w3e4r5t");
+        let bcrypt_hash = BcryptHasher::new(4).hash(&password).unwrap();
+        let scrypt_hash = ScryptHasher::new(4, 8, 1).unwrap().hash(&password).unwrap();
+        assert_eq!(Some("2b"), algorithm_of(&bcrypt_hash));
+        assert_eq!(Some("scrypt"), algorithm_of(&scrypt_hash));
+
+        let hashers = PasswordHashers::from_names("scrypt", &["bcrypt", "argon2id"]).unwrap();
+        assert!(hashers.verify(&password, &bcrypt_hash));
+        assert!(hashers.verify(&password, &scrypt_hash));
+        assert!(!hashers.verify(&Secret::from("wrong"), &bcrypt_hash));
+        assert!(hashers.needs_rehash(&bcrypt_hash));
+        assert!(!hashers.needs_rehash(&scrypt_hash));
+        assert!(!PasswordHashers::from_names("argon2id", &[])
+            .unwrap()
+            .verify(&password, &bcrypt_hash));
+        assert!(PasswordHashers::from_names("md5", &[]).is_err());
+
+        let legacy = Secret::new(bcrypt_hash.expose_secret().replacen("$2b$", "$2y$", 1));
+        assert!(hashers.verify(&password, &legacy));
+    }
+
+    #[test]
+    pub fn test_kms_hasher_peppers_the_inner_hash() {
+        let password = Secret::from("!Q2w3e4r5t");
+        let kms = |key_id: &str| {
+            KmsHasher::new(Box::new(FakeKms), key_id, Box::new(BcryptHasher::new(4)))
+        };
+        let hash = kms("key-1").hash(&password).unwrap();
+        assert!(
+            hash.expose_secret().starts_with("$kms$key-1$2b$"),
+            "{:?}",
+            hash.expose_secret()
+        );
+        assert_eq!(Some("kms"), algorithm_of(&hash));
+        assert!(kms("key-2").verify(&password, &hash));
+        assert!(!kms("key-1").verify(&Secret::from("wrong"), &hash));
+        assert!(!BcryptHasher::new(4).verify(&password, &hash));
+    }
+}
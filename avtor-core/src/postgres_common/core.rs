@@ -1,13 +1,28 @@
-use anyhow::Error;
+use crate::error::DbError;
+
+use super::fault;
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
 extern crate core;
 extern crate proc_macro;
 use futures::{
-    future::BoxFuture, stream::Iter, FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
+    future::{self, BoxFuture},
+    stream::Iter,
+    FutureExt, Stream, StreamExt, TryFutureExt, TryStreamExt,
 };
-use tokio_postgres::{types::ToSql, Client, Row, RowStream, Statement, Transaction};
+use tokio_postgres::{types::ToSql, Client, GenericClient, Row, RowStream, Statement, Transaction};
+
+/// Entities declare their columns with `#[derive(Entity)]`, see avtor-derive.
+pub use avtor_derive::Entity;
+/// For the code `#[derive(Entity)]` generates in crates that don't depend on
+/// tokio-postgres themselves.
+#[doc(hidden)]
+pub use tokio_postgres;
 
 trait MyTransaction<'a> {
-    fn prepare(query: &str) -> BoxFuture<'a, Result<Statement, Error>>;
+    fn prepare(query: &str) -> BoxFuture<'a, Result<Statement, DbError>>;
 }
 
 pub fn create_insert_sql(table: &String, id_field: &String, fields: &[String]) -> String {
@@ -22,30 +37,40 @@ pub fn create_insert_sql(table: &String, id_field: &String, fields: &[String]) -
     )
 }
 
-pub fn create_update_sql(table: &String, id_field: &String, fields: &[String]) -> String {
-    let (head, tail) = fields.split_at(1);
-    let first = format!("{} = $1", head.first().unwrap());
+pub fn create_update_sql(
+    table: &String,
+    id_field: &String,
+    fields: &[String],
+) -> Result<String, DbError> {
+    let Some((head, tail)) = fields.split_first() else {
+        return Err(DbError::InvalidQuery(format!(
+            "nothing to update in {}, every column but {} is read only",
+            table, id_field
+        )));
+    };
+    let first = format!("{} = $1", head);
     let (fields_sql, _) = tail.into_iter().fold((first, 2), |acc, x| {
         let (q, i) = acc;
         (format!("{} , {} = ${}", q, x, i.to_string()), i + 1)
     });
-    format!(
+    Ok(format!(
         "update {} set {} where {} = ${}",
         table,
         fields_sql,
         id_field,
         fields.len() + 1
-    )
+    ))
 }
 
-pub async fn insert<'a>(
-    client: &Transaction<'a>,
+pub async fn insert<C: GenericClient>(
+    client: &C,
     table: &String,
     id_field: &String,
     fields: &[String],
     id_param: &(dyn ToSql + Sync),
     params: &[&(dyn ToSql + Sync)],
-) -> Result<(), Error> {
+) -> Result<(), DbError> {
+    fault::inject().await?;
     let insert_sql = create_insert_sql(table, id_field, fields);
     let stmt = client.prepare(&insert_sql).await?;
     let all_params = &[&[id_param], params].concat();
@@ -53,15 +78,111 @@ pub async fn insert<'a>(
     Ok(())
 }
 
-pub async fn update(
-    client: &Client,
+pub fn create_upsert_sql(table: &String, id_field: &String, fields: &[String]) -> String {
+    let insert_sql = create_insert_sql(table, id_field, fields);
+    if fields.is_empty() {
+        return format!("{} on conflict ({}) do nothing", insert_sql, id_field);
+    }
+    let set_sql = fields
+        .iter()
+        .map(|f| format!("{} = excluded.{}", f, f))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "{} on conflict ({}) do update set {}",
+        insert_sql, id_field, set_sql
+    )
+}
+
+pub async fn upsert<C: GenericClient>(
+    client: &C,
+    table: &String,
+    id_field: &String,
+    fields: &[String],
+    id_param: &(dyn ToSql + Sync),
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<(), DbError> {
+    fault::inject().await?;
+    let upsert_sql = create_upsert_sql(table, id_field, fields);
+    let stmt = client.prepare(&upsert_sql).await?;
+    let all_params = &[&[id_param], params].concat();
+    client.execute(&stmt, all_params.as_slice()).await?;
+    Ok(())
+}
+
+/// Postgres rejects statements binding more than this many parameters.
+pub const MAX_QUERY_PARAMS: usize = 65535;
+
+pub fn create_insert_many_sql(
+    table: &String,
+    id_field: &String,
+    fields: &[String],
+    row_count: usize,
+) -> String {
+    let fields_sql: String = fields
+        .iter()
+        .fold(id_field.clone(), |acc, x| format!("{}, {}", acc, x));
+    let column_count = fields.len() + 1;
+    let values_sql = (0..row_count)
+        .map(|row| {
+            let first = row * column_count + 1;
+            let row_params = (first + 1..first + column_count)
+                .fold(format!("${}", first), |acc, x| format!("{}, ${}", acc, x));
+            format!("({})", row_params)
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "insert into {} ({}) values {}",
+        table, fields_sql, values_sql
+    )
+}
+
+/// Inserts `rows` with multi-row `VALUES` statements, chunked so no statement
+/// exceeds `MAX_QUERY_PARAMS`. Each row holds the id param followed by the
+/// params for `fields`, in the same order as `insert`. The chunks go in one
+/// transaction, a savepoint when `client` is a transaction already, so
+/// either every row is inserted or none is.
+pub async fn insert_many<C: GenericClient>(
+    client: &mut C,
+    table: &String,
+    id_field: &String,
+    fields: &[String],
+    rows: &[Vec<&(dyn ToSql + Sync)>],
+) -> Result<u64, DbError> {
+    fault::inject().await?;
+    let column_count = fields.len() + 1;
+    if let Some(bad) = rows.iter().find(|r| r.len() != column_count) {
+        return Err(DbError::InvalidQuery(format!(
+            "expected {} params per row for {} but got {}",
+            column_count,
+            table,
+            bad.len()
+        )));
+    }
+    let rows_per_chunk = MAX_QUERY_PARAMS / column_count;
+    let trans = client.transaction().await?;
+    let mut inserted = 0;
+    for chunk in rows.chunks(rows_per_chunk.max(1)) {
+        let insert_sql = create_insert_many_sql(table, id_field, fields, chunk.len());
+        let stmt = trans.prepare(&insert_sql).await?;
+        let all_params = chunk.concat();
+        inserted += trans.execute(&stmt, all_params.as_slice()).await?;
+    }
+    trans.commit().await?;
+    Ok(inserted)
+}
+
+pub async fn update<C: GenericClient>(
+    client: &C,
     table: &String,
     id_field: &String,
     fields: &[String],
     id_param: &(dyn ToSql + Sync),
     params: &[&(dyn ToSql + Sync)],
-) -> Result<(), Error> {
-    let update_sql = create_update_sql(table, id_field, fields);
+) -> Result<(), DbError> {
+    fault::inject().await?;
+    let update_sql = create_update_sql(table, id_field, fields)?;
     let stmt = client.prepare(&update_sql).await?;
     let all_params = &[params, &[id_param]].concat();
     client.execute(&stmt, all_params.as_slice()).await?;
@@ -71,6 +192,17 @@ pub async fn update(
 pub type Field = String;
 pub type Value = (dyn ToSql + Sync);
 
+/// A row that could not be mapped onto an entity, naming the column that failed.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to decode column {column}: {source}")]
+pub struct RowDecodeError {
+    pub column: String,
+    #[source]
+    pub source: tokio_postgres::Error,
+}
+
+/// `In` and `Nin` take a single array-typed value (e.g. a `Vec<T>`), bound as
+/// one Postgres array parameter.
 pub enum QueryCondition<'a> {
     Eq(Field, &'a Value),
     Neq(Field, &'a Value),
@@ -82,6 +214,11 @@ pub enum QueryCondition<'a> {
     Nin(Field, &'a Value),
     Like(Field, &'a Value),
     NLike(Field, &'a Value),
+    IsNull(Field),
+    IsNotNull(Field),
+    Between(Field, &'a Value, &'a Value),
+    ILike(Field, &'a Value),
+    NotILike(Field, &'a Value),
 }
 
 pub fn query_cond_to_string(q_cond: &QueryCondition, n: i32) -> String {
@@ -92,242 +229,787 @@ pub fn query_cond_to_string(q_cond: &QueryCondition, n: i32) -> String {
         QueryCondition::Gte(f, _) => format!("{} >= ${}", f, n.to_string()),
         QueryCondition::Lt(f, _) => format!("{} <= ${}", f, n.to_string()),
         QueryCondition::Lte(f, _) => format!("{} <= ${}", f, n.to_string()),
-        QueryCondition::In(f, _) => format!("{} = Any(${})", f, n.to_string()),
-        QueryCondition::Nin(f, _) => format!("{} != Any(${})", f, n.to_string()),
+        QueryCondition::In(f, _) => format!("{} = any(${})", f, n),
+        QueryCondition::Nin(f, _) => format!("{} <> all(${})", f, n),
         QueryCondition::Like(f, _) => format!("{} like ${}", f, n.to_string()),
         QueryCondition::NLike(f, _) => format!("{} not like ${}", f, n.to_string()),
+        QueryCondition::IsNull(f) => format!("{} is null", f),
+        QueryCondition::IsNotNull(f) => format!("{} is not null", f),
+        QueryCondition::Between(f, _, _) => format!("{} between ${} and ${}", f, n, n + 1),
+        QueryCondition::ILike(f, _) => format!("{} ilike ${}", f, n),
+        QueryCondition::NotILike(f, _) => format!("{} not ilike ${}", f, n),
+    }
+}
+
+/// The values bound for a condition, in placeholder order.
+pub fn query_cond_params<'a>(q_cond: &QueryCondition<'a>) -> Vec<&'a Value> {
+    match q_cond {
+        QueryCondition::Eq(_, p) => vec![*p],
+        QueryCondition::Neq(_, p) => vec![*p],
+        QueryCondition::Gt(_, p) => vec![*p],
+        QueryCondition::Gte(_, p) => vec![*p],
+        QueryCondition::Lt(_, p) => vec![*p],
+        QueryCondition::Lte(_, p) => vec![*p],
+        QueryCondition::In(_, p) => vec![*p],
+        QueryCondition::Nin(_, p) => vec![*p],
+        QueryCondition::Like(_, p) => vec![*p],
+        QueryCondition::NLike(_, p) => vec![*p],
+        QueryCondition::IsNull(_) => vec![],
+        QueryCondition::IsNotNull(_) => vec![],
+        QueryCondition::Between(_, from, to) => vec![*from, *to],
+        QueryCondition::ILike(_, p) => vec![*p],
+        QueryCondition::NotILike(_, p) => vec![*p],
     }
 }
 
 trait NewTrait: ToSql + Sized + Sync {}
 
+/// Wraps a criteria value so the entity macro can tell a `None` apart from
+/// every other value: `(&NullCheck(x)).is_none_value()` picks the `Option`
+/// impl when `x` is an `Option` and falls back to the always false one.
+pub struct NullCheck<'a, T>(pub &'a T);
+
+pub trait OptionNullCheck {
+    fn is_none_value(&self) -> bool;
+}
+
+impl<'a, T> OptionNullCheck for NullCheck<'a, Option<T>> {
+    fn is_none_value(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+pub trait ValueNullCheck {
+    fn is_none_value(&self) -> bool;
+}
+
+impl<'a, 'b, T> ValueNullCheck for &'b NullCheck<'a, T> {
+    fn is_none_value(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort {
+    pub field: Field,
+    pub direction: SortDirection,
+}
+
+/// Only plain (optionally schema-qualified) identifiers are allowed where a
+/// column or table name is interpolated into SQL rather than bound as a param.
+pub fn is_valid_identifier(ident: &str) -> bool {
+    !ident.is_empty()
+        && ident.split('.').all(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+                }
+                _ => false,
+            }
+        })
+}
+
+pub fn validate_identifier(ident: &str) -> Result<(), DbError> {
+    if is_valid_identifier(ident) {
+        Ok(())
+    } else {
+        Err(DbError::InvalidQuery(format!(
+            "invalid sql identifier: {}",
+            ident
+        )))
+    }
+}
+
+pub fn sorts_to_string(sorts: &[Sort]) -> Result<String, DbError> {
+    if sorts.is_empty() {
+        return Ok("".to_string());
+    }
+    let parts = sorts
+        .iter()
+        .map(|s| {
+            validate_identifier(&s.field)?;
+            let direction = match s.direction {
+                SortDirection::Asc => "asc",
+                SortDirection::Desc => "desc",
+            };
+            Ok(format!("{} {}", s.field, direction))
+        })
+        .collect::<Result<Vec<String>, DbError>>()?;
+    Ok(format!(" order by {}", parts.join(", ")))
+}
+
+/// Limit and offset are plain integers, so they are rendered inline rather than
+/// bound, keeping the returned params tied to the query conditions only.
+pub fn limit_offset_to_string(limit: Option<i64>, offset: Option<i64>) -> Result<String, DbError> {
+    let limit_part = match limit {
+        Some(l) if l < 0 => {
+            return Err(DbError::InvalidQuery(format!(
+                "limit must not be negative: {}",
+                l
+            )))
+        }
+        Some(l) => format!(" limit {}", l),
+        None => "".to_string(),
+    };
+    let offset_part = match offset {
+        Some(o) if o < 0 => {
+            return Err(DbError::InvalidQuery(format!(
+                "offset must not be negative: {}",
+                o
+            )))
+        }
+        Some(o) => format!(" offset {}", o),
+        None => "".to_string(),
+    };
+    Ok(format!("{}{}", limit_part, offset_part))
+}
+
+/// Empty `columns` selects `*`; otherwise exactly the given (validated) columns,
+/// typically `Entity::field_names()` or a narrower projection.
+pub fn columns_to_string(columns: &[&str]) -> Result<String, DbError> {
+    if columns.is_empty() {
+        return Ok("*".to_string());
+    }
+    for column in columns {
+        validate_identifier(column)?;
+    }
+    Ok(columns.join(", "))
+}
+
 pub fn generate_select<'a>(
     table: &String,
+    columns: &[&str],
     query_conditions: &'a Vec<QueryCondition<'a>>,
-) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
-    let base_query = format!("select * from {}", table);
+    sorts: &[Sort],
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<(String, Vec<&'a (dyn ToSql + Sync)>), DbError> {
+    let base_query = format!("select {} from {}", columns_to_string(columns)?, table);
+    let order_part = format!(
+        "{}{}",
+        sorts_to_string(sorts)?,
+        limit_offset_to_string(limit, offset)?
+    );
     if query_conditions.is_empty() {
-        (base_query, vec![])
+        Ok((format!("{}{}", base_query, order_part), vec![]))
     } else {
-        let (where_part, _) = query_conditions
-            .into_iter()
-            .fold(("".to_string(), 1), |acc, x| {
-                let (q, i) = acc;
-                (format!("{} and {}", q, query_cond_to_string(x, i)), i + 1)
-            });
-        let query_with_where = format!("{} where 1 = 1 {}", base_query, where_part);
-        let params = query_conditions
-            .into_iter()
-            .map(|x| match x {
-                QueryCondition::Eq(_, p) => *p,
-                QueryCondition::Neq(_, p) => *p,
-                QueryCondition::Gt(_, p) => *p,
-                QueryCondition::Gte(_, p) => *p,
-                QueryCondition::Lt(_, p) => *p,
-                QueryCondition::Lte(_, p) => *p,
-                QueryCondition::In(_, p) => *p,
-                QueryCondition::Nin(_, p) => *p,
-                QueryCondition::Like(_, p) => *p,
-                QueryCondition::NLike(_, p) => *p,
-            })
-            .collect();
-        (query_with_where, params)
+        let (where_part, params) = generate_where(query_conditions);
+        let query_with_where = format!("{} where 1 = 1 {}{}", base_query, where_part, order_part);
+        Ok((query_with_where, params))
     }
 }
 
-pub async fn select_all<'a, F: Fn(Row) -> A + Send + 'static, A>(
-    client: &Client,
+pub fn generate_where<'a>(
+    query_conditions: &'a Vec<QueryCondition<'a>>,
+) -> (String, Vec<&'a (dyn ToSql + Sync)>) {
+    let (where_part, _) = query_conditions
+        .into_iter()
+        .fold(("".to_string(), 1), |acc, x| {
+            let (q, i) = acc;
+            let next = i + query_cond_params(x).len() as i32;
+            (format!("{} and {}", q, query_cond_to_string(x, i)), next)
+        });
+    let params = query_conditions
+        .into_iter()
+        .flat_map(query_cond_params)
+        .collect();
+    (where_part, params)
+}
+
+/// Deleting without conditions would wipe the table, so at least one condition
+/// is required.
+pub fn create_delete_sql<'a>(
     table: &String,
+    query_conditions: &'a Vec<QueryCondition<'a>>,
+) -> Result<(String, Vec<&'a (dyn ToSql + Sync)>), DbError> {
+    if query_conditions.is_empty() {
+        return Err(DbError::InvalidQuery(format!(
+            "refusing to delete from {} without conditions",
+            table
+        )));
+    }
+    let (where_part, params) = generate_where(query_conditions);
+    Ok((
+        format!("delete from {} where 1 = 1 {}", table, where_part),
+        params,
+    ))
+}
+
+pub async fn delete<'a, 'b>(
+    client: &Transaction<'a>,
+    table: &String,
+    query_conditions: &Vec<QueryCondition<'b>>,
+) -> Result<u64, DbError> {
+    fault::inject().await?;
+    let (delete_sql, params) = create_delete_sql(table, query_conditions)?;
+    let stmt = client.prepare(&delete_sql).await?;
+    let deleted = client.execute(&stmt, params.as_slice()).await?;
+    Ok(deleted)
+}
+
+pub async fn delete_by_id<'a>(
+    client: &Transaction<'a>,
+    table: &String,
+    id_field: &String,
+    id_param: &Value,
+) -> Result<u64, DbError> {
+    let conds = vec![QueryCondition::Eq(id_field.clone(), id_param)];
+    delete(client, table, &conds).await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn select_all<'a, C, F, A, E>(
+    client: &C,
+    table: &String,
+    columns: &[&str],
     query_conditions: &Vec<QueryCondition<'a>>,
+    sorts: &[Sort],
+    limit: Option<i64>,
+    offset: Option<i64>,
     map_row: F,
-) -> Result<Vec<A>, Error> {
-    let (query, params) = generate_select(table, query_conditions);
+) -> Result<Vec<A>, DbError>
+where
+    C: GenericClient,
+    F: Fn(Row) -> Result<A, E> + Send + 'static,
+    E: Into<DbError>,
+{
+    fault::inject().await?;
+    let (query, params) = generate_select(table, columns, query_conditions, sorts, limit, offset)?;
     let stmt = client.prepare(&query).await?;
     let rows = client.query(&stmt, params.as_slice()).await?;
-    Ok(rows.into_iter().map(map_row).collect())
+    rows.into_iter()
+        .map(|row| map_row(row).map_err(Into::into))
+        .collect()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn select_raw<'a, F: Fn(Result<Row, tokio_postgres::Error>) -> A + Send + 'static, A>(
     client: &Client,
     table: &String,
+    columns: &[&str],
     query_conditions: &Vec<QueryCondition<'a>>,
+    sorts: &[Sort],
+    limit: Option<i64>,
+    offset: Option<i64>,
     map_row: F,
-) -> Result<impl Stream<Item = A>, Error> {
-    let (query, params) = generate_select(table, query_conditions);
+) -> Result<impl Stream<Item = A>, DbError> {
+    fault::inject().await?;
+    let (query, params) = generate_select(table, columns, query_conditions, sorts, limit, offset)?;
     let stmt = client.prepare(&query).await?;
     let rows = client.query_raw(&stmt, params.into_iter()).await?;
     Ok(rows.map(map_row))
 }
 
-/*
-pub async fn select_all_stream<'a, A, F: Fn(RowStream) -> A + Send + 'static>(
-    client: &Client,
+/// Streams mapped rows from either a `Client` or a `Transaction`. Unlike
+/// `select_raw`, row and decode errors end up in the stream instead of being
+/// handed to the mapper.
+#[allow(clippy::too_many_arguments)]
+pub async fn select_all_stream<'a, C, F, A, E>(
+    client: &C,
     table: &String,
+    columns: &[&str],
     query_conditions: &Vec<QueryCondition<'a>>,
+    sorts: &[Sort],
+    limit: Option<i64>,
+    offset: Option<i64>,
     map_row: F,
-) -> Result<impl Stream<Item = Result<A, Error>>> {
-    let (query, params) = generate_select(table, query_conditions);
+) -> Result<impl Stream<Item = Result<A, DbError>>, DbError>
+where
+    C: GenericClient,
+    F: Fn(Row) -> Result<A, E> + Send + 'static,
+    E: Into<DbError>,
+{
+    fault::inject().await?;
+    let (query, params) = generate_select(table, columns, query_conditions, sorts, limit, offset)?;
     let stmt = client.prepare(&query).await?;
-    client.query_raw(&stmt, params.into_iter()).map_ok(map_row).into_stream()
-    let rows = client.query_raw(&stmt, params.into_iter()).await?;
-    rows.map_ok(map_row)
+    let rows = client.query_raw(&stmt, params).await?;
+    Ok(rows
+        .map_err(DbError::from)
+        .and_then(move |row| future::ready(map_row(row).map_err(Into::into))))
 }
-*/
 
-pub async fn select<'a, F: Fn(Row) -> A + Send + 'static, A>(
+pub async fn select<'a, F, A, E>(
     client: &Transaction<'a>,
     table: &String,
+    columns: &[&str],
     query_conditions: &'a Vec<QueryCondition<'a>>,
     from_row: F,
-) -> Result<Option<A>, Error> {
-    let (query, params) = generate_select(table, query_conditions);
+) -> Result<Option<A>, DbError>
+where
+    F: Fn(Row) -> Result<A, E> + Send + 'static,
+    E: Into<DbError>,
+{
+    fault::inject().await?;
+    let (query, params) = generate_select(table, columns, query_conditions, &[], None, None)?;
     let stmt = client.prepare(&query).await?;
     let row_opt = client.query_opt(&stmt, params.as_slice()).await?;
-    Ok(row_opt.map(from_row))
-}
-
-macro_rules! entity {
-    (
-        $(#[$struct_meta:meta])*
-        pub struct $name:ident {
-            $(
-                $(#[$field_meta:meta])*
-                $field_vis:vis $field_name:ident : $field_type:ty
-            ),*$(,)+
-    }) => {
-
-        $(#[$struct_meta])*
-        pub struct $name {
-            $(
-                $(#[$field_meta])*
-                pub $field_name : $field_type,
-            )*
-        }
+    row_opt.map(from_row).transpose().map_err(Into::into)
+}
 
-        paste::paste! {
-            #[derive(Debug)]
-            enum [<$name Fields>] {
-                $([<$field_name:camel>]),*
-            }
+/// Opaque position in a keyset-paginated result, holding the sort value and id
+/// of the last row of the previous page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(String);
 
-            impl std::fmt::Display for [<$name Fields>] {
-                fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                    std::fmt::Debug::fmt(self, f)
-                }
-            }
+impl Cursor {
+    pub fn encode<S: Display, I: Display>(sort_value: &S, id: &I) -> Cursor {
+        let sort_value = sort_value.to_string();
+        let raw = format!("{}:{}{}", sort_value.len(), sort_value, id);
+        Cursor(raw.bytes().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    pub fn decode<S: FromStr, I: FromStr>(&self) -> Result<(S, I), DbError> {
+        let bytes = (0..self.0.len())
+            .step_by(2)
+            .map(|i| {
+                self.0
+                    .get(i..i + 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(|| DbError::InvalidQuery("malformed cursor".to_string()))?;
+        let raw = String::from_utf8(bytes)
+            .map_err(|_| DbError::InvalidQuery("malformed cursor".to_string()))?;
+        let (len, rest) = raw
+            .split_once(':')
+            .ok_or_else(|| DbError::InvalidQuery("malformed cursor".to_string()))?;
+        let len: usize = len
+            .parse()
+            .map_err(|_| DbError::InvalidQuery("malformed cursor".to_string()))?;
+        if !rest.is_char_boundary(len) || len > rest.len() {
+            return Err(DbError::InvalidQuery("malformed cursor".to_string()));
         }
+        let (sort_value, id) = rest.split_at(len);
+        let sort_value = sort_value
+            .parse()
+            .map_err(|_| DbError::InvalidQuery("malformed cursor".to_string()))?;
+        let id = id
+            .parse()
+            .map_err(|_| DbError::InvalidQuery("malformed cursor".to_string()))?;
+        Ok((sort_value, id))
+    }
 
-        paste::paste! {
-            #[derive(Debug)]
-            pub enum [<$name Criteria>] {
-                $([<$field_name:camel Eq>]($field_type)),*,
-                $([<$field_name:camel Neq >]($field_type)),*,
-                $([<$field_name:camel Gt>]($field_type)),*,
-                $([<$field_name:camel Gte>]($field_type)),*,
-                $([<$field_name:camel Lt>]($field_type)),*,
-                $([<$field_name:camel Lte>]($field_type)),*,
-                $([<$field_name:camel In>](Vec<$field_type>)),*,
-                $([<$field_name:camel Nin>](Vec<$field_type>)),*,
-                $([<$field_name:camel Like>]($field_type)),*,
-                $([<$field_name:camel NLike>]($field_type)),*,
-            }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
 
-            #[derive(Default,Debug)]
-            pub struct [<$name CriteriaStruct>] {
-                pub $([<$field_name _eq>]: Option<$field_type>),*,
-                pub $([<$field_name _neq >]: Option<$field_type>),*,
-                pub $([<$field_name _gt>]: Option<$field_type>),*,
-                pub $([<$field_name _gte>]: Option<$field_type>),*,
-                pub $([<$field_name _lt>]: Option<$field_type>),*,
-                pub $([<$field_name _lte>]: Option<$field_type>),*,
-                pub $([<$field_name _in>]: Vec<$field_type>),*,
-                pub $([<$field_name _nin>]: Vec<$field_type>),*,
-                pub $([<$field_name _like>]: Option<$field_type>),*,
-                pub $([<$field_name _nlike>]: Option<$field_type>),*,
-            }
+impl From<String> for Cursor {
+    fn from(s: String) -> Self {
+        Cursor(s)
+    }
+}
 
-            impl [<$name Criteria>] {
-                fn to_query_condition<'a>(&'a self) -> QueryCondition<'a> {
-                    match self {
-                        $([<$name Criteria>]::[<$field_name:camel Eq>](x) => QueryCondition::Eq(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Neq>](x) => QueryCondition::Neq(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Gt>](x) => QueryCondition::Gt(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Gte>](x) => QueryCondition::Gte(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Lt>](x) => QueryCondition::Lt(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Lte>](x) => QueryCondition::Lte(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel In>](x) => QueryCondition::In(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Nin>](x) => QueryCondition::Nin(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel Like>](x) => QueryCondition::Like(stringify!($field_name).to_string(), x)),*,
-                        $([<$name Criteria>]::[<$field_name:camel NLike>](x) => QueryCondition::NLike(stringify!($field_name).to_string(), x)),*,
-                    }
-                }
-            }
+impl Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
-            impl [<$name CriteriaStruct>] {
-                fn to_criteria(self) -> Vec<[<$name Criteria>]> {
-                    let mut c = vec![];
-                    $(if let Some(x) = self.[<$field_name _eq>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Eq>](x));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _neq>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Neq>](x));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _gt>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Gt>](x));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _gte>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Gte>](x));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _lt>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Lt>](x));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _lte>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Lte>](x));
-                    })*
-                    $(if !self.[<$field_name _in>].is_empty() {
-                        c.push([<$name Criteria>]::[<$field_name:camel In>](self.[<$field_name _in>]));
-                    })*
-                    $(if !self.[<$field_name _nin>].is_empty() {
-                        c.push([<$name Criteria>]::[<$field_name:camel Nin>](self.[<$field_name _nin>]));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _like>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel Like>](x));
-                    })*
-                    $(if let Some(x) = self.[<$field_name _nlike>] {
-                        c.push([<$name Criteria>]::[<$field_name:camel NLike>](x));
-                    })*
-                    c
-                }
-            }
+#[derive(Debug)]
+pub struct CursorPage<A> {
+    pub items: Vec<A>,
+    pub next_cursor: Option<Cursor>,
+}
+
+pub fn create_cursor_select_sql(
+    table: &String,
+    columns: &[&str],
+    query_conditions: &Vec<QueryCondition>,
+    sort_field: &String,
+    id_field: &String,
+    has_cursor: bool,
+) -> Result<String, DbError> {
+    validate_identifier(sort_field)?;
+    validate_identifier(id_field)?;
+    let base_query = if query_conditions.is_empty() {
+        format!(
+            "select {} from {} where 1 = 1",
+            columns_to_string(columns)?,
+            table
+        )
+    } else {
+        let (query, _) = generate_select(table, columns, query_conditions, &[], None, None)?;
+        query
+    };
+    let n = query_conditions
+        .iter()
+        .map(|c| query_cond_params(c).len())
+        .sum::<usize>()
+        + 1;
+    let (after_part, limit_n) = if has_cursor {
+        (
+            format!(" and ({}, {}) > (${}, ${})", sort_field, id_field, n, n + 1),
+            n + 2,
+        )
+    } else {
+        ("".to_string(), n)
+    };
+    Ok(format!(
+        "{}{} order by {}, {} limit ${}",
+        base_query, after_part, sort_field, id_field, limit_n
+    ))
+}
+
+/// Fetches one page of rows ordered by `(sort_field, id_field)`, starting after
+/// `after` when given. `cursor_key` extracts the sort value and id of a mapped
+/// row so the cursor for the next page can be built from the last item.
+#[allow(clippy::too_many_arguments)]
+pub async fn select_page<'a, C, F, A, E, S, I>(
+    client: &C,
+    table: &String,
+    columns: &[&str],
+    query_conditions: &'a Vec<QueryCondition<'a>>,
+    sort_field: &String,
+    id_field: &String,
+    after: Option<&Cursor>,
+    limit: i64,
+    map_row: F,
+    cursor_key: impl Fn(&A) -> (S, I),
+) -> Result<CursorPage<A>, DbError>
+where
+    C: GenericClient,
+    F: Fn(Row) -> Result<A, E> + Send + 'static,
+    E: Into<DbError>,
+    S: ToSql + Sync + Display + FromStr,
+    I: ToSql + Sync + Display + FromStr,
+{
+    fault::inject().await?;
+    let decoded: Option<(S, I)> = after.map(|c| c.decode()).transpose()?;
+    let query = create_cursor_select_sql(
+        table,
+        columns,
+        query_conditions,
+        sort_field,
+        id_field,
+        decoded.is_some(),
+    )?;
+    let (_, condition_params) = generate_select(table, columns, query_conditions, &[], None, None)?;
+    let fetch_limit = limit + 1;
+    let mut params: Vec<&(dyn ToSql + Sync)> = condition_params;
+    if let Some((sort_value, id)) = &decoded {
+        params.push(sort_value);
+        params.push(id);
+    }
+    params.push(&fetch_limit);
+    let stmt = client.prepare(&query).await?;
+    let rows = client.query(&stmt, params.as_slice()).await?;
+    let mut items = rows
+        .into_iter()
+        .map(|row| map_row(row).map_err(Into::into))
+        .collect::<Result<Vec<A>, DbError>>()?;
+    let has_more = items.len() as i64 > limit;
+    items.truncate(limit.max(0) as usize);
+    let next_cursor = match items.last() {
+        Some(last) if has_more => {
+            let (sort_value, id) = cursor_key(last);
+            Some(Cursor::encode(&sort_value, &id))
         }
+        _ => None,
+    };
+    Ok(CursorPage { items, next_cursor })
+}
 
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
 
-        impl $name {
+    use super::{
+        create_cursor_select_sql, create_delete_sql, create_insert_many_sql, create_update_sql,
+        create_upsert_sql, generate_select, Cursor, QueryCondition, Sort, SortDirection,
+    };
 
-            fn field_names() -> &'static [&'static str] {
-                static NAMES: &'static [&'static str] = &[$(stringify!($field_name)),*];
-                NAMES
-            }
+    #[allow(dead_code, clippy::wrong_self_convention)]
+    mod widget {
+        use uuid::Uuid;
 
-            fn field_types() -> &'static [&'static str] {
-                static TYPES: &'static [&'static str] = &[$(stringify!($field_type)),*];
-                TYPES
-            }
+        use crate::postgres_common::core::{query_cond_to_string, Entity};
 
-            fn from_row(row: tokio_postgres::Row) -> $name {
-                $(let $field_name: $field_type = row.get(stringify!($field_name));)*
-                $name {
-                    $($field_name),*
-                }
-           }
+        #[derive(Debug, Entity)]
+        #[entity(table = "widgets", vis = "pub(crate)")]
+        pub struct Widget {
+            pub id: Uuid,
+            /// Shown to people.
+            #[entity(column = "display_name")]
+            pub name: String,
+        }
 
-            fn to_params_x<'a>(&'a self) -> Vec<&'a (dyn tokio_postgres::types::ToSql + Sync)> {
-                vec![
-                    $(&self.$field_name as &(dyn tokio_postgres::types::ToSql + Sync)),*
-                ][1..].into_iter().map(|x| *x as &(dyn tokio_postgres::types::ToSql + Sync)).collect::<Vec<&'a (dyn tokio_postgres::types::ToSql + Sync)>>()
-            }
+        #[derive(Debug, Entity)]
+        pub struct Tagged<T> {
+            pub id: Uuid,
+            pub tag: T,
+        }
+
+        #[test]
+        pub fn test_entity_table_and_column_attributes() {
+            assert_eq!("widgets", Widget::table_name());
+            assert_eq!(&["id", "display_name"], Widget::field_names());
+            assert_eq!(vec!["display_name"], Widget::write_field_names());
+            let crit = WidgetCriteria::NameEq("w".to_string());
+            assert_eq!(
+                "display_name = $1",
+                query_cond_to_string(&crit.to_query_condition(), 1)
+            );
+            assert_eq!("display_name", WidgetSort::NameDesc.to_sort().field);
+            assert_eq!(WidgetFields::Name, WidgetSort::NameDesc.field());
+            assert_eq!(
+                vec!["id", "display_name"],
+                WidgetFields::ALL
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect::<Vec<String>>()
+            );
+
+            let crit = TaggedCriteria::<i32>::TagIn(vec![1, 2]);
+            assert_eq!(
+                "tag = any($1)",
+                query_cond_to_string(&crit.to_query_condition(), 1)
+            );
+            assert_eq!(vec!["tag"], Tagged::<i32>::write_field_names());
         }
     }
-}
 
-pub(crate) use entity;
+    #[test]
+    pub fn test_cursor_round_trip() {
+        let id = Uuid::new_v4();
+        let cursor = Cursor::encode(&"some:name".to_string(), &id);
+        let (sort_value, decoded_id): (String, Uuid) = cursor.decode().unwrap();
+        assert_eq!("some:name", sort_value);
+        assert_eq!(id, decoded_id);
+    }
+
+    #[test]
+    pub fn test_cursor_decode_rejects_garbage() {
+        let cursor = Cursor::from("not a cursor".to_string());
+        assert!(cursor.decode::<String, Uuid>().is_err());
+    }
+
+    #[test]
+    pub fn test_cursor_select_sql() {
+        let name = "edb".to_string();
+        let conds = vec![QueryCondition::Eq("name".to_string(), &name)];
+        let sql = create_cursor_select_sql(
+            &"users".to_string(),
+            &[],
+            &conds,
+            &"username".to_string(),
+            &"id".to_string(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            "select * from users where 1 = 1  and name = $1 and (username, id) > ($2, $3) order by username, id limit $4",
+            sql
+        );
+        let first_page = create_cursor_select_sql(
+            &"users".to_string(),
+            &[],
+            &vec![],
+            &"username".to_string(),
+            &"id".to_string(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            "select * from users where 1 = 1 order by username, id limit $1",
+            first_page
+        );
+    }
+
+    #[test]
+    pub fn test_generate_select_with_sorts() {
+        let sorts = vec![
+            Sort {
+                field: "username".to_string(),
+                direction: SortDirection::Asc,
+            },
+            Sort {
+                field: "id".to_string(),
+                direction: SortDirection::Desc,
+            },
+        ];
+        let conds = vec![];
+        let (sql, params) =
+            generate_select(&"users".to_string(), &[], &conds, &sorts, None, None).unwrap();
+        assert_eq!("select * from users order by username asc, id desc", sql);
+        assert!(params.is_empty());
+    }
+
+    #[test]
+    pub fn test_generate_select_rejects_injected_sort_field() {
+        let sorts = vec![Sort {
+            field: "id; drop table users".to_string(),
+            direction: SortDirection::Asc,
+        }];
+        assert!(generate_select(&"users".to_string(), &[], &vec![], &sorts, None, None).is_err());
+    }
+
+    #[test]
+    pub fn test_generate_select_with_limit_and_offset() {
+        let name = "edb".to_string();
+        let conds = vec![QueryCondition::Eq("name".to_string(), &name)];
+        let (sql, params) = generate_select(
+            &"accounts".to_string(),
+            &[],
+            &conds,
+            &[],
+            Some(10),
+            Some(20),
+        )
+        .unwrap();
+        assert_eq!(
+            "select * from accounts where 1 = 1  and name = $1 limit 10 offset 20",
+            sql
+        );
+        assert_eq!(1, params.len());
+        assert!(
+            generate_select(&"accounts".to_string(), &[], &conds, &[], Some(-1), None).is_err()
+        );
+    }
+
+    #[test]
+    pub fn test_create_delete_sql() {
+        let id = 1;
+        let name = "edb".to_string();
+        let conds = vec![
+            QueryCondition::Eq("id".to_string(), &id),
+            QueryCondition::Neq("name".to_string(), &name),
+        ];
+        let (sql, params) = create_delete_sql(&"accounts".to_string(), &conds).unwrap();
+        assert_eq!(
+            "delete from accounts where 1 = 1  and id = $1 and name != $2",
+            sql
+        );
+        assert_eq!(2, params.len());
+        assert!(create_delete_sql(&"accounts".to_string(), &vec![]).is_err());
+    }
+
+    #[test]
+    pub fn test_create_insert_many_sql() {
+        let fields = vec!["username".to_string(), "roles".to_string()];
+        let sql = create_insert_many_sql(&"users".to_string(), &"id".to_string(), &fields, 2);
+        assert_eq!(
+            "insert into users (id, username, roles) values ($1, $2, $3), ($4, $5, $6)",
+            sql
+        );
+    }
+
+    #[test]
+    pub fn test_create_update_sql() {
+        let fields = vec!["name".to_string(), "slug".to_string()];
+        let sql = create_update_sql(&"accounts".to_string(), &"id".to_string(), &fields).unwrap();
+        assert_eq!(
+            "update accounts set name = $1 , slug = $2 where id = $3",
+            sql
+        );
+        assert!(create_update_sql(&"accounts".to_string(), &"id".to_string(), &[]).is_err());
+    }
+
+    #[test]
+    pub fn test_create_upsert_sql() {
+        let fields = vec!["name".to_string(), "slug".to_string()];
+        let sql = create_upsert_sql(&"accounts".to_string(), &"id".to_string(), &fields);
+        assert_eq!(
+            "insert into accounts (id, name, slug) values ($1, $2, $3) on conflict (id) do update set name = excluded.name, slug = excluded.slug",
+            sql
+        );
+    }
+
+    #[test]
+    pub fn test_null_conditions_take_no_param() {
+        let name = "edb".to_string();
+        let id = 1;
+        let conds = vec![
+            QueryCondition::IsNull("deleted_at".to_string()),
+            QueryCondition::Eq("name".to_string(), &name),
+            QueryCondition::IsNotNull("slug".to_string()),
+            QueryCondition::Gt("id".to_string(), &id),
+        ];
+        let (sql, params) =
+            generate_select(&"accounts".to_string(), &[], &conds, &[], None, None).unwrap();
+        assert_eq!(
+            "select * from accounts where 1 = 1  and deleted_at is null and name = $1 and slug is not null and id > $2",
+            sql
+        );
+        assert_eq!(2, params.len());
+    }
+
+    #[test]
+    pub fn test_between_takes_two_params() {
+        let from = 1;
+        let to = 10;
+        let name = "edb".to_string();
+        let conds = vec![
+            QueryCondition::Between("seq_order".to_string(), &from, &to),
+            QueryCondition::Eq("name".to_string(), &name),
+        ];
+        let (sql, params) =
+            generate_select(&"migrations".to_string(), &[], &conds, &[], None, None).unwrap();
+        assert_eq!(
+            "select * from migrations where 1 = 1  and seq_order between $1 and $2 and name = $3",
+            sql
+        );
+        assert_eq!(3, params.len());
+    }
+
+    #[test]
+    pub fn test_ilike_conditions() {
+        let name = "EDB%".to_string();
+        let conds = vec![
+            QueryCondition::ILike("username".to_string(), &name),
+            QueryCondition::NotILike("roles".to_string(), &name),
+        ];
+        let (sql, _) = generate_select(&"users".to_string(), &[], &conds, &[], None, None).unwrap();
+        assert_eq!(
+            "select * from users where 1 = 1  and username ilike $1 and roles not ilike $2",
+            sql
+        );
+    }
+
+    #[test]
+    pub fn test_in_and_nin_bind_one_array_param() {
+        let ids = vec![1, 2, 3];
+        let conds = vec![
+            QueryCondition::In("id".to_string(), &ids),
+            QueryCondition::Nin("seq_order".to_string(), &ids),
+        ];
+        let (sql, params) =
+            generate_select(&"migrations".to_string(), &[], &conds, &[], None, None).unwrap();
+        assert_eq!(
+            "select * from migrations where 1 = 1  and id = any($1) and seq_order <> all($2)",
+            sql
+        );
+        assert_eq!(2, params.len());
+    }
+
+    #[test]
+    pub fn test_generate_select_with_columns() {
+        let conds = vec![];
+        let (sql, _) = generate_select(
+            &"users".to_string(),
+            &["id", "username"],
+            &conds,
+            &[],
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!("select id, username from users", sql);
+        assert!(generate_select(
+            &"users".to_string(),
+            &["id, password"],
+            &conds,
+            &[],
+            None,
+            None
+        )
+        .is_err());
+    }
+}
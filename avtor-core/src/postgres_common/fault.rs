@@ -0,0 +1,89 @@
+//! Fault injection for resilience tests. With the `fault-injection` feature
+//! every helper in `postgres_common::core` calls `inject` before touching the
+//! database; without it `inject` is a no-op.
+
+use crate::error::DbError;
+
+#[cfg(feature = "fault-injection")]
+pub use enabled::{clear_faults, set_faults, FaultConfig};
+
+#[cfg(feature = "fault-injection")]
+mod enabled {
+    use std::{sync::RwLock, time::Duration};
+
+    use rand::Rng;
+
+    use crate::error::DbError;
+
+    /// Rates are probabilities between 0 and 1, checked on every call.
+    #[derive(Debug, Clone, Default)]
+    pub struct FaultConfig {
+        pub error_rate: f64,
+        pub drop_connection_rate: f64,
+        pub latency: Duration,
+    }
+
+    static FAULTS: RwLock<Option<FaultConfig>> = RwLock::new(None);
+
+    pub fn set_faults(config: FaultConfig) {
+        *FAULTS.write().unwrap() = Some(config);
+    }
+
+    pub fn clear_faults() {
+        *FAULTS.write().unwrap() = None;
+    }
+
+    pub async fn inject() -> Result<(), DbError> {
+        let config = match FAULTS.read().unwrap().clone() {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        if !config.latency.is_zero() {
+            tokio::time::sleep(config.latency).await;
+        }
+        let roll: f64 = rand::thread_rng().gen();
+        if roll < config.drop_connection_rate {
+            return Err(DbError::ConnectionLost("injected fault".to_string()));
+        }
+        if roll < config.drop_connection_rate + config.error_rate {
+            return Err(DbError::Other("injected fault".to_string()));
+        }
+        Ok(())
+    }
+}
+
+pub async fn inject() -> Result<(), DbError> {
+    #[cfg(feature = "fault-injection")]
+    enabled::inject().await?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "fault-injection"))]
+mod tests {
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+
+    use super::{clear_faults, inject, set_faults, FaultConfig};
+    use crate::error::DbError;
+
+    #[test]
+    pub fn test_inject_follows_config() {
+        set_faults(FaultConfig {
+            drop_connection_rate: 1.0,
+            ..FaultConfig::default()
+        });
+        assert!(matches!(
+            block_on(inject()),
+            Err(DbError::ConnectionLost(_))
+        ));
+        set_faults(FaultConfig {
+            error_rate: 1.0,
+            latency: Duration::ZERO,
+            ..FaultConfig::default()
+        });
+        assert!(matches!(block_on(inject()), Err(DbError::Other(_))));
+        clear_faults();
+        assert!(block_on(inject()).is_ok());
+    }
+}
@@ -1 +1,3 @@
 pub mod core;
+pub mod fault;
+pub mod retry;
@@ -0,0 +1,173 @@
+use std::{future::Future, time::Duration};
+
+use rand::Rng;
+
+use crate::error::DbError;
+
+/// How many times to run an operation and how long to wait in between. The
+/// delay doubles after every failed attempt up to `max_delay`; with `jitter`
+/// each wait is a random duration between zero and that bound.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(2),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let bound = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter && !bound.is_zero() {
+            rand::thread_rng().gen_range(Duration::ZERO..=bound)
+        } else {
+            bound
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RetryError {
+    #[error("gave up after {attempts} attempts: {last}")]
+    Exhausted { attempts: u32, last: DbError },
+
+    #[error(transparent)]
+    Permanent(DbError),
+}
+
+/// Runs `op` until it succeeds, fails with an error `should_retry` rejects, or
+/// `policy.max_attempts` is used up. `op` is called again for every attempt, so
+/// it should open its own transaction.
+pub async fn retry_if<F, Fut, T, P>(
+    policy: &RetryPolicy,
+    should_retry: P,
+    mut op: F,
+) -> Result<T, RetryError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DbError>>,
+    P: Fn(&DbError) -> bool,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(v) => return Ok(v),
+            Err(e) if !should_retry(&e) => return Err(RetryError::Permanent(e)),
+            Err(e) if attempt >= policy.max_attempts => {
+                return Err(RetryError::Exhausted {
+                    attempts: attempt,
+                    last: e,
+                })
+            }
+            Err(_) => {
+                tokio::time::sleep(policy.delay_for(attempt)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// `retry_if` for `DbError::is_transient` errors. Lost connections are left
+/// to the caller, who has to reconnect.
+pub async fn retry<F, Fut, T>(policy: &RetryPolicy, op: F) -> Result<T, RetryError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DbError>>,
+{
+    retry_if(policy, DbError::is_transient, op).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use super::{retry, RetryError, RetryPolicy};
+    use crate::error::DbError;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+            jitter: false,
+        }
+    }
+
+    #[test]
+    pub fn test_delay_doubles_up_to_max() {
+        let p = policy();
+        assert_eq!(Duration::from_millis(1), p.delay_for(1));
+        assert_eq!(Duration::from_millis(2), p.delay_for(2));
+        assert_eq!(Duration::from_millis(4), p.delay_for(3));
+        assert_eq!(Duration::from_millis(4), p.delay_for(40));
+    }
+
+    #[tokio::test]
+    pub async fn test_retries_until_success() {
+        let calls = Cell::new(0);
+        let r = retry(&policy(), || {
+            calls.set(calls.get() + 1);
+            let n = calls.get();
+            async move {
+                if n < 3 {
+                    Err(DbError::SerializationFailure("conflict".to_string()))
+                } else {
+                    Ok(n)
+                }
+            }
+        })
+        .await;
+        assert_eq!(3, r.unwrap());
+    }
+
+    #[tokio::test]
+    pub async fn test_exhausted_and_permanent() {
+        let calls = Cell::new(0);
+        let r: Result<(), RetryError> = retry(&policy(), || {
+            calls.set(calls.get() + 1);
+            async { Err(DbError::SerializationFailure("conflict".to_string())) }
+        })
+        .await;
+        assert!(matches!(r, Err(RetryError::Exhausted { attempts: 3, .. })));
+        assert_eq!(3, calls.get());
+
+        calls.set(0);
+        let r: Result<(), RetryError> = retry(&policy(), || {
+            calls.set(calls.get() + 1);
+            async { Err(DbError::ConnectionLost("closed".to_string())) }
+        })
+        .await;
+        assert!(matches!(r, Err(RetryError::Permanent(_))));
+        assert_eq!(1, calls.get());
+
+        calls.set(0);
+        let r: Result<(), RetryError> = retry(&policy(), || {
+            calls.set(calls.get() + 1);
+            async {
+                Err(DbError::UniqueViolation {
+                    constraint: None,
+                    message: "duplicate".to_string(),
+                })
+            }
+        })
+        .await;
+        assert!(matches!(r, Err(RetryError::Permanent(_))));
+        assert_eq!(1, calls.get());
+    }
+}
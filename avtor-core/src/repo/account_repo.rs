@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+use tokio_postgres::Transaction;
+
+use crate::models::users::{
+    find_account_by_id, find_account_by_slug, insert_account, Account, AccountId,
+    CreateAccountError,
+};
+
+#[async_trait]
+pub trait AccountRepo: Send + Sync {
+    async fn find_account_by_id(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Option<Account>, CreateAccountError>;
+
+    async fn find_account_by_slug(
+        &self,
+        slug: String,
+    ) -> Result<Option<Account>, CreateAccountError>;
+
+    async fn insert_account(&self, account: Account) -> Result<(), CreateAccountError>;
+}
+
+/// Delegates to the closure-style functions in `models::users`.
+pub struct PgAccountRepo<'a> {
+    trans: &'a Transaction<'a>,
+}
+
+impl<'a> PgAccountRepo<'a> {
+    pub fn new(trans: &'a Transaction<'a>) -> Self {
+        PgAccountRepo { trans }
+    }
+}
+
+#[async_trait]
+impl<'a> AccountRepo for PgAccountRepo<'a> {
+    async fn find_account_by_id(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Option<Account>, CreateAccountError> {
+        find_account_by_id(self.trans)(account_id).await
+    }
+
+    async fn find_account_by_slug(
+        &self,
+        slug: String,
+    ) -> Result<Option<Account>, CreateAccountError> {
+        find_account_by_slug(self.trans)(slug).await
+    }
+
+    async fn insert_account(&self, account: Account) -> Result<(), CreateAccountError> {
+        insert_account(self.trans)(account).await
+    }
+}
@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use tokio_postgres::Transaction;
+
+use crate::email::EmailKind;
+use crate::error::DbError;
+use crate::models::email_templates::{
+    find_email_template, insert_email_template, update_email_template, AccountEmailTemplate,
+};
+use crate::models::users::AccountId;
+
+#[async_trait]
+pub trait EmailTemplateRepo: Send + Sync {
+    async fn find_email_template(
+        &self,
+        account_id: AccountId,
+        kind: EmailKind,
+    ) -> Result<Option<AccountEmailTemplate>, DbError>;
+
+    async fn insert_email_template(&self, template: AccountEmailTemplate) -> Result<(), DbError>;
+
+    /// Writes every field but the id and `created_on`.
+    async fn update_email_template(&self, template: &AccountEmailTemplate) -> Result<(), DbError>;
+}
+
+/// Delegates to the closure-style functions in `models::email_templates`.
+pub struct PgEmailTemplateRepo<'a> {
+    trans: &'a Transaction<'a>,
+}
+
+impl<'a> PgEmailTemplateRepo<'a> {
+    pub fn new(trans: &'a Transaction<'a>) -> Self {
+        PgEmailTemplateRepo { trans }
+    }
+}
+
+#[async_trait]
+impl<'a> EmailTemplateRepo for PgEmailTemplateRepo<'a> {
+    async fn find_email_template(
+        &self,
+        account_id: AccountId,
+        kind: EmailKind,
+    ) -> Result<Option<AccountEmailTemplate>, DbError> {
+        find_email_template(self.trans)(account_id, kind).await
+    }
+
+    async fn insert_email_template(&self, template: AccountEmailTemplate) -> Result<(), DbError> {
+        insert_email_template(self.trans)(template).await
+    }
+
+    async fn update_email_template(&self, template: &AccountEmailTemplate) -> Result<(), DbError> {
+        update_email_template(self.trans)(template).await
+    }
+}
@@ -0,0 +1,65 @@
+use async_trait::async_trait;
+use tokio_postgres::Transaction;
+
+use crate::error::DbError;
+use crate::models::invitations::{
+    find_invitation_by_id, find_invitations_by_account, insert_invitation, lock_invitation_by_id,
+    update_invitation, Invitation, InvitationId,
+};
+use crate::models::users::AccountId;
+
+#[async_trait]
+pub trait InvitationRepo: Send + Sync {
+    async fn find_invitation_by_id(&self, id: InvitationId) -> Result<Option<Invitation>, DbError>;
+
+    /// Finds the invitation and keeps others from changing it until the
+    /// transaction ends.
+    async fn lock_invitation(&self, id: InvitationId) -> Result<Option<Invitation>, DbError>;
+
+    async fn find_invitations_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<Invitation>, DbError>;
+
+    async fn insert_invitation(&self, invitation: Invitation) -> Result<(), DbError>;
+
+    /// Writes every field but the id and `created_on`.
+    async fn update_invitation(&self, invitation: &Invitation) -> Result<(), DbError>;
+}
+
+/// Delegates to the closure-style functions in `models::invitations`.
+pub struct PgInvitationRepo<'a> {
+    trans: &'a Transaction<'a>,
+}
+
+impl<'a> PgInvitationRepo<'a> {
+    pub fn new(trans: &'a Transaction<'a>) -> Self {
+        PgInvitationRepo { trans }
+    }
+}
+
+#[async_trait]
+impl<'a> InvitationRepo for PgInvitationRepo<'a> {
+    async fn find_invitation_by_id(&self, id: InvitationId) -> Result<Option<Invitation>, DbError> {
+        find_invitation_by_id(self.trans)(id).await
+    }
+
+    async fn lock_invitation(&self, id: InvitationId) -> Result<Option<Invitation>, DbError> {
+        lock_invitation_by_id(self.trans)(id).await
+    }
+
+    async fn find_invitations_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<Invitation>, DbError> {
+        find_invitations_by_account(self.trans)(account_id).await
+    }
+
+    async fn insert_invitation(&self, invitation: Invitation) -> Result<(), DbError> {
+        insert_invitation(self.trans)(invitation).await
+    }
+
+    async fn update_invitation(&self, invitation: &Invitation) -> Result<(), DbError> {
+        update_invitation(self.trans)(invitation).await
+    }
+}
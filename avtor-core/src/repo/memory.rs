@@ -0,0 +1,588 @@
+//! In-memory repositories for exercising use cases without a database.
+
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::email::EmailKind;
+use crate::error::DbError;
+use crate::models::email_templates::AccountEmailTemplate;
+use crate::models::invitations::{Invitation, InvitationId};
+use crate::models::migrations::Migration;
+use crate::models::users::{
+    next_free_slug, Account, AccountId, CreateAccountError, CreateSuperUserError, User,
+};
+
+use super::{AccountRepo, EmailTemplateRepo, InvitationRepo, MigrationRepo, UserRepo};
+
+#[derive(Default)]
+pub struct MemoryUserRepo {
+    users: Mutex<Vec<User>>,
+}
+
+#[async_trait]
+impl UserRepo for MemoryUserRepo {
+    async fn find_super_user(&self) -> Result<Option<User>, CreateSuperUserError> {
+        let users = self.users.lock().unwrap();
+        Ok(users.iter().find(|u| u.is_super_user()).cloned())
+    }
+
+    async fn find_user_by_username(
+        &self,
+        username: String,
+    ) -> Result<Option<User>, CreateSuperUserError> {
+        let users = self.users.lock().unwrap();
+        Ok(users.iter().find(|u| u.username() == username).cloned())
+    }
+
+    async fn find_user_by_email(
+        &self,
+        account_id: AccountId,
+        email: String,
+    ) -> Result<Option<User>, CreateSuperUserError> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .iter()
+            .find(|u| u.account_id() == account_id.uuid() && u.email() == Some(email.as_str()))
+            .cloned())
+    }
+
+    async fn find_users_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<User>, CreateSuperUserError> {
+        let users = self.users.lock().unwrap();
+        Ok(users
+            .iter()
+            .filter(|u| u.account_id() == account_id.uuid())
+            .cloned()
+            .collect())
+    }
+
+    /// Enforces the same unique username and email indexes as the database
+    /// and sets the timestamps it would.
+    async fn insert_user(&self, user: User) -> Result<(), CreateSuperUserError> {
+        let mut users = self.users.lock().unwrap();
+        if users
+            .iter()
+            .any(|u| u.username().to_lowercase() == user.username().to_lowercase())
+        {
+            return Err(CreateSuperUserError::UsernameTaken);
+        }
+        if user.email().is_some()
+            && users
+                .iter()
+                .any(|u| u.account_id() == user.account_id() && u.email() == user.email())
+        {
+            return Err(CreateSuperUserError::EmailTaken);
+        }
+        let now = Some(Utc::now().naive_utc());
+        users.push(User {
+            created_on: now,
+            updated_on: now,
+            ..user
+        });
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryAccountRepo {
+    accounts: Mutex<Vec<Account>>,
+}
+
+#[async_trait]
+impl AccountRepo for MemoryAccountRepo {
+    async fn find_account_by_id(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Option<Account>, CreateAccountError> {
+        let accounts = self.accounts.lock().unwrap();
+        Ok(accounts.iter().find(|a| a.id() == account_id).cloned())
+    }
+
+    async fn find_account_by_slug(
+        &self,
+        slug: String,
+    ) -> Result<Option<Account>, CreateAccountError> {
+        let accounts = self.accounts.lock().unwrap();
+        Ok(accounts.iter().find(|a| a.slug() == slug).cloned())
+    }
+
+    async fn insert_account(&self, account: Account) -> Result<(), CreateAccountError> {
+        let mut accounts = self.accounts.lock().unwrap();
+        if accounts.iter().any(|a| a.id() == account.id()) {
+            return Err(CreateAccountError::AccountExists);
+        }
+        // accounts_name_key is on lower(name)
+        if accounts
+            .iter()
+            .any(|a| a.name.to_lowercase() == account.name.to_lowercase())
+        {
+            return Err(CreateAccountError::NameTaken);
+        }
+        let taken: Vec<String> = accounts.iter().map(|a| a.slug().to_string()).collect();
+        let slug = next_free_slug(account.slug(), &taken);
+        let now = Some(Utc::now().naive_utc());
+        accounts.push(Account {
+            created_on: now,
+            updated_on: now,
+            ..account.with_slug(slug)
+        });
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryInvitationRepo {
+    invitations: Mutex<Vec<Invitation>>,
+}
+
+#[async_trait]
+impl InvitationRepo for MemoryInvitationRepo {
+    async fn find_invitation_by_id(&self, id: InvitationId) -> Result<Option<Invitation>, DbError> {
+        let invitations = self.invitations.lock().unwrap();
+        Ok(invitations.iter().find(|i| i.id() == id).cloned())
+    }
+
+    /// Nothing to lock; the repo is for tests that run one call at a time.
+    async fn lock_invitation(&self, id: InvitationId) -> Result<Option<Invitation>, DbError> {
+        self.find_invitation_by_id(id).await
+    }
+
+    async fn find_invitations_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<Invitation>, DbError> {
+        let invitations = self.invitations.lock().unwrap();
+        Ok(invitations
+            .iter()
+            .filter(|i| i.account_id == account_id.uuid())
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_invitation(&self, invitation: Invitation) -> Result<(), DbError> {
+        let now = Some(Utc::now().naive_utc());
+        self.invitations.lock().unwrap().push(Invitation {
+            created_on: now,
+            ..invitation
+        });
+        Ok(())
+    }
+
+    async fn update_invitation(&self, invitation: &Invitation) -> Result<(), DbError> {
+        let mut invitations = self.invitations.lock().unwrap();
+        if let Some(stored) = invitations.iter_mut().find(|i| i.id() == invitation.id()) {
+            *stored = Invitation {
+                created_on: stored.created_on,
+                ..invitation.clone()
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryEmailTemplateRepo {
+    templates: Mutex<Vec<AccountEmailTemplate>>,
+}
+
+#[async_trait]
+impl EmailTemplateRepo for MemoryEmailTemplateRepo {
+    async fn find_email_template(
+        &self,
+        account_id: AccountId,
+        kind: EmailKind,
+    ) -> Result<Option<AccountEmailTemplate>, DbError> {
+        let templates = self.templates.lock().unwrap();
+        Ok(templates
+            .iter()
+            .find(|t| t.account_id == account_id.uuid() && t.kind == kind.as_str())
+            .cloned())
+    }
+
+    async fn insert_email_template(&self, template: AccountEmailTemplate) -> Result<(), DbError> {
+        let now = Some(Utc::now().naive_utc());
+        self.templates.lock().unwrap().push(AccountEmailTemplate {
+            created_on: now,
+            ..template
+        });
+        Ok(())
+    }
+
+    async fn update_email_template(&self, template: &AccountEmailTemplate) -> Result<(), DbError> {
+        let mut templates = self.templates.lock().unwrap();
+        if let Some(stored) = templates.iter_mut().find(|t| t.id == template.id) {
+            *stored = AccountEmailTemplate {
+                created_on: stored.created_on,
+                ..template.clone()
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryMigrationRepo {
+    migrations: Mutex<Vec<Migration>>,
+}
+
+#[async_trait]
+impl MigrationRepo for MemoryMigrationRepo {
+    async fn find_all(&self) -> Result<Vec<Migration>, DbError> {
+        let mut migrations = self.migrations.lock().unwrap().clone();
+        migrations.sort_by_key(|m| m.seq_order);
+        Ok(migrations)
+    }
+
+    async fn find_by_name(&self, name: String) -> Result<Option<Migration>, DbError> {
+        let migrations = self.migrations.lock().unwrap();
+        Ok(migrations.iter().find(|m| m.name == name).cloned())
+    }
+
+    async fn create(&self, migration: Migration) -> Result<(), DbError> {
+        self.migrations.lock().unwrap().push(migration);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use futures::executor::block_on;
+    use uuid::Uuid;
+
+    use crate::identifier::StandardNormalizer;
+    use crate::models::migrations::{default_migration, Migration};
+    use crate::models::users::{
+        create_super_user_with_repos, create_user_with_repos, find_user_by_email_with_repos,
+        AccountDto, CreateSuperUserError, CreateUserError, UserDto, SUPER_USER_ROLE,
+    };
+    use crate::password::{
+        breach::{BloomFilter, BreachCheckError, BreachChecker, NoBreachCheck},
+        verify_password,
+    };
+    use crate::repo::{AccountRepo, MigrationRepo, UserRepo};
+    use crate::secret::Secret;
+
+    use super::{MemoryAccountRepo, MemoryMigrationRepo, MemoryUserRepo};
+
+    fn user_dto() -> UserDto {
+        UserDto {
+            id: Uuid::new_v4(),
+            username: "someusername".to_string(),
+            password: "!Q2w3e4r5t".into(),
+            roles: SUPER_USER_ROLE.to_string(),
+            account_id: Uuid::from_str("a304f299-b547-4d3d-bd42-732f617b258a").unwrap(),
+            email: None,
+        }
+    }
+
+    fn account_dto() -> AccountDto {
+        AccountDto {
+            id: Uuid::from_str("3c3f5220-8b3d-40a3-8da2-196a69beaca8").unwrap(),
+            name: "edb".to_string(),
+        }
+    }
+
+    #[test]
+    pub fn test_create_super_user_with_memory_repos() {
+        let users = MemoryUserRepo::default();
+        let accounts = MemoryAccountRepo::default();
+        let res = block_on(create_super_user_with_repos(
+            &users,
+            &accounts,
+            &StandardNormalizer::default(),
+            &NoBreachCheck,
+            &user_dto(),
+            &account_dto(),
+        ));
+        assert!(res.is_ok());
+
+        let res = block_on(create_super_user_with_repos(
+            &users,
+            &accounts,
+            &StandardNormalizer::default(),
+            &NoBreachCheck,
+            &user_dto(),
+            &account_dto(),
+        ));
+        assert!(matches!(res, Err(CreateSuperUserError::SuperUserExists)));
+    }
+
+    /// Fails every check, like an unreachable breach service.
+    struct UnreachableBreachCheck;
+
+    #[async_trait::async_trait]
+    impl BreachChecker for UnreachableBreachCheck {
+        async fn is_breached(&self, _password: &Secret<String>) -> Result<bool, BreachCheckError> {
+            Err(BreachCheckError::Unavailable("unreachable".to_string()))
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_users_are_refused_before_the_breach_check() {
+        let users = MemoryUserRepo::default();
+        let accounts = MemoryAccountRepo::default();
+        let normalizer = StandardNormalizer::default();
+        let invalid = UserDto {
+            email: Some("not-an-email".to_string()),
+            ..user_dto()
+        };
+        let res = block_on(create_super_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &UnreachableBreachCheck,
+            &invalid,
+            &account_dto(),
+        ));
+        assert!(matches!(res, Err(CreateSuperUserError::UserInvalid(_))));
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &UnreachableBreachCheck,
+            &UserDto {
+                roles: "user".to_string(),
+                ..invalid
+            },
+        ));
+        assert!(matches!(res, Err(CreateUserError::UserInvalid(_))));
+    }
+
+    #[test]
+    pub fn test_user_conflicts_are_reported_before_the_breach_check() {
+        let users = MemoryUserRepo::default();
+        let accounts = MemoryAccountRepo::default();
+        let normalizer = StandardNormalizer::default();
+        let super_user = UserDto {
+            account_id: account_dto().id,
+            ..user_dto()
+        };
+        block_on(create_super_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &super_user,
+            &account_dto(),
+        ))
+        .unwrap();
+
+        let create = |dto: &UserDto| {
+            block_on(create_user_with_repos(
+                &users,
+                &accounts,
+                &normalizer,
+                &UnreachableBreachCheck,
+                dto,
+            ))
+        };
+        let reserved = UserDto {
+            id: Uuid::new_v4(),
+            username: "other".to_string(),
+            ..super_user.clone()
+        };
+        assert!(matches!(
+            create(&reserved),
+            Err(CreateUserError::ReservedRole(_))
+        ));
+        let taken = UserDto {
+            id: Uuid::new_v4(),
+            roles: "user".to_string(),
+            ..super_user.clone()
+        };
+        assert!(matches!(
+            create(&taken),
+            Err(CreateUserError::UsernameTaken)
+        ));
+        let fresh = UserDto {
+            username: "other".to_string(),
+            ..taken
+        };
+        assert!(matches!(
+            create(&fresh),
+            Err(CreateUserError::BreachCheck(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_create_user_with_memory_repos() {
+        let users = MemoryUserRepo::default();
+        let accounts = MemoryAccountRepo::default();
+        let normalizer = StandardNormalizer::default();
+        let super_user = UserDto {
+            account_id: account_dto().id,
+            ..user_dto()
+        };
+        block_on(create_super_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &super_user,
+            &account_dto(),
+        ))
+        .unwrap();
+
+        let regular = UserDto {
+            username: "regular".to_string(),
+            roles: "user".to_string(),
+            email: Some("Regular@Example.com".to_string()),
+            ..super_user
+        };
+        block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &regular,
+        ))
+        .unwrap();
+        let stored = block_on(users.find_user_by_username("regular".to_string()))
+            .unwrap()
+            .unwrap();
+        assert!(verify_password(&regular.password, stored.password()));
+
+        let duplicate = UserDto {
+            username: "Regular".to_string(),
+            ..regular.clone()
+        };
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &duplicate,
+        ));
+        assert!(matches!(res, Err(CreateUserError::UsernameTaken)));
+
+        let same_email = UserDto {
+            username: "other".to_string(),
+            ..regular.clone()
+        };
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &same_email,
+        ));
+        assert!(matches!(res, Err(CreateUserError::EmailTaken)));
+        let account = block_on(accounts.find_account_by_slug("edb".to_string()))
+            .unwrap()
+            .unwrap();
+        let found = block_on(find_user_by_email_with_repos(
+            &users,
+            &normalizer,
+            account.id(),
+            " REGULAR@example.com",
+        ))
+        .unwrap();
+        assert_eq!(Some("regular"), found.as_ref().map(|u| u.username()));
+
+        let bad_email = UserDto {
+            username: "other".to_string(),
+            email: Some("not-an-email".to_string()),
+            ..regular.clone()
+        };
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &bad_email,
+        ));
+        assert!(matches!(res, Err(CreateUserError::UserInvalid(_))));
+
+        let reserved = UserDto {
+            username: "other".to_string(),
+            roles: format!("user, {}", SUPER_USER_ROLE),
+            email: None,
+            ..regular.clone()
+        };
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &reserved,
+        ));
+        assert!(matches!(res, Err(CreateUserError::ReservedRole(_))));
+
+        let lookalike = UserDto {
+            username: "regu1ar".to_string(),
+            email: None,
+            ..regular.clone()
+        };
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &lookalike,
+        ));
+        assert!(matches!(res, Err(CreateUserError::UsernameConfusable(u)) if u == "regular"));
+
+        let breached = UserDto {
+            username: "breached".to_string(),
+            password: "password".into(),
+            email: None,
+            ..regular.clone()
+        };
+        let filter = BloomFilter::from_hash_lines(
+            "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493".as_bytes(),
+            10,
+            0.001,
+        )
+        .unwrap();
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &filter,
+            &breached,
+        ));
+        assert!(matches!(res, Err(CreateUserError::PasswordBreached)));
+
+        let orphan = UserDto {
+            username: "orphan".to_string(),
+            account_id: Uuid::new_v4(),
+            ..regular
+        };
+        let res = block_on(create_user_with_repos(
+            &users,
+            &accounts,
+            &normalizer,
+            &NoBreachCheck,
+            &orphan,
+        ));
+        assert!(matches!(res, Err(CreateUserError::AccountNotFound)));
+    }
+
+    #[test]
+    pub fn test_memory_migrations_sorted_by_seq_order() {
+        let repo = MemoryMigrationRepo::default();
+        for (name, seq_order) in [("second", 2), ("first", 1)] {
+            let migration = Migration {
+                name: name.to_string(),
+                seq_order,
+                ..default_migration()
+            };
+            block_on(repo.create(migration)).unwrap();
+        }
+        let names: Vec<String> = block_on(repo.find_all())
+            .unwrap()
+            .into_iter()
+            .map(|m| m.name)
+            .collect();
+        assert_eq!(vec!["first", "second"], names);
+        assert!(block_on(repo.find_by_name("first".to_string()))
+            .unwrap()
+            .is_some());
+    }
+}
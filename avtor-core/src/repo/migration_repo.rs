@@ -0,0 +1,41 @@
+use async_trait::async_trait;
+use tokio_postgres::Transaction;
+
+use crate::error::DbError;
+use crate::models::migrations::{create, find_all, find_one, Migration, MigrationCriteria};
+
+#[async_trait]
+pub trait MigrationRepo: Send + Sync {
+    /// Ordered by `seq_order`.
+    async fn find_all(&self) -> Result<Vec<Migration>, DbError>;
+
+    async fn find_by_name(&self, name: String) -> Result<Option<Migration>, DbError>;
+
+    async fn create(&self, migration: Migration) -> Result<(), DbError>;
+}
+
+/// Delegates to the closure-style functions in `models::migrations`.
+pub struct PgMigrationRepo<'a> {
+    trans: &'a Transaction<'a>,
+}
+
+impl<'a> PgMigrationRepo<'a> {
+    pub fn new(trans: &'a Transaction<'a>) -> Self {
+        PgMigrationRepo { trans }
+    }
+}
+
+#[async_trait]
+impl<'a> MigrationRepo for PgMigrationRepo<'a> {
+    async fn find_all(&self) -> Result<Vec<Migration>, DbError> {
+        find_all(self.trans)().await
+    }
+
+    async fn find_by_name(&self, name: String) -> Result<Option<Migration>, DbError> {
+        find_one(self.trans)(vec![MigrationCriteria::NameEq(name)]).await
+    }
+
+    async fn create(&self, migration: Migration) -> Result<(), DbError> {
+        create(self.trans)(migration).await
+    }
+}
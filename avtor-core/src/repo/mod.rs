@@ -1 +1,12 @@
+pub mod account_repo;
+pub mod email_template_repo;
+pub mod invitation_repo;
+pub mod memory;
+pub mod migration_repo;
 pub mod user_repo;
+
+pub use account_repo::{AccountRepo, PgAccountRepo};
+pub use email_template_repo::{EmailTemplateRepo, PgEmailTemplateRepo};
+pub use invitation_repo::{InvitationRepo, PgInvitationRepo};
+pub use migration_repo::{MigrationRepo, PgMigrationRepo};
+pub use user_repo::{PgUserRepo, UserRepo};
@@ -1 +1,76 @@
-use crate::{models::users::User, postgres_common::core::insert};
+use async_trait::async_trait;
+use tokio_postgres::Transaction;
+
+use crate::models::users::{
+    find_super_user, find_user_by_email, find_user_by_username, find_users_by_account, insert_user,
+    AccountId, CreateSuperUserError, User,
+};
+
+#[async_trait]
+pub trait UserRepo: Send + Sync {
+    async fn find_super_user(&self) -> Result<Option<User>, CreateSuperUserError>;
+
+    /// `username` must already be normalized, see `IdentifierNormalizer`.
+    async fn find_user_by_username(
+        &self,
+        username: String,
+    ) -> Result<Option<User>, CreateSuperUserError>;
+
+    /// `email` must already be normalized, see `IdentifierNormalizer`.
+    async fn find_user_by_email(
+        &self,
+        account_id: AccountId,
+        email: String,
+    ) -> Result<Option<User>, CreateSuperUserError>;
+
+    async fn find_users_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<User>, CreateSuperUserError>;
+
+    async fn insert_user(&self, user: User) -> Result<(), CreateSuperUserError>;
+}
+
+/// Delegates to the closure-style functions in `models::users`.
+pub struct PgUserRepo<'a> {
+    trans: &'a Transaction<'a>,
+}
+
+impl<'a> PgUserRepo<'a> {
+    pub fn new(trans: &'a Transaction<'a>) -> Self {
+        PgUserRepo { trans }
+    }
+}
+
+#[async_trait]
+impl<'a> UserRepo for PgUserRepo<'a> {
+    async fn find_super_user(&self) -> Result<Option<User>, CreateSuperUserError> {
+        find_super_user(self.trans)().await
+    }
+
+    async fn find_user_by_username(
+        &self,
+        username: String,
+    ) -> Result<Option<User>, CreateSuperUserError> {
+        find_user_by_username(self.trans)(username).await
+    }
+
+    async fn find_user_by_email(
+        &self,
+        account_id: AccountId,
+        email: String,
+    ) -> Result<Option<User>, CreateSuperUserError> {
+        find_user_by_email(self.trans)(account_id, email).await
+    }
+
+    async fn find_users_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<User>, CreateSuperUserError> {
+        find_users_by_account(self.trans)(account_id).await
+    }
+
+    async fn insert_user(&self, user: User) -> Result<(), CreateSuperUserError> {
+        insert_user(self.trans)(user).await
+    }
+}
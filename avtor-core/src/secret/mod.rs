@@ -0,0 +1,112 @@
+use std::fmt::{self, Debug, Display};
+
+use bytes::BytesMut;
+use postgres_types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+use serde::{Deserialize, Serialize, Serializer};
+use zeroize::Zeroize;
+
+const REDACTED: &str = "********";
+
+/// A credential that is wiped from memory when dropped and never shows up in
+/// `Debug`, `Display` or serialized output. Use `expose_secret` where the
+/// real value is needed, e.g. to hash it.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Secret<T> {
+        Secret(value)
+    }
+
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl From<String> for Secret<String> {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+impl From<&str> for Secret<String> {
+    fn from(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl<T: Zeroize> Display for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+/// Serializes as the redaction marker; validation errors, for one, serialize
+/// the offending value.
+impl<T: Zeroize> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<T: Zeroize + ToSql> ToSql for Secret<T> {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        self.0.to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        T::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a, T: Zeroize + FromSql<'a>> FromSql<'a> for Secret<T> {
+    fn from_sql(
+        ty: &Type,
+        raw: &'a [u8],
+    ) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        T::from_sql(ty, raw).map(Secret)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        T::accepts(ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+
+    #[test]
+    pub fn test_secret_is_redacted() {
+        let secret = Secret::from("hunter2");
+        assert_eq!("Secret(********)", format!("{:?}", secret));
+        assert_eq!("********", secret.to_string());
+        assert_eq!("\"********\"", serde_json::to_string(&secret).unwrap());
+        assert_eq!("hunter2", secret.expose_secret());
+    }
+
+    #[test]
+    pub fn test_secret_deserializes_plain_value() {
+        let secret: Secret<String> = serde_json::from_str("\"hunter2\"").unwrap();
+        assert_eq!("hunter2", secret.expose_secret());
+    }
+}
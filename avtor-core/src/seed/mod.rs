@@ -0,0 +1,269 @@
+//! Loads accounts and users for staging environments and tests. Unlike
+//! migrations, seeding can be repeated: every row is matched by its natural
+//! key (account slug, username) and only written when it differs.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{Client, Transaction};
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::identifier::IdentifierNormalizer;
+use crate::models::users::{
+    account_from_dto, find_account_by_slug, find_user_by_username, hash_map_from_validation_errors,
+    roles_contain, upsert_account, upsert_user, user_from_dto, AccountDto, UserDto, SYSTEM_ROLE,
+};
+use crate::password::{hash_password, verify_password};
+use crate::secret::Secret;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SeedError {
+    #[error("seeded {key} is invalid: {fields:?}")]
+    Invalid {
+        key: String,
+        fields: HashMap<String, String>,
+    },
+
+    #[error("seeded user {0} can't have the system role")]
+    ReservedRole(String),
+
+    #[error("seeded user {username} refers to unknown account {account}")]
+    AccountNotFound { username: String, account: String },
+
+    #[error("Repo Error: {0}")]
+    RepoError(String),
+}
+
+impl From<tokio_postgres::Error> for SeedError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        SeedError::RepoError(e.to_string())
+    }
+}
+
+/// What one `Seeder` did, counted by natural key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SeedCounts {
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// One kind of seeded data. `seed` must leave the rows as described however
+/// often it runs.
+#[async_trait]
+pub trait Seeder: Send + Sync {
+    /// Shown in reports, e.g. `accounts`.
+    fn name(&self) -> &'static str;
+
+    async fn seed(&self, trans: &Transaction<'_>) -> Result<SeedCounts, SeedError>;
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedAccount {
+    /// Natural key; kept as given instead of being derived from the name.
+    pub slug: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedUser {
+    /// Natural key.
+    pub username: String,
+    pub password: Secret<String>,
+    pub roles: String,
+    /// Slug of the account, seeded in the same file or already present.
+    pub account: String,
+    #[serde(default)]
+    pub email: Option<String>,
+}
+
+/// Layout of a seed file, in YAML or JSON:
+/// `{accounts: [{slug, name}], users: [{username, password, roles, account,
+/// email?}]}`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SeedFile {
+    pub accounts: Vec<SeedAccount>,
+    pub users: Vec<SeedUser>,
+}
+
+impl SeedFile {
+    /// Accounts before users so users can refer to accounts from the same
+    /// file.
+    pub fn seeders(self, normalizer: Arc<dyn IdentifierNormalizer>) -> Vec<Box<dyn Seeder>> {
+        vec![
+            Box::new(AccountSeeder(self.accounts)),
+            Box::new(UserSeeder {
+                users: self.users,
+                normalizer,
+            }),
+        ]
+    }
+}
+
+pub struct AccountSeeder(pub Vec<SeedAccount>);
+
+#[async_trait]
+impl Seeder for AccountSeeder {
+    fn name(&self) -> &'static str {
+        "accounts"
+    }
+
+    async fn seed(&self, trans: &Transaction<'_>) -> Result<SeedCounts, SeedError> {
+        let mut counts = SeedCounts::default();
+        for seed in &self.0 {
+            let existing = find_account_by_slug(trans)(seed.slug.clone())
+                .await
+                .map_err(|e| SeedError::RepoError(e.to_string()))?;
+            let id = match &existing {
+                Some(account) if account.name == seed.name => {
+                    counts.unchanged += 1;
+                    continue;
+                }
+                Some(account) => {
+                    counts.updated += 1;
+                    account.id().uuid()
+                }
+                None => {
+                    counts.inserted += 1;
+                    Uuid::new_v4()
+                }
+            };
+            let dto = AccountDto {
+                id,
+                name: seed.name.clone(),
+            };
+            dto.validate().map_err(|e| SeedError::Invalid {
+                key: format!("account {}", seed.slug),
+                fields: hash_map_from_validation_errors(e),
+            })?;
+            let account = account_from_dto(dto).with_slug(seed.slug.clone());
+            upsert_account(trans)(account)
+                .await
+                .map_err(|e| SeedError::RepoError(e.to_string()))?;
+        }
+        Ok(counts)
+    }
+}
+
+pub struct UserSeeder {
+    pub users: Vec<SeedUser>,
+    /// Applied to every username and email before it is matched or written.
+    pub normalizer: Arc<dyn IdentifierNormalizer>,
+}
+
+#[async_trait]
+impl Seeder for UserSeeder {
+    fn name(&self) -> &'static str {
+        "users"
+    }
+
+    /// The password is only rehashed when it no longer verifies, so an
+    /// unchanged user is left alone.
+    async fn seed(&self, trans: &Transaction<'_>) -> Result<SeedCounts, SeedError> {
+        let mut counts = SeedCounts::default();
+        for seed in &self.users {
+            let username = self.normalizer.username(&seed.username);
+            let email = seed.email.as_deref().map(|e| self.normalizer.email(e));
+            if roles_contain(&seed.roles, SYSTEM_ROLE) {
+                return Err(SeedError::ReservedRole(seed.username.clone()));
+            }
+            let account = find_account_by_slug(trans)(seed.account.clone())
+                .await
+                .map_err(|e| SeedError::RepoError(e.to_string()))?
+                .ok_or_else(|| SeedError::AccountNotFound {
+                    username: seed.username.clone(),
+                    account: seed.account.clone(),
+                })?;
+            let existing = find_user_by_username(trans)(username.clone())
+                .await
+                .map_err(|e| SeedError::RepoError(e.to_string()))?;
+            let id = match &existing {
+                Some(user)
+                    if user.roles() == seed.roles
+                        && user.account_id() == account.id().uuid()
+                        && user.email() == email.as_deref()
+                        && verify_password(&seed.password, user.password()) =>
+                {
+                    counts.unchanged += 1;
+                    continue;
+                }
+                Some(user) => {
+                    counts.updated += 1;
+                    user.id().uuid()
+                }
+                None => {
+                    counts.inserted += 1;
+                    Uuid::new_v4()
+                }
+            };
+            let dto = UserDto {
+                id,
+                username,
+                password: seed.password.clone(),
+                roles: seed.roles.clone(),
+                account_id: account.id().uuid(),
+                email,
+            };
+            dto.validate().map_err(|e| SeedError::Invalid {
+                key: format!("user {}", seed.username),
+                fields: hash_map_from_validation_errors(e),
+            })?;
+            let password =
+                hash_password(&dto.password).map_err(|e| SeedError::RepoError(e.to_string()))?;
+            let user = user_from_dto(UserDto { password, ..dto });
+            upsert_user(trans)(user)
+                .await
+                .map_err(|e| SeedError::RepoError(format!("user {}: {}", seed.username, e)))?;
+        }
+        Ok(counts)
+    }
+}
+
+/// Runs `seeders` in order in one transaction, so a failing seeder leaves
+/// nothing behind.
+pub async fn run_seeders(
+    client: &mut Client,
+    seeders: &[Box<dyn Seeder>],
+) -> Result<Vec<(&'static str, SeedCounts)>, SeedError> {
+    let trans = client.transaction().await?;
+    let mut counts = vec![];
+    for seeder in seeders {
+        counts.push((seeder.name(), seeder.seed(&trans).await?));
+    }
+    trans.commit().await?;
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::SeedFile;
+    use crate::identifier::StandardNormalizer;
+
+    #[test]
+    pub fn test_seed_file_orders_accounts_first() {
+        let file: SeedFile = serde_json::from_str(
+            r#"{"users": [{"username": "demo", "password": "!Q2w3e4r5t",
+                "roles": "user", "account": "acme"}],
+                "accounts": [{"slug": "acme", "name": "Acme"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(1, file.users.len());
+        let names: Vec<&str> = file
+            .seeders(Arc::new(StandardNormalizer::default()))
+            .iter()
+            .map(|s| s.name())
+            .collect();
+        assert_eq!(vec!["accounts", "users"], names);
+
+        let unknown = serde_json::from_str::<SeedFile>(r#"{"roles": []}"#);
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const EXPIRES_PARAM: &str = "expires";
+const SIGNATURE_PARAM: &str = "signature";
+
+/// How long after `expires` a url is still accepted by `verify_url`, to
+/// absorb clock drift between the server that signed it and the one checking.
+/// Callers that read a leeway from config use it as the default.
+pub const DEFAULT_LEEWAY_SECONDS: i64 = 30;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SignedUrlError {
+    #[error("malformed signed url")]
+    Malformed,
+
+    #[error("claim name {0} is reserved")]
+    ReservedClaim(String),
+
+    #[error("signature does not match")]
+    InvalidSignature,
+
+    #[error("signed url expired at {0}")]
+    Expired(DateTime<Utc>),
+}
+
+/// The verified contents of a signed url. `claims` typically carries the avtor
+/// user or account id the url was issued to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedUrl {
+    pub path: String,
+    pub expires: DateTime<Utc>,
+    pub claims: BTreeMap<String, String>,
+    /// How far past `expires` the url was when it was accepted thanks to the
+    /// leeway; worth recording, as a steady stream of these points at clock
+    /// skew between servers.
+    pub past_expiry: Option<Duration>,
+}
+
+fn encode_component(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn decode_component(s: &str) -> Result<String, SignedUrlError> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).ok_or(SignedUrlError::Malformed)?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| SignedUrlError::Malformed)?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| SignedUrlError::Malformed)
+}
+
+fn mac_for(key: &[u8], message: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac
+}
+
+/// Builds `path?<claims>&expires=<unix seconds>&signature=<hex hmac-sha256>`.
+/// Claims are written in sorted order so the signed string is canonical.
+pub fn sign_url(
+    key: &[u8],
+    path: &str,
+    expires: DateTime<Utc>,
+    claims: &BTreeMap<String, String>,
+) -> Result<String, SignedUrlError> {
+    let mut query: Vec<String> = vec![];
+    for (name, value) in claims {
+        if name == EXPIRES_PARAM || name == SIGNATURE_PARAM {
+            return Err(SignedUrlError::ReservedClaim(name.clone()));
+        }
+        query.push(format!(
+            "{}={}",
+            encode_component(name),
+            encode_component(value)
+        ));
+    }
+    query.push(format!("{}={}", EXPIRES_PARAM, expires.timestamp()));
+    let unsigned = format!("{}?{}", encode_component(path), query.join("&"));
+    let signature: String = mac_for(key, &unsigned)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(format!("{}&{}={}", unsigned, SIGNATURE_PARAM, signature))
+}
+
+/// `verify_url_with_leeway` with `DEFAULT_LEEWAY_SECONDS`.
+pub fn verify_url(key: &[u8], url: &str, now: DateTime<Utc>) -> Result<SignedUrl, SignedUrlError> {
+    verify_url_with_leeway(key, url, now, Duration::seconds(DEFAULT_LEEWAY_SECONDS))
+}
+
+/// Checks the signature before looking at anything else, then the expiry,
+/// accepting urls up to `leeway` past it.
+pub fn verify_url_with_leeway(
+    key: &[u8],
+    url: &str,
+    now: DateTime<Utc>,
+    leeway: Duration,
+) -> Result<SignedUrl, SignedUrlError> {
+    let marker = format!("&{}=", SIGNATURE_PARAM);
+    let split = url.rfind(&marker).ok_or(SignedUrlError::Malformed)?;
+    let (unsigned, signature_hex) = (&url[..split], &url[split + marker.len()..]);
+    let signature = (0..signature_hex.len())
+        .step_by(2)
+        .map(|i| {
+            signature_hex
+                .get(i..i + 2)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or(SignedUrlError::Malformed)
+        })
+        .collect::<Result<Vec<u8>, SignedUrlError>>()?;
+    mac_for(key, unsigned)
+        .verify_slice(&signature)
+        .map_err(|_| SignedUrlError::InvalidSignature)?;
+
+    let (path, query) = unsigned.split_once('?').ok_or(SignedUrlError::Malformed)?;
+    let mut claims = BTreeMap::new();
+    let mut expires = None;
+    for pair in query.split('&') {
+        let (name, value) = pair.split_once('=').ok_or(SignedUrlError::Malformed)?;
+        if name == EXPIRES_PARAM {
+            let secs: i64 = value.parse().map_err(|_| SignedUrlError::Malformed)?;
+            expires = Some(
+                Utc.timestamp_opt(secs, 0)
+                    .single()
+                    .ok_or(SignedUrlError::Malformed)?,
+            );
+        } else {
+            claims.insert(decode_component(name)?, decode_component(value)?);
+        }
+    }
+    let expires = expires.ok_or(SignedUrlError::Malformed)?;
+    if now >= expires + leeway {
+        return Err(SignedUrlError::Expired(expires));
+    }
+    Ok(SignedUrl {
+        path: decode_component(path)?,
+        expires,
+        claims,
+        past_expiry: Some(now - expires).filter(|d| *d >= Duration::zero()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{sign_url, verify_url, verify_url_with_leeway, SignedUrlError};
+
+    const KEY: &[u8] = b"test signing key";
+
+    fn claims() -> BTreeMap<String, String> {
+        let mut claims = BTreeMap::new();
+        claims.insert("user_id".to_string(), "some user&id=1".to_string());
+        claims
+    }
+
+    #[test]
+    pub fn test_sign_and_verify_round_trip() {
+        let expires = Utc::now() + Duration::minutes(5);
+        let url = sign_url(KEY, "/files/report 1.pdf", expires, &claims()).unwrap();
+        let verified = verify_url(KEY, &url, Utc::now()).unwrap();
+        assert_eq!("/files/report 1.pdf", verified.path);
+        assert_eq!(expires.timestamp(), verified.expires.timestamp());
+        assert_eq!(claims(), verified.claims);
+        assert_eq!(None, verified.past_expiry);
+    }
+
+    #[test]
+    pub fn test_verify_rejects_tampering_and_wrong_key() {
+        let expires = Utc::now() + Duration::minutes(5);
+        let url = sign_url(KEY, "/files/a", expires, &claims()).unwrap();
+        let tampered = url.replace("/files/a", "/files/b");
+        assert_eq!(
+            Err(SignedUrlError::InvalidSignature),
+            verify_url(KEY, &tampered, Utc::now())
+        );
+        assert_eq!(
+            Err(SignedUrlError::InvalidSignature),
+            verify_url(b"other key", &url, Utc::now())
+        );
+        assert_eq!(
+            Err(SignedUrlError::Malformed),
+            verify_url(KEY, "/files/a", Utc::now())
+        );
+    }
+
+    #[test]
+    pub fn test_verify_rejects_expired() {
+        let expires = Utc::now() - Duration::seconds(31);
+        let url = sign_url(KEY, "/files/a", expires, &BTreeMap::new()).unwrap();
+        assert!(matches!(
+            verify_url(KEY, &url, Utc::now()),
+            Err(SignedUrlError::Expired(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_verify_leeway() {
+        let expires = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let url = sign_url(KEY, "/files/a", expires, &BTreeMap::new()).unwrap();
+        let now = expires + Duration::seconds(10);
+        let verified = verify_url(KEY, &url, now).unwrap();
+        assert_eq!(Some(Duration::seconds(10)), verified.past_expiry);
+        assert!(matches!(
+            verify_url_with_leeway(KEY, &url, now, Duration::zero()),
+            Err(SignedUrlError::Expired(_))
+        ));
+    }
+}
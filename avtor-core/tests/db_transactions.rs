@@ -0,0 +1,71 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use avtor_core::db::{with_transaction, TransactionError};
+use tokio_postgres::{Client, NoTls};
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+async fn connect() -> Client {
+    let conn_str = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env_or("db_user", "avtor"),
+        env_or("db_pass", "avtor"),
+        env_or("db_host", "localhost"),
+        env_or("db_port", "5556"),
+        env_or("db_name", "avtor"),
+    );
+    let (client, conn) = tokio_postgres::connect(&conn_str, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+async fn count(client: &Client) -> i64 {
+    client
+        .query_one("select count(*) from tx_items", &[])
+        .await
+        .unwrap()
+        .get(0)
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn commits_on_ok_and_rolls_back_on_err() {
+    let mut client = connect().await;
+    client
+        .batch_execute("create temp table tx_items (name text not null)")
+        .await
+        .unwrap();
+
+    let r: Result<(), TransactionError<String>> = with_transaction(&mut client, |trans| {
+        Box::pin(async move {
+            trans
+                .execute("insert into tx_items (name) values ('kept')", &[])
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(())
+        })
+    })
+    .await;
+    assert!(r.is_ok());
+    assert_eq!(1, count(&client).await);
+
+    let r: Result<(), TransactionError<String>> = with_transaction(&mut client, |trans| {
+        Box::pin(async move {
+            trans
+                .execute("insert into tx_items (name) values ('dropped')", &[])
+                .await
+                .map_err(|e| e.to_string())?;
+            Err("use case failed".to_string())
+        })
+    })
+    .await;
+    assert!(matches!(r, Err(TransactionError::Failed(e)) if e == "use case failed"));
+    assert_eq!(1, count(&client).await);
+}
@@ -0,0 +1,117 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+//! The entity is declared here so the derive is also checked from outside
+//! avtor-core.
+
+use avtor_core::config::database_url_from_env;
+use avtor_core::postgres_common::core::{insert_many, select_page, upsert, Entity};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::NoTls;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Entity)]
+#[entity(table = "entity_method_items")]
+struct Item {
+    id: Uuid,
+    name: String,
+    seq: i32,
+}
+
+#[tokio::test]
+#[ignore]
+async fn generated_methods_round_trip() {
+    let url = database_url_from_env().unwrap();
+    let (mut client, conn) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    let mut trans = client.transaction().await.unwrap();
+    trans
+        .batch_execute(
+            "create temp table entity_method_items (id uuid primary key, name text not null, seq int not null)",
+        )
+        .await
+        .unwrap();
+
+    let items: Vec<Item> = (0..3)
+        .map(|seq| Item {
+            id: Uuid::new_v4(),
+            name: format!("item_{}", seq),
+            seq,
+        })
+        .collect();
+    items[0].insert(&trans).await.unwrap();
+    let rows: Vec<Vec<&(dyn ToSql + Sync)>> = items[1..]
+        .iter()
+        .map(|i| vec![&i.id as &(dyn ToSql + Sync), &i.name, &i.seq])
+        .collect();
+    let fields = ["name".to_string(), "seq".to_string()];
+    let table = Item::table_name().to_string();
+    let inserted = insert_many(&mut trans, &table, &"id".to_string(), &fields, &rows)
+        .await
+        .unwrap();
+    assert_eq!(2, inserted);
+    let renamed = Item {
+        name: "renamed".to_string(),
+        ..items[1].clone()
+    };
+    renamed.update(&trans).await.unwrap();
+    let upserted = Item {
+        seq: 10,
+        ..renamed.clone()
+    };
+    upsert(
+        &trans,
+        &table,
+        &"id".to_string(),
+        &fields,
+        &upserted.id,
+        &[&upserted.name, &upserted.seq],
+    )
+    .await
+    .unwrap();
+
+    let found = Item::find_by_id(&trans, &items[1].id).await.unwrap();
+    assert_eq!(Some(upserted), found);
+    assert_eq!(
+        None,
+        Item::find_by_id(&trans, &Uuid::new_v4()).await.unwrap()
+    );
+
+    let mut later = Item::find_where(&trans, &[ItemCriteria::SeqGte(1)])
+        .await
+        .unwrap();
+    later.sort_by_key(|i| i.seq);
+    assert_eq!(
+        vec!["item_2", "renamed"],
+        later.iter().map(|i| i.name.as_str()).collect::<Vec<&str>>()
+    );
+
+    let page = select_page(
+        &trans,
+        &table,
+        &["id", "name", "seq"],
+        &vec![],
+        &"seq".to_string(),
+        &"id".to_string(),
+        None,
+        2,
+        |row| -> Result<Item, tokio_postgres::Error> {
+            Ok(Item {
+                id: row.try_get("id")?,
+                name: row.try_get("name")?,
+                seq: row.try_get("seq")?,
+            })
+        },
+        |i: &Item| (i.seq, i.id),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        vec![&items[0], &items[2]],
+        page.items.iter().collect::<Vec<_>>()
+    );
+    assert!(page.next_cursor.is_some());
+}
@@ -0,0 +1,202 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use avtor_core::config::database_url_from_env;
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::migrations::Runner;
+use avtor_core::models::invitations::{
+    accept_invitation, find_invitations, invite_user, InvitationDto, InvitationError,
+    InvitationStatus,
+};
+use avtor_core::models::users::{account_from_dto, AccountDto, AccountId};
+use avtor_core::password::breach::NoBreachCheck;
+use avtor_core::repo::{AccountRepo, PgAccountRepo, PgInvitationRepo, PgUserRepo, UserRepo};
+use avtor_core::secret::Secret;
+use avtor_core::signed_url::DEFAULT_LEEWAY_SECONDS;
+use chrono::{Duration, Utc};
+use tokio_postgres::{Client, NoTls, Transaction};
+use uuid::Uuid;
+
+async fn connect() -> Client {
+    let url = database_url_from_env().unwrap();
+    let (client, conn) = tokio_postgres::connect(&url, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn invitation_accepted_through_postgres_repos() {
+    let mut client = connect().await;
+    Runner::builtin().run(&mut client).await.unwrap();
+    // rolled back when dropped
+    let trans = client.transaction().await.unwrap();
+    let (invitations, users, accounts) = (
+        PgInvitationRepo::new(&trans),
+        PgUserRepo::new(&trans),
+        PgAccountRepo::new(&trans),
+    );
+    let account_id = Uuid::new_v4();
+    accounts
+        .insert_account(account_from_dto(AccountDto {
+            id: account_id,
+            name: format!("invitations {}", account_id.to_simple()),
+        }))
+        .await
+        .unwrap();
+
+    let key = Secret::from("key");
+    let normalizer = StandardNormalizer::default();
+    let issued = invite_user(
+        &invitations,
+        &accounts,
+        &normalizer,
+        &key,
+        Duration::days(1),
+        Utc::now(),
+        Duration::seconds(DEFAULT_LEEWAY_SECONDS),
+        &InvitationDto {
+            email: "invitee@example.com".to_string(),
+            account_id,
+            roles: "user".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let username = format!("invitee_{}", account_id.to_simple());
+    accept_invitation(
+        &invitations,
+        &users,
+        &accounts,
+        &normalizer,
+        &NoBreachCheck,
+        &key,
+        Utc::now(),
+        Duration::seconds(DEFAULT_LEEWAY_SECONDS),
+        &issued.token,
+        &username,
+        "!Q2w3e4r5t".into(),
+    )
+    .await
+    .unwrap();
+
+    let user = users
+        .find_user_by_username(username)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(Some("invitee@example.com"), user.email());
+    let accepted = find_invitations(
+        &invitations,
+        AccountId::from(account_id),
+        Some(InvitationStatus::Accepted),
+        Utc::now(),
+        Duration::seconds(DEFAULT_LEEWAY_SECONDS),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        vec![issued.invitation.id()],
+        accepted.iter().map(|i| i.id()).collect::<Vec<_>>()
+    );
+}
+
+async fn accept(
+    trans: &Transaction<'_>,
+    key: &Secret<String>,
+    token: &Secret<String>,
+    username: &str,
+) -> Result<Uuid, InvitationError> {
+    accept_invitation(
+        &PgInvitationRepo::new(trans),
+        &PgUserRepo::new(trans),
+        &PgAccountRepo::new(trans),
+        &StandardNormalizer::default(),
+        &NoBreachCheck,
+        key,
+        Utc::now(),
+        Duration::seconds(DEFAULT_LEEWAY_SECONDS),
+        token,
+        &format!("{}_{}", username, Uuid::new_v4().to_simple()),
+        "!Q2w3e4r5t".into(),
+    )
+    .await
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn second_concurrent_accept_of_a_token_fails() {
+    let (mut first, mut second) = (connect().await, connect().await);
+    Runner::builtin().run(&mut first).await.unwrap();
+    let key = Secret::from("key");
+    let normalizer = StandardNormalizer::default();
+    let account_id = Uuid::new_v4();
+
+    // both connections have to see the invitation, so it is committed
+    let setup = first.transaction().await.unwrap();
+    let (invitations, accounts) = (PgInvitationRepo::new(&setup), PgAccountRepo::new(&setup));
+    accounts
+        .insert_account(account_from_dto(AccountDto {
+            id: account_id,
+            name: format!("concurrent accepts {}", account_id.to_simple()),
+        }))
+        .await
+        .unwrap();
+    let issued = invite_user(
+        &invitations,
+        &accounts,
+        &normalizer,
+        &key,
+        Duration::days(1),
+        Utc::now(),
+        Duration::seconds(DEFAULT_LEEWAY_SECONDS),
+        &InvitationDto {
+            email: "invitee@example.com".to_string(),
+            account_id,
+            roles: "user".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    setup.commit().await.unwrap();
+
+    let first_trans = first.transaction().await.unwrap();
+    accept(&first_trans, &key, &issued.token, "first")
+        .await
+        .unwrap();
+    // the second accept waits on the invitation's lock until the first commits
+    let second_trans = second.transaction().await.unwrap();
+    let (second_result, _) = tokio::join!(
+        accept(&second_trans, &key, &issued.token, "second"),
+        async {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            first_trans.commit().await.unwrap();
+        }
+    );
+    second_trans.rollback().await.unwrap();
+    assert!(
+        matches!(second_result, Err(InvitationError::NotPending("accepted"))),
+        "{:?}",
+        second_result
+    );
+
+    let cleanup = first.transaction().await.unwrap();
+    let users = PgUserRepo::new(&cleanup);
+    let accepted = users
+        .find_users_by_account(AccountId::from(account_id))
+        .await
+        .unwrap();
+    assert_eq!(1, accepted.len());
+    for sql in [
+        "delete from users where account_id = $1",
+        "delete from invitations where account_id = $1",
+        "delete from accounts where id = $1",
+    ] {
+        cleanup.execute(sql, &[&account_id]).await.unwrap();
+    }
+    cleanup.commit().await.unwrap();
+}
@@ -0,0 +1,271 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use std::time::Duration;
+
+use avtor_core::migrations::builtin::builtin_migrations;
+use avtor_core::migrations::{MigrationDef, MigrationError, Runner, MIGRATION_LOCK_KEY};
+use avtor_core::models::migration_runs::{find_runs_by_name, DIRECTION_UP};
+use avtor_core::models::migrations::{find_latest_seq_order, SchemaVersionError};
+use tokio_postgres::{Client, NoTls};
+use uuid::Uuid;
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+async fn connect() -> Client {
+    let conn_str = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env_or("db_user", "avtor"),
+        env_or("db_pass", "avtor"),
+        env_or("db_host", "localhost"),
+        env_or("db_port", "5556"),
+        env_or("db_name", "avtor"),
+    );
+    let (client, conn) = tokio_postgres::connect(&conn_str, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn failed_migration_is_recorded() {
+    let mut client = connect().await;
+    Runner::builtin().run(&mut client).await.unwrap();
+
+    let name = format!("failing_{}", Uuid::new_v4().to_simple());
+    let runner = Runner::new(vec![MigrationDef {
+        seq_order: i32::MAX,
+        name: name.clone(),
+        up: "create index if not exists no_such_index on no_such_table (id);".to_string(),
+        down: "".to_string(),
+    }]);
+    let r = runner.run(&mut client).await;
+    assert!(matches!(r, Err(MigrationError::Failed { .. })), "{:?}", r);
+
+    let runs = find_runs_by_name(&client)(name.clone()).await.unwrap();
+    client
+        .execute("delete from migration_runs where name = $1", &[&name])
+        .await
+        .unwrap();
+    assert_eq!(1, runs.len());
+    assert_eq!(DIRECTION_UP, runs[0].direction);
+    assert_eq!(1, runs[0].statements);
+    assert!(runs[0]
+        .error
+        .as_deref()
+        .unwrap_or("")
+        .contains("no_such_table"));
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn run_waits_for_migration_lock() {
+    let holder = connect().await;
+    let mut client = connect().await;
+    holder
+        .execute("select pg_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])
+        .await
+        .unwrap();
+
+    let runner = Runner::builtin().run_lock_wait(Duration::from_millis(300));
+    let r = runner.run(&mut client).await;
+    assert!(matches!(r, Err(MigrationError::InProgress)), "{:?}", r);
+
+    holder
+        .execute("select pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])
+        .await
+        .unwrap();
+    runner.run(&mut client).await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn older_runner_refuses_newer_schema() {
+    let mut client = connect().await;
+    Runner::builtin().run(&mut client).await.unwrap();
+    let latest = find_latest_seq_order(&client).await.unwrap();
+
+    let older = Runner::new(
+        builtin_migrations()
+            .into_iter()
+            .filter(|m| m.seq_order <= 5)
+            .collect(),
+    );
+    let newer = |e: MigrationError| {
+        matches!(
+            e,
+            MigrationError::SchemaVersion(SchemaVersionError::DatabaseNewer { binary: 5, .. })
+        )
+    };
+    assert!(newer(older.run(&mut client).await.unwrap_err()));
+    assert_eq!(latest, find_latest_seq_order(&client).await.unwrap());
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn older_runner_rolls_back_newer_schema() {
+    let mut client = connect().await;
+    let older = Runner::builtin();
+    older.run(&mut client).await.unwrap();
+    let latest = find_latest_seq_order(&client).await.unwrap();
+
+    let table = format!("rollback_probe_{}", Uuid::new_v4().to_simple());
+    let mut migrations = builtin_migrations();
+    migrations.push(MigrationDef {
+        seq_order: older.expected_schema_version() + 1,
+        name: table.clone(),
+        up: format!("create table {} (id int);", table),
+        down: format!("drop table {};", table),
+    });
+    Runner::new(migrations).run(&mut client).await.unwrap();
+
+    let rolled_back = older.rollback(&mut client, None).await.unwrap();
+    let exists: bool = client
+        .query_one("select to_regclass($1) is not null", &[&table])
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(
+        vec![table],
+        rolled_back.into_iter().map(|m| m.name).collect::<Vec<_>>()
+    );
+    assert!(!exists);
+    assert_eq!(latest, find_latest_seq_order(&client).await.unwrap());
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn irreversible_migration_stops_rollback() {
+    let mut client = connect().await;
+    let runner = Runner::builtin();
+    runner.run(&mut client).await.unwrap();
+    let latest = find_latest_seq_order(&client).await.unwrap();
+
+    let r = runner.rollback(&mut client, Some(6)).await;
+    assert!(
+        matches!(&r, Err(MigrationError::Irreversible(names))
+            if names == &["migration_07_normalized_usernames"]),
+        "{:?}",
+        r
+    );
+    assert_eq!(latest, find_latest_seq_order(&client).await.unwrap());
+}
+
+/// migration_01 as the first avtor-cli stored it, before checksums existed.
+const BASELINE_MIGRATION_01_UP: &str = "
+create table if not exists accounts (
+  id uuid not null primary key,
+  name varchar(255),
+  created_on timestamp default current_timestamp
+);
+                        
+CREATE table if not exists users (
+  id uuid not null primary key,
+  username varchar(255) not null,
+  password varchar(255) not null,
+  roles text not null,
+  account_id uuid not null references accounts(id),
+  created_on timestamp default current_timestamp 
+);";
+
+/// A schema of its own, set as the search path, holding the tables of the
+/// first avtor-cli and its migrations row with `up` and no checksum.
+async fn legacy_schema(client: &Client, up: &str) -> String {
+    let schema = format!("legacy_{}", Uuid::new_v4().to_simple());
+    client
+        .batch_execute(&format!(
+            "create schema {schema}; set search_path to {schema};
+            create table migrations (
+              id uuid not null primary key,
+              name varchar(255) not null,
+              seq_order int not null,
+              up text not null,
+              down text not null,
+              applied_on timestamp default current_timestamp
+            );
+            {BASELINE_MIGRATION_01_UP}"
+        ))
+        .await
+        .unwrap();
+    client
+        .execute(
+            "insert into migrations (id, name, seq_order, up, down)
+            values ($1, 'migration_01', 1, $2, 'drop table users; drop table accounts;')",
+            &[&Uuid::new_v4(), &up],
+        )
+        .await
+        .unwrap();
+    schema
+}
+
+async fn stored_checksum(client: &Client) -> Option<String> {
+    client
+        .query_one("select checksum from migrations where seq_order = 1", &[])
+        .await
+        .unwrap()
+        .get(0)
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn baseline_database_migrates_without_drift() {
+    let mut client = connect().await;
+    let schema = legacy_schema(&client, BASELINE_MIGRATION_01_UP).await;
+    let runner = Runner::builtin();
+    let r = runner.run(&mut client).await;
+    let stored = stored_checksum(&client).await;
+    client
+        .batch_execute(&format!("drop schema {} cascade", schema))
+        .await
+        .unwrap();
+    assert!(r.is_ok(), "{:?}", r);
+    assert_eq!(Some(runner.migrations()[0].checksum()), stored);
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn edited_legacy_migration_is_drift() {
+    let mut client = connect().await;
+    let edited = BASELINE_MIGRATION_01_UP.replace("roles text", "roles varchar(64)");
+    let schema = legacy_schema(&client, &edited).await;
+
+    let r = Runner::builtin().run(&mut client).await;
+    let stored = stored_checksum(&client).await;
+    client
+        .batch_execute(&format!("drop schema {} cascade", schema))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&r, Err(MigrationError::Drift(names)) if names == &["migration_01"]),
+        "{:?}",
+        r
+    );
+    assert_eq!(None, stored);
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn tenant_named_system_migrates() {
+    let mut client = connect().await;
+    let schema = legacy_schema(&client, BASELINE_MIGRATION_01_UP).await;
+    client
+        .execute(
+            "insert into accounts (id, name) values ($1, 'System')",
+            &[&Uuid::new_v4()],
+        )
+        .await
+        .unwrap();
+
+    let r = Runner::builtin().run(&mut client).await;
+    client
+        .batch_execute(&format!("drop schema {} cascade", schema))
+        .await
+        .unwrap();
+    assert!(r.is_ok(), "{:?}", r);
+}
@@ -0,0 +1,178 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use avtor_core::error::DbError;
+use avtor_core::postgres_common::core::{
+    insert, select_all, select_all_stream, QueryCondition, Sort, SortDirection,
+};
+use futures::TryStreamExt;
+use tokio_postgres::{Client, NoTls};
+use uuid::Uuid;
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+async fn connect() -> Client {
+    let conn_str = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env_or("db_user", "avtor"),
+        env_or("db_pass", "avtor"),
+        env_or("db_host", "localhost"),
+        env_or("db_port", "5556"),
+        env_or("db_name", "avtor"),
+    );
+    let (client, conn) = tokio_postgres::connect(&conn_str, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+async fn seed(client: &Client) -> Vec<Uuid> {
+    client
+        .batch_execute(
+            "create temp table in_condition_items (id uuid primary key, name text not null, seq int not null)",
+        )
+        .await
+        .unwrap();
+    let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+    for (i, id) in ids.iter().enumerate() {
+        let name = format!("item_{}", i);
+        let seq = i as i32;
+        client
+            .execute(
+                "insert into in_condition_items (id, name, seq) values ($1, $2, $3)",
+                &[id, &name, &seq],
+            )
+            .await
+            .unwrap();
+    }
+    ids
+}
+
+fn seq_of(row: tokio_postgres::Row) -> Result<i32, tokio_postgres::Error> {
+    row.try_get("seq")
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn in_and_nin_bind_arrays() {
+    let client = connect().await;
+    let ids = seed(&client).await;
+    let table = "in_condition_items".to_string();
+
+    let wanted = vec![ids[0], ids[2]];
+    let conds = vec![QueryCondition::In("id".to_string(), &wanted)];
+    let mut found = select_all(&client, &table, &[], &conds, &[], None, None, seq_of)
+        .await
+        .unwrap();
+    found.sort();
+    assert_eq!(vec![0, 2], found);
+
+    let excluded = vec![1, 3];
+    let conds = vec![QueryCondition::Nin("seq".to_string(), &excluded)];
+    let mut found = select_all(&client, &table, &[], &conds, &[], None, None, seq_of)
+        .await
+        .unwrap();
+    found.sort();
+    assert_eq!(vec![0, 2], found);
+
+    let names = vec!["item_3".to_string()];
+    let conds = vec![QueryCondition::In("name".to_string(), &names)];
+    let found = select_all(&client, &table, &[], &conds, &[], None, None, seq_of)
+        .await
+        .unwrap();
+    assert_eq!(vec![3], found);
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn empty_arrays_match_nothing_for_in_and_everything_for_nin() {
+    let client = connect().await;
+    seed(&client).await;
+    let table = "in_condition_items".to_string();
+    let empty: Vec<i32> = vec![];
+
+    let conds = vec![QueryCondition::In("seq".to_string(), &empty)];
+    let found = select_all(&client, &table, &[], &conds, &[], None, None, seq_of)
+        .await
+        .unwrap();
+    assert!(found.is_empty());
+
+    let conds = vec![QueryCondition::Nin("seq".to_string(), &empty)];
+    let found = select_all(&client, &table, &[], &conds, &[], None, None, seq_of)
+        .await
+        .unwrap();
+    assert_eq!(4, found.len());
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn stream_rows_inside_a_transaction() {
+    let mut client = connect().await;
+    let trans = client.transaction().await.unwrap();
+    trans
+        .batch_execute(
+            "create temp table stream_items (id uuid primary key, seq int not null) on commit drop;
+             insert into stream_items (id, seq) values (gen_random_uuid(), 2), (gen_random_uuid(), 1);",
+        )
+        .await
+        .unwrap();
+    let table = "stream_items".to_string();
+    let conds = vec![];
+    let sorts = vec![Sort {
+        field: "seq".to_string(),
+        direction: SortDirection::Asc,
+    }];
+    let stream = select_all_stream(&trans, &table, &["seq"], &conds, &sorts, None, None, seq_of)
+        .await
+        .unwrap();
+    let found: Vec<i32> = stream.try_collect().await.unwrap();
+    assert_eq!(vec![1, 2], found);
+    trans.commit().await.unwrap();
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn duplicate_insert_is_a_unique_violation() {
+    let mut client = connect().await;
+    let trans = client.transaction().await.unwrap();
+    trans
+        .batch_execute(
+            "create temp table unique_items (id uuid primary key, name text not null unique)",
+        )
+        .await
+        .unwrap();
+    let table = "unique_items".to_string();
+    let fields = vec!["name".to_string()];
+    let name = "dup".to_string();
+    insert(
+        &trans,
+        &table,
+        &"id".to_string(),
+        &fields,
+        &Uuid::new_v4(),
+        &[&name],
+    )
+    .await
+    .unwrap();
+    let err = insert(
+        &trans,
+        &table,
+        &"id".to_string(),
+        &fields,
+        &Uuid::new_v4(),
+        &[&name],
+    )
+    .await
+    .unwrap_err();
+    match err {
+        DbError::UniqueViolation { constraint, .. } => {
+            assert_eq!(Some("unique_items_name_key".to_string()), constraint)
+        }
+        other => panic!("expected unique violation, got {:?}", other),
+    }
+}
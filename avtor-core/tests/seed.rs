@@ -0,0 +1,76 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use std::sync::Arc;
+
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::migrations::Runner;
+use avtor_core::seed::{run_seeders, SeedAccount, SeedFile, SeedUser};
+use tokio_postgres::{Client, NoTls};
+use uuid::Uuid;
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+async fn connect() -> Client {
+    let conn_str = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env_or("db_user", "avtor"),
+        env_or("db_pass", "avtor"),
+        env_or("db_host", "localhost"),
+        env_or("db_port", "5556"),
+        env_or("db_name", "avtor"),
+    );
+    let (client, conn) = tokio_postgres::connect(&conn_str, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn seeding_twice_changes_nothing() {
+    let mut client = connect().await;
+    Runner::builtin().run(&mut client).await.unwrap();
+
+    let suffix = Uuid::new_v4().to_simple().to_string();
+    let slug = format!("seed-{}", suffix);
+    let username = format!("seed_{}", suffix);
+    let file = || SeedFile {
+        accounts: vec![SeedAccount {
+            slug: slug.clone(),
+            name: format!("Seed {}", suffix),
+        }],
+        users: vec![SeedUser {
+            username: username.clone(),
+            password: "!Q2w3e4r5t".into(),
+            roles: "user".to_string(),
+            account: slug.clone(),
+            email: Some(format!("Seed.{}@Example.com", suffix)),
+        }],
+    };
+
+    let normalizer = Arc::new(StandardNormalizer::default());
+    let first = run_seeders(&mut client, &file().seeders(normalizer.clone()))
+        .await
+        .unwrap();
+    let second = run_seeders(&mut client, &file().seeders(normalizer))
+        .await
+        .unwrap();
+    client
+        .execute("delete from users where username = $1", &[&username])
+        .await
+        .unwrap();
+    client
+        .execute("delete from accounts where slug = $1", &[&slug])
+        .await
+        .unwrap();
+    assert!(first.iter().all(|(_, c)| c.inserted == 1));
+    assert!(second
+        .iter()
+        .all(|(_, c)| c.unchanged == 1 && c.inserted == 0));
+}
@@ -0,0 +1,152 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use avtor_core::db::with_transaction;
+use avtor_core::identifier::StandardNormalizer;
+use avtor_core::migrations::Runner;
+use avtor_core::models::users::{
+    create_super_user_with_repos, AccountDto, AccountId, CreateSuperUserError, User, UserDto,
+    SUPER_USER_ROLE,
+};
+use avtor_core::password::breach::NoBreachCheck;
+use avtor_core::repo::{PgAccountRepo, PgUserRepo, UserRepo};
+use tokio_postgres::{Client, NoTls};
+use uuid::Uuid;
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+async fn connect() -> Client {
+    let conn_str = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env_or("db_user", "avtor"),
+        env_or("db_pass", "avtor"),
+        env_or("db_host", "localhost"),
+        env_or("db_port", "5556"),
+        env_or("db_name", "avtor"),
+    );
+    let (client, conn) = tokio_postgres::connect(&conn_str, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+/// Pauses after the existence check so both attempts get past it before
+/// either inserts.
+struct SlowCheckUserRepo<'a>(PgUserRepo<'a>);
+
+#[async_trait]
+impl<'a> UserRepo for SlowCheckUserRepo<'a> {
+    async fn find_super_user(&self) -> Result<Option<User>, CreateSuperUserError> {
+        let found = self.0.find_super_user().await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        found
+    }
+
+    async fn find_user_by_username(
+        &self,
+        username: String,
+    ) -> Result<Option<User>, CreateSuperUserError> {
+        self.0.find_user_by_username(username).await
+    }
+
+    async fn find_user_by_email(
+        &self,
+        account_id: AccountId,
+        email: String,
+    ) -> Result<Option<User>, CreateSuperUserError> {
+        self.0.find_user_by_email(account_id, email).await
+    }
+
+    async fn find_users_by_account(
+        &self,
+        account_id: AccountId,
+    ) -> Result<Vec<User>, CreateSuperUserError> {
+        self.0.find_users_by_account(account_id).await
+    }
+
+    async fn insert_user(&self, user: User) -> Result<(), CreateSuperUserError> {
+        self.0.insert_user(user).await
+    }
+}
+
+async fn attempt(user_id: Uuid, account_id: Uuid) -> Result<(), CreateSuperUserError> {
+    let mut client = connect().await;
+    let user_dto = UserDto {
+        id: user_id,
+        username: format!("root_{}", user_id.to_simple()),
+        password: "!Q2w3e4r5t".into(),
+        roles: SUPER_USER_ROLE.to_string(),
+        account_id,
+        email: None,
+    };
+    let account_dto = AccountDto {
+        id: account_id,
+        name: format!("race {}", account_id.to_simple()),
+    };
+    with_transaction(&mut client, move |trans| {
+        Box::pin(async move {
+            create_super_user_with_repos(
+                &SlowCheckUserRepo(PgUserRepo::new(trans)),
+                &PgAccountRepo::new(trans),
+                &StandardNormalizer::default(),
+                &NoBreachCheck,
+                &user_dto,
+                &account_dto,
+            )
+            .await
+        })
+    })
+    .await
+    .map_err(|e| e.into_inner_or(|e| CreateSuperUserError::RepoError(e.to_string())))
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn concurrent_super_user_creation_allows_one() {
+    let mut client = connect().await;
+    Runner::builtin().run(&mut client).await.unwrap();
+
+    let ids: Vec<(Uuid, Uuid)> = (0..2).map(|_| (Uuid::new_v4(), Uuid::new_v4())).collect();
+    let (a, b) = tokio::join!(attempt(ids[0].0, ids[0].1), attempt(ids[1].0, ids[1].1));
+    let successes = [&a, &b].iter().filter(|r| r.is_ok()).count();
+
+    let pattern = format!("%{}%", SUPER_USER_ROLE);
+    let super_users: i64 = client
+        .query_one(
+            "select count(*) from users where roles like $1",
+            &[&pattern],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    for (user_id, account_id) in &ids {
+        client
+            .execute("delete from users where id = $1", &[user_id])
+            .await
+            .unwrap();
+        client
+            .execute("delete from accounts where id = $1", &[account_id])
+            .await
+            .unwrap();
+    }
+
+    assert!(successes <= 1, "both creations succeeded: {:?} {:?}", a, b);
+    assert_eq!(1, super_users);
+    for r in [a, b] {
+        if let Err(e) = r {
+            assert!(
+                matches!(e, CreateSuperUserError::SuperUserExists),
+                "{:?}",
+                e
+            );
+        }
+    }
+}
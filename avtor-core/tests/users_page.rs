@@ -0,0 +1,96 @@
+//! Runs against the database from docker-compose.yaml; start it and use
+//! `cargo test -- --ignored` (connection settings come from config/local.env).
+
+use avtor_core::migrations::Runner;
+use avtor_core::models::users::{find_users_page, UserFilter};
+use tokio_postgres::{Client, NoTls};
+use uuid::Uuid;
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+async fn connect() -> Client {
+    let conn_str = format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env_or("db_user", "avtor"),
+        env_or("db_pass", "avtor"),
+        env_or("db_host", "localhost"),
+        env_or("db_port", "5556"),
+        env_or("db_name", "avtor"),
+    );
+    let (client, conn) = tokio_postgres::connect(&conn_str, NoTls).await.unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            eprintln!("conn error: {}", e);
+        }
+    });
+    client
+}
+
+#[tokio::test]
+#[ignore = "requires postgres from docker-compose.yaml"]
+async fn users_page_filters_and_follows_cursor() {
+    let mut client = connect().await;
+    Runner::builtin().run(&mut client).await.unwrap();
+
+    let account_id = Uuid::new_v4();
+    let suffix = account_id.to_simple().to_string();
+    client
+        .execute(
+            "insert into accounts (id, name, slug) values ($1, $2, $2)",
+            &[&account_id, &format!("page {}", suffix)],
+        )
+        .await
+        .unwrap();
+    for (name, roles) in [("a", "user"), ("b", "billing"), ("c", "billing, user")] {
+        client
+            .execute(
+                "insert into users (id, username, password, roles, account_id) values ($1, $2, '!', $3, $4)",
+                &[&Uuid::new_v4(), &format!("{}_{}", name, suffix), &roles, &account_id],
+            )
+            .await
+            .unwrap();
+    }
+
+    let filter = UserFilter {
+        account_id: Some(account_id),
+        role: None,
+    };
+    let first = find_users_page(&client)(filter.clone(), None, 2)
+        .await
+        .unwrap();
+    let second = find_users_page(&client)(filter, first.next_cursor.clone(), 2)
+        .await
+        .unwrap();
+    let by_role = find_users_page(&client)(
+        UserFilter {
+            account_id: Some(account_id),
+            role: Some("user".to_string()),
+        },
+        None,
+        10,
+    )
+    .await
+    .unwrap();
+
+    client
+        .execute("delete from users where account_id = $1", &[&account_id])
+        .await
+        .unwrap();
+    client
+        .execute("delete from accounts where id = $1", &[&account_id])
+        .await
+        .unwrap();
+
+    let names = |users: &[avtor_core::models::users::User]| -> Vec<String> {
+        users
+            .iter()
+            .map(|u| u.username()[..1].to_string())
+            .collect()
+    };
+    assert_eq!(vec!["a", "b"], names(&first.items));
+    assert_eq!(vec!["c"], names(&second.items));
+    assert!(second.next_cursor.is_none());
+    assert_eq!(vec!["a", "c"], names(&by_role.items));
+}
@@ -0,0 +1,576 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    ext::IdentExt, parse_quote, Attribute, Data, DeriveInput, Fields, Generics, Ident, LitStr,
+    Type, Visibility,
+};
+
+struct EntityField {
+    ident: Ident,
+    vis: Visibility,
+    ty: Type,
+    column: String,
+    read_only: bool,
+}
+
+impl EntityField {
+    /// `account_id` is `AccountId`, the prefix of its criteria and sort
+    /// variants.
+    fn camel(&self) -> String {
+        camel(&self.ident.unraw().to_string())
+    }
+}
+
+fn camel(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Same rule as `postgres_common::core::is_valid_identifier`, checked at
+/// compile time so a bad name fails the build instead of the first query.
+fn is_valid_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name.split('.').all(|part| {
+            let mut chars = part.chars();
+            matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+}
+
+fn identifier(lit: LitStr) -> syn::Result<String> {
+    let name = lit.value();
+    if is_valid_identifier(&name) {
+        Ok(name)
+    } else {
+        Err(syn::Error::new(
+            lit.span(),
+            format!("`{}` is not a valid SQL identifier", name),
+        ))
+    }
+}
+
+#[derive(Default)]
+struct EntityAttrs {
+    table: Option<String>,
+    /// Of the generated `Fields`, `Criteria`, `CriteriaStruct` and `Sort`
+    /// types; the struct's own when not given.
+    vis: Option<Visibility>,
+}
+
+fn entity_attrs(attrs: &[Attribute]) -> syn::Result<EntityAttrs> {
+    let mut entity = EntityAttrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("entity")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("table") {
+                entity.table = Some(identifier(meta.value()?.parse()?)?);
+                Ok(())
+            } else if meta.path.is_ident("vis") {
+                let lit: LitStr = meta.value()?.parse()?;
+                entity.vis = Some(lit.parse().map_err(|_| {
+                    syn::Error::new(lit.span(), format!("`{}` is not a visibility", lit.value()))
+                })?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `table = \"...\"` or `vis = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(entity)
+}
+
+fn entity_field(field: &syn::Field) -> syn::Result<EntityField> {
+    let ident = field.ident.clone().expect("named field");
+    let mut column = ident.unraw().to_string();
+    let mut read_only = false;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("entity")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("column") {
+                column = identifier(meta.value()?.parse()?)?;
+                Ok(())
+            } else if meta.path.is_ident("read_only") {
+                read_only = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `column = \"...\"` or `read_only`"))
+            }
+        })?;
+    }
+    Ok(EntityField {
+        ident,
+        vis: field.vis.clone(),
+        ty: field.ty.clone(),
+        column,
+        read_only,
+    })
+}
+
+fn entity_fields(input: &DeriveInput) -> syn::Result<Vec<EntityField>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "Entity needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "Entity can only be derived for structs",
+            ))
+        }
+    };
+    let fields = fields
+        .iter()
+        .map(entity_field)
+        .collect::<syn::Result<Vec<_>>>()?;
+    match fields.first() {
+        None => Err(syn::Error::new_spanned(
+            &input.ident,
+            "Entity needs at least one field, the id",
+        )),
+        Some(id) if id.read_only => Err(syn::Error::new_spanned(
+            &id.ident,
+            "the id can't be read_only, it is written by every insert",
+        )),
+        Some(_) => Ok(fields),
+    }
+}
+
+/// Generic entities need their field types to be usable as params and
+/// decodable from rows. Concrete ones don't get the bounds since they'd only
+/// add noise.
+fn impl_generics(generics: &Generics, fields: &[EntityField], tp: &TokenStream) -> Generics {
+    let mut generics = generics.clone();
+    if generics.type_params().next().is_none() {
+        return generics;
+    }
+    let where_clause = generics.make_where_clause();
+    for ty in fields.iter().map(|f| &f.ty) {
+        where_clause.predicates.push(parse_quote! {
+            #ty: #tp::types::ToSql + Sync + 'static
+        });
+        where_clause.predicates.push(parse_quote! {
+            Vec<#ty>: #tp::types::ToSql + Sync
+        });
+        where_clause.predicates.push(parse_quote! {
+            #ty: for<'r> #tp::types::FromSql<'r>
+        });
+    }
+    generics
+}
+
+pub fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let EntityAttrs { table, vis } = entity_attrs(&input.attrs)?;
+    let fields = entity_fields(input)?;
+
+    let core = quote!(::avtor_core::postgres_common::core);
+    let tp = quote!(#core::tokio_postgres);
+    let name = &input.ident;
+    let vis = vis.as_ref().unwrap_or(&input.vis);
+    let fields_enum = format_ident!("{}Fields", name);
+    let criteria = format_ident!("{}Criteria", name);
+    let criteria_struct = format_ident!("{}CriteriaStruct", name);
+    let sort = format_ident!("{}Sort", name);
+
+    let generics = &input.generics;
+    let bounded = impl_generics(generics, &fields, &tp);
+    let (impl_generics, ty_generics, where_clause) = bounded.split_for_impl();
+    let def_where_clause = &generics.where_clause;
+
+    let idents: Vec<&Ident> = fields.iter().map(|f| &f.ident).collect();
+    let field_vis: Vec<&Visibility> = fields.iter().map(|f| &f.vis).collect();
+    let types: Vec<&Type> = fields.iter().map(|f| &f.ty).collect();
+    let columns: Vec<&String> = fields.iter().map(|f| &f.column).collect();
+    let id_column = columns[0];
+    let write: Vec<&EntityField> = fields[1..].iter().filter(|f| !f.read_only).collect();
+    let write_idents: Vec<&Ident> = write.iter().map(|f| &f.ident).collect();
+    let write_columns: Vec<&String> = write.iter().map(|f| &f.column).collect();
+
+    let variants = |suffix: &str| -> Vec<Ident> {
+        fields
+            .iter()
+            .map(|f| Ident::new(&format!("{}{}", f.camel(), suffix), Span::call_site()))
+            .collect()
+    };
+    let struct_fields = |suffix: &str| -> Vec<Ident> {
+        fields
+            .iter()
+            .map(|f| format_ident!("{}_{}", f.ident.unraw(), suffix))
+            .collect()
+    };
+    let (eq, neq, gt, gte, lt, lte) = (
+        variants("Eq"),
+        variants("Neq"),
+        variants("Gt"),
+        variants("Gte"),
+        variants("Lt"),
+        variants("Lte"),
+    );
+    let (is_in, nin, like, nlike, is_null, is_not_null) = (
+        variants("In"),
+        variants("Nin"),
+        variants("Like"),
+        variants("NLike"),
+        variants("IsNull"),
+        variants("IsNotNull"),
+    );
+    let (between, ilike, not_ilike) =
+        (variants("Between"), variants("ILike"), variants("NotILike"));
+    let (asc, desc) = (variants("Asc"), variants("Desc"));
+    let field_variants = variants("");
+    let (eq_f, neq_f, gt_f, gte_f, lt_f, lte_f) = (
+        struct_fields("eq"),
+        struct_fields("neq"),
+        struct_fields("gt"),
+        struct_fields("gte"),
+        struct_fields("lt"),
+        struct_fields("lte"),
+    );
+    let (in_f, nin_f, like_f, nlike_f, is_null_f) = (
+        struct_fields("in"),
+        struct_fields("nin"),
+        struct_fields("like"),
+        struct_fields("nlike"),
+        struct_fields("is_null"),
+    );
+    let (between_f, ilike_f, not_ilike_f) = (
+        struct_fields("between"),
+        struct_fields("ilike"),
+        struct_fields("not_ilike"),
+    );
+
+    let id_ident = idents[0];
+    let id_type = types[0];
+    let db_error = quote!(::avtor_core::error::DbError);
+    let table_methods = table.map(|table| {
+        quote! {
+            pub fn table_name() -> &'static str {
+                #table
+            }
+
+            pub async fn find_by_id<C: #tp::GenericClient>(
+                client: &C,
+                id: &#id_type,
+            ) -> Result<Option<Self>, #db_error> {
+                let conds = vec![#core::QueryCondition::Eq(#id_column.to_string(), id)];
+                let mut found = #core::select_all(
+                    client,
+                    &#table.to_string(),
+                    Self::field_names(),
+                    &conds,
+                    &[],
+                    Some(1),
+                    None,
+                    Self::from_row,
+                )
+                .await?;
+                Ok(found.pop())
+            }
+
+            /// Every row matching all of `criteria`, in no particular order.
+            pub async fn find_where<C: #tp::GenericClient>(
+                client: &C,
+                criteria: &[#criteria #ty_generics],
+            ) -> Result<Vec<Self>, #db_error> {
+                let conds = criteria.iter().map(|c| c.to_query_condition()).collect();
+                #core::select_all(
+                    client,
+                    &#table.to_string(),
+                    Self::field_names(),
+                    &conds,
+                    &[],
+                    None,
+                    None,
+                    Self::from_row,
+                )
+                .await
+            }
+
+            pub async fn insert<C: #tp::GenericClient>(&self, client: &C) -> Result<(), #db_error> {
+                #core::insert(
+                    client,
+                    &#table.to_string(),
+                    &#id_column.to_string(),
+                    Self::write_field_names().as_slice(),
+                    &self.#id_ident,
+                    &self.to_params_x(),
+                )
+                .await
+            }
+
+            /// Writes every field but the id and the read only ones to the
+            /// row with this id, `InvalidQuery` if there are none.
+            pub async fn update<C: #tp::GenericClient>(&self, client: &C) -> Result<(), #db_error> {
+                #core::update(
+                    client,
+                    &#table.to_string(),
+                    &#id_column.to_string(),
+                    Self::write_field_names().as_slice(),
+                    &self.#id_ident,
+                    &self.to_params_x(),
+                )
+                .await
+            }
+        }
+    });
+
+    Ok(quote! {
+        /// One variant per column, for naming columns in queries without
+        /// spelling them out as strings.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #vis enum #fields_enum {
+            #(#field_variants,)*
+        }
+
+        #[allow(dead_code)]
+        impl #fields_enum {
+            pub const ALL: &'static [Self] = &[#(Self::#field_variants),*];
+
+            pub fn column(&self) -> &'static str {
+                match self {
+                    #(Self::#field_variants => #columns,)*
+                }
+            }
+        }
+
+        impl ::std::fmt::Display for #fields_enum {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                f.write_str(self.column())
+            }
+        }
+
+        #[derive(Debug)]
+        #vis enum #criteria #generics #def_where_clause {
+            #(#eq(#types),)*
+            #(#neq(#types),)*
+            #(#gt(#types),)*
+            #(#gte(#types),)*
+            #(#lt(#types),)*
+            #(#lte(#types),)*
+            #(#is_in(Vec<#types>),)*
+            #(#nin(Vec<#types>),)*
+            #(#like(#types),)*
+            #(#nlike(#types),)*
+            #(#is_null,)*
+            #(#is_not_null,)*
+            #(#between(#types, #types),)*
+            #(#ilike(#types),)*
+            #(#not_ilike(#types),)*
+        }
+
+        #[derive(Default, Debug)]
+        #vis struct #criteria_struct #generics #def_where_clause {
+            #(#field_vis #eq_f: Option<#types>,)*
+            #(#field_vis #neq_f: Option<#types>,)*
+            #(#field_vis #gt_f: Option<#types>,)*
+            #(#field_vis #gte_f: Option<#types>,)*
+            #(#field_vis #lt_f: Option<#types>,)*
+            #(#field_vis #lte_f: Option<#types>,)*
+            #(#field_vis #in_f: Vec<#types>,)*
+            #(#field_vis #nin_f: Vec<#types>,)*
+            #(#field_vis #like_f: Option<#types>,)*
+            #(#field_vis #nlike_f: Option<#types>,)*
+            #(#field_vis #is_null_f: Option<bool>,)*
+            #(#field_vis #between_f: Option<(#types, #types)>,)*
+            #(#field_vis #ilike_f: Option<#types>,)*
+            #(#field_vis #not_ilike_f: Option<#types>,)*
+        }
+
+        #[allow(dead_code)]
+        impl #impl_generics #criteria #ty_generics #where_clause {
+            /// `Eq(None)` and `Neq(None)` on an `Option` field become
+            /// `is null` and `is not null`, since `= null` matches nothing.
+            fn to_query_condition<'a>(&'a self) -> #core::QueryCondition<'a> {
+                #[allow(unused_imports)]
+                use #core::{NullCheck, OptionNullCheck, ValueNullCheck};
+                use #core::QueryCondition;
+                match self {
+                    #(Self::#eq(x) if (&NullCheck(x)).is_none_value() => QueryCondition::IsNull(#columns.to_string()),)*
+                    #(Self::#neq(x) if (&NullCheck(x)).is_none_value() => QueryCondition::IsNotNull(#columns.to_string()),)*
+                    #(Self::#eq(x) => QueryCondition::Eq(#columns.to_string(), x),)*
+                    #(Self::#neq(x) => QueryCondition::Neq(#columns.to_string(), x),)*
+                    #(Self::#gt(x) => QueryCondition::Gt(#columns.to_string(), x),)*
+                    #(Self::#gte(x) => QueryCondition::Gte(#columns.to_string(), x),)*
+                    #(Self::#lt(x) => QueryCondition::Lt(#columns.to_string(), x),)*
+                    #(Self::#lte(x) => QueryCondition::Lte(#columns.to_string(), x),)*
+                    #(Self::#is_in(x) => QueryCondition::In(#columns.to_string(), x),)*
+                    #(Self::#nin(x) => QueryCondition::Nin(#columns.to_string(), x),)*
+                    #(Self::#like(x) => QueryCondition::Like(#columns.to_string(), x),)*
+                    #(Self::#nlike(x) => QueryCondition::NLike(#columns.to_string(), x),)*
+                    #(Self::#is_null => QueryCondition::IsNull(#columns.to_string()),)*
+                    #(Self::#is_not_null => QueryCondition::IsNotNull(#columns.to_string()),)*
+                    #(Self::#between(from, to) => QueryCondition::Between(#columns.to_string(), from, to),)*
+                    #(Self::#ilike(x) => QueryCondition::ILike(#columns.to_string(), x),)*
+                    #(Self::#not_ilike(x) => QueryCondition::NotILike(#columns.to_string(), x),)*
+                }
+            }
+        }
+
+        #[allow(dead_code)]
+        impl #impl_generics #criteria_struct #ty_generics #where_clause {
+            fn to_criteria(self) -> Vec<#criteria #ty_generics> {
+                let mut c = vec![];
+                #(if let Some(x) = self.#eq_f {
+                    c.push(#criteria::#eq(x));
+                })*
+                #(if let Some(x) = self.#neq_f {
+                    c.push(#criteria::#neq(x));
+                })*
+                #(if let Some(x) = self.#gt_f {
+                    c.push(#criteria::#gt(x));
+                })*
+                #(if let Some(x) = self.#gte_f {
+                    c.push(#criteria::#gte(x));
+                })*
+                #(if let Some(x) = self.#lt_f {
+                    c.push(#criteria::#lt(x));
+                })*
+                #(if let Some(x) = self.#lte_f {
+                    c.push(#criteria::#lte(x));
+                })*
+                #(if !self.#in_f.is_empty() {
+                    c.push(#criteria::#is_in(self.#in_f));
+                })*
+                #(if !self.#nin_f.is_empty() {
+                    c.push(#criteria::#nin(self.#nin_f));
+                })*
+                #(if let Some(x) = self.#like_f {
+                    c.push(#criteria::#like(x));
+                })*
+                #(if let Some(x) = self.#nlike_f {
+                    c.push(#criteria::#nlike(x));
+                })*
+                #(match self.#is_null_f {
+                    Some(true) => c.push(#criteria::#is_null),
+                    Some(false) => c.push(#criteria::#is_not_null),
+                    None => {}
+                })*
+                #(if let Some((from, to)) = self.#between_f {
+                    c.push(#criteria::#between(from, to));
+                })*
+                #(if let Some(x) = self.#ilike_f {
+                    c.push(#criteria::#ilike(x));
+                })*
+                #(if let Some(x) = self.#not_ilike_f {
+                    c.push(#criteria::#not_ilike(x));
+                })*
+                c
+            }
+        }
+
+        #[derive(Debug, Clone, Copy)]
+        #vis enum #sort {
+            #(#asc,)*
+            #(#desc,)*
+        }
+
+        #[allow(dead_code)]
+        impl #sort {
+            pub fn field(&self) -> #fields_enum {
+                match self {
+                    #(Self::#asc => #fields_enum::#field_variants,)*
+                    #(Self::#desc => #fields_enum::#field_variants,)*
+                }
+            }
+
+            pub fn to_sort(&self) -> #core::Sort {
+                use #core::{Sort, SortDirection};
+                match self {
+                    #(Self::#asc => Sort { field: #columns.to_string(), direction: SortDirection::Asc },)*
+                    #(Self::#desc => Sort { field: #columns.to_string(), direction: SortDirection::Desc },)*
+                }
+            }
+        }
+
+        #[allow(dead_code)]
+        impl #impl_generics #name #ty_generics #where_clause {
+            #table_methods
+
+            /// Every column, for selects and `from_row`.
+            fn field_names() -> &'static [&'static str] {
+                &[#(#columns),*]
+            }
+
+            /// The columns `to_params_x` has values for: everything but the
+            /// id and the read only columns.
+            fn write_field_names() -> Vec<String> {
+                vec![#(#write_columns.to_string()),*]
+            }
+
+            fn from_row(row: #tp::Row) -> Result<Self, #core::RowDecodeError> {
+                Ok(Self {
+                    #(#idents: row.try_get(#columns).map_err(|source| {
+                        #core::RowDecodeError { column: #columns.to_string(), source }
+                    })?,)*
+                })
+            }
+
+            pub fn upsert_sql(table: &String) -> String {
+                let fields = Self::write_field_names();
+                #core::create_upsert_sql(table, &#id_column.to_string(), fields.as_slice())
+            }
+
+            fn to_params_x(&self) -> Vec<&#core::Value> {
+                vec![#(&self.#write_idents as &#core::Value),*]
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::{camel, expand};
+
+    #[test]
+    pub fn test_camel() {
+        assert_eq!("AccountId", camel("account_id"));
+        assert_eq!("Id", camel("id"));
+    }
+
+    #[test]
+    pub fn test_errors_name_the_problem() {
+        let error = |input| expand(&input).unwrap_err().to_string();
+        assert_eq!(
+            "Entity needs at least one field, the id",
+            error(parse_quote! { struct Empty {} })
+        );
+        assert_eq!(
+            "Entity needs a struct with named fields",
+            error(parse_quote! { struct Pair(u32, u32); })
+        );
+        assert_eq!(
+            "`users; drop table x` is not a valid SQL identifier",
+            error(parse_quote! {
+                #[entity(table = "users; drop table x")]
+                struct User { id: u32 }
+            })
+        );
+        assert_eq!(
+            "expected `column = \"...\"` or `read_only`",
+            error(parse_quote! { struct User { #[entity(colum = "x")] id: u32 } })
+        );
+        assert_eq!(
+            "the id can't be read_only, it is written by every insert",
+            error(parse_quote! { struct User { #[entity(read_only)] id: u32 } })
+        );
+        assert_eq!(
+            "`public` is not a visibility",
+            error(parse_quote! {
+                #[entity(vis = "public")]
+                struct User { id: u32 }
+            })
+        );
+    }
+}
@@ -0,0 +1,33 @@
+mod entity;
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+/// Generates the postgres plumbing of an entity: `field_names`,
+/// `write_field_names`, `from_row`, `to_params_x` and `upsert_sql`, plus the
+/// `<Name>Fields`, `<Name>Criteria`, `<Name>CriteriaStruct` and `<Name>Sort`
+/// types.
+///
+/// The first field is the id. The generated types have the struct's
+/// visibility and the `<Name>CriteriaStruct` fields that of the field they
+/// filter on.
+///
+/// - `#[entity(table = "users")]` on the struct generates `table_name()` and
+///   the `find_by_id`, `find_where`, `insert` and `update` methods, which
+///   take a `Client` or a `Transaction`.
+/// - `#[entity(vis = "pub(crate)")]` on the struct gives the generated types
+///   another visibility.
+/// - `#[entity(column = "account_id")]` maps a field to a column of another
+///   name.
+/// - `#[entity(read_only)]` marks a column that is read with the entity but
+///   never written by it, like the timestamps the database fills in.
+///
+/// The generated code refers to `::avtor_core`, so it works in any crate
+/// that depends on avtor-core.
+#[proc_macro_derive(Entity, attributes(entity))]
+pub fn derive_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    entity::expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
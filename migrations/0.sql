@@ -6,4 +6,12 @@ CREATE table if not exists migrations (
   up text not null,
   down text not null,
   applied_on timestamp default current_timestamp 
-);
\ No newline at end of file
+);
+
+create table if not exists system_info (
+  id uuid not null primary key,
+  event varchar(64) not null,
+  subject varchar(255) not null,
+  avtor_version varchar(64) not null,
+  recorded_on timestamp not null default current_timestamp
+);